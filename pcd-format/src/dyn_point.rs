@@ -0,0 +1,415 @@
+//! The [PcdPoint] adapter over records of arbitrary schema.
+
+use crate::{common::*, point::PcdPoint};
+use pcd_rs::{DynRecord, Field, Schema};
+use std::{error::Error, fmt, sync::Arc};
+
+/// The error raised when binding a schema or accessing the fields of a
+/// [DynPoint].
+#[derive(Debug, Clone, PartialEq)]
+pub enum DynPointError {
+    /// A required field is absent.
+    MissingField(String),
+    /// The field is expected to hold a single number but has multiple
+    /// elements.
+    NotScalar(String),
+    /// The field is expected to be a floating-point number.
+    NotFloat(String),
+    /// The value cannot be stored in the field without loss.
+    NotRepresentable(String, f64),
+}
+
+impl fmt::Display for DynPointError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::MissingField(name) => write!(f, r#""{name}" field is required but is not found"#),
+            Self::NotScalar(name) => write!(f, r#""{name}" field is not a single number"#),
+            Self::NotFloat(name) => write!(f, r#""{name}" field is not a floating-point number"#),
+            Self::NotRepresentable(name, value) => {
+                write!(
+                    f,
+                    r#"{value} cannot be stored in the "{name}" field exactly"#
+                )
+            }
+        }
+    }
+}
+
+impl Error for DynPointError {}
+
+/// The unit of a timestamp field.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum TimeUnit {
+    Seconds,
+    Milliseconds,
+    Nanoseconds,
+}
+
+impl TimeUnit {
+    fn to_seconds(self, value: f64) -> f64 {
+        match self {
+            Self::Seconds => value,
+            Self::Milliseconds => value / 1e3,
+            Self::Nanoseconds => value / 1e9,
+        }
+    }
+}
+
+/// The field names recognized as timestamps, searched in order.
+/// The `t` field is the one of the Ouster schema.
+const TIMESTAMP_FIELDS: [(&str, TimeUnit); 5] = [
+    ("timestamp", TimeUnit::Seconds),
+    ("time", TimeUnit::Seconds),
+    ("timestamp_ms", TimeUnit::Milliseconds),
+    ("timestamp_ns", TimeUnit::Nanoseconds),
+    ("t", TimeUnit::Nanoseconds),
+];
+
+/// A PCD schema bound to the indices of the well-known fields.
+///
+/// The binding is done once per file and is shared by every
+/// [DynPoint] read from it. The x, y and z fields are required. Other
+/// fields are bound only if they are present and hold a single
+/// number.
+#[derive(Debug, Clone)]
+pub struct DynSchema {
+    field_defs: Schema,
+    x: usize,
+    y: usize,
+    z: usize,
+    distance: Option<usize>,
+    azimuthal_angle: Option<usize>,
+    vertical_angle: Option<usize>,
+    polar_angle: Option<usize>,
+    intensity: Option<usize>,
+    laser_id: Option<usize>,
+    timestamp: Option<(usize, TimeUnit)>,
+    normal: Option<[usize; 3]>,
+    curvature: Option<usize>,
+    label: Option<usize>,
+    rgba: Option<usize>,
+    reflectivity: Option<usize>,
+    near_ir: Option<usize>,
+    tag: Option<usize>,
+}
+
+impl DynSchema {
+    /// Binds the fields in a PCD header.
+    pub fn new(field_defs: &Schema) -> Result<Self, DynPointError> {
+        let find_scalar = |name: &str| -> Result<Option<usize>, DynPointError> {
+            let Some((idx, field)) = field_defs
+                .iter()
+                .enumerate()
+                .find(|(_, field)| field.name == name)
+            else {
+                return Ok(None);
+            };
+
+            if field.count != 1 {
+                return Err(DynPointError::NotScalar(name.to_string()));
+            }
+            Ok(Some(idx))
+        };
+        let find_optional = |name: &str| find_scalar(name).ok().flatten();
+        let find_required =
+            |name: &str| find_scalar(name)?.ok_or(DynPointError::MissingField(name.to_string()));
+
+        let timestamp = TIMESTAMP_FIELDS
+            .iter()
+            .find_map(|&(name, unit)| Some((find_optional(name)?, unit)));
+        let normal = (|| {
+            Some([
+                find_optional("normal_x")?,
+                find_optional("normal_y")?,
+                find_optional("normal_z")?,
+            ])
+        })();
+
+        Ok(Self {
+            field_defs: field_defs.clone(),
+            x: find_required("x")?,
+            y: find_required("y")?,
+            z: find_required("z")?,
+            distance: find_optional("distance"),
+            azimuthal_angle: find_optional("azimuthal_angle"),
+            vertical_angle: find_optional("vertical_angle"),
+            polar_angle: find_optional("polar_angle"),
+            intensity: find_optional("intensity"),
+            laser_id: find_optional("laser_id").or_else(|| find_optional("ring")),
+            timestamp,
+            normal,
+            curvature: find_optional("curvature"),
+            label: find_optional("label"),
+            rgba: find_optional("rgba").or_else(|| find_optional("rgb")),
+            reflectivity: find_optional("reflectivity"),
+            near_ir: find_optional("near_ir").or_else(|| find_optional("ambient")),
+            tag: find_optional("tag"),
+        })
+    }
+
+    /// Gives the fields of the bound schema.
+    pub fn field_defs(&self) -> &Schema {
+        &self.field_defs
+    }
+
+    /// Finds the index of a field by name.
+    pub fn field_index(&self, name: &str) -> Option<usize> {
+        self.field_defs
+            .iter()
+            .enumerate()
+            .find(|(_, field)| field.name == name)
+            .map(|(idx, _)| idx)
+    }
+}
+
+/// A point of arbitrary schema that implements [PcdPoint].
+///
+/// Numeric fields of any type are coerced to the type required by the
+/// accessors. The spherical coordinates are read from the distance and
+/// angle fields if the schema has them, or are computed from x, y and
+/// z otherwise. They are considered measured only if the schema has
+/// all of them.
+#[derive(Debug, Clone)]
+pub struct DynPoint {
+    schema: Arc<DynSchema>,
+    record: DynRecord,
+}
+
+impl DynPoint {
+    /// Wraps a record read from a file with the bound schema.
+    pub fn new(schema: Arc<DynSchema>, record: DynRecord) -> Self {
+        Self { schema, record }
+    }
+
+    /// Gives the bound schema.
+    pub fn schema(&self) -> &Arc<DynSchema> {
+        &self.schema
+    }
+
+    /// Gives the underlying record.
+    pub fn record(&self) -> &DynRecord {
+        &self.record
+    }
+
+    /// Unwraps the underlying record.
+    pub fn into_record(self) -> DynRecord {
+        self.record
+    }
+
+    /// Gives the value of a named field coerced to `f64`. It gives
+    /// `None` if the field is absent.
+    pub fn get(&self, name: &str) -> Option<f64> {
+        let idx = self.schema.field_index(name)?;
+        Some(self.value(idx))
+    }
+
+    /// Sets the value of a named field. The value is converted to the
+    /// type of the field. It fails on integer fields if the value is
+    /// not an integer in the range of the type.
+    pub fn set(&mut self, name: &str, value: f64) -> Result<(), DynPointError> {
+        let idx = self
+            .schema
+            .field_index(name)
+            .ok_or_else(|| DynPointError::MissingField(name.to_string()))?;
+        self.set_value(idx, value)
+    }
+
+    /// Sets the x, y and z fields. It fails if the fields are not
+    /// floating-point numbers, since integer coordinates cannot keep
+    /// arbitrary values.
+    pub fn set_xyz(&mut self, [x, y, z]: [f64; 3]) -> Result<(), DynPointError> {
+        let DynSchema {
+            x: x_idx,
+            y: y_idx,
+            z: z_idx,
+            ..
+        } = *self.schema;
+
+        for idx in [x_idx, y_idx, z_idx] {
+            if !matches!(self.record.0[idx], Field::F32(_) | Field::F64(_)) {
+                let name = &self.schema.field_defs.fields[idx].name;
+                return Err(DynPointError::NotFloat(name.clone()));
+            }
+        }
+
+        self.set_value(x_idx, x)?;
+        self.set_value(y_idx, y)?;
+        self.set_value(z_idx, z)?;
+        Ok(())
+    }
+
+    fn value(&self, idx: usize) -> f64 {
+        match &self.record.0[idx] {
+            Field::I8(vec) => vec[0] as f64,
+            Field::I16(vec) => vec[0] as f64,
+            Field::I32(vec) => vec[0] as f64,
+            Field::U8(vec) => vec[0] as f64,
+            Field::U16(vec) => vec[0] as f64,
+            Field::U32(vec) => vec[0] as f64,
+            Field::F32(vec) => vec[0] as f64,
+            Field::F64(vec) => vec[0],
+        }
+    }
+
+    fn set_value(&mut self, idx: usize, value: f64) -> Result<(), DynPointError> {
+        // An integer is stored only if it converts back to the value.
+        macro_rules! store_exact {
+            ($vec:expr, $ty:ty) => {{
+                let converted = value as $ty;
+                let exact = converted as f64 == value;
+                if exact {
+                    $vec[0] = converted;
+                }
+                exact
+            }};
+        }
+
+        let stored = match &mut self.record.0[idx] {
+            Field::I8(vec) => store_exact!(vec, i8),
+            Field::I16(vec) => store_exact!(vec, i16),
+            Field::I32(vec) => store_exact!(vec, i32),
+            Field::U8(vec) => store_exact!(vec, u8),
+            Field::U16(vec) => store_exact!(vec, u16),
+            Field::U32(vec) => store_exact!(vec, u32),
+            Field::F32(vec) => {
+                vec[0] = value as f32;
+                true
+            }
+            Field::F64(vec) => {
+                vec[0] = value;
+                true
+            }
+        };
+
+        if !stored {
+            let name = self.schema.field_defs.fields[idx].name.clone();
+            return Err(DynPointError::NotRepresentable(name, value));
+        }
+        Ok(())
+    }
+
+    fn raw_u32(&self, idx: usize) -> u32 {
+        match &self.record.0[idx] {
+            Field::U32(vec) => vec[0],
+            Field::I32(vec) => vec[0] as u32,
+            Field::F32(vec) => vec[0].to_bits(),
+            _ => self.value(idx) as u32,
+        }
+    }
+}
+
+impl PcdPoint for DynPoint {
+    fn x(&self) -> f64 {
+        self.value(self.schema.x)
+    }
+
+    fn y(&self) -> f64 {
+        self.value(self.schema.y)
+    }
+
+    fn z(&self) -> f64 {
+        self.value(self.schema.z)
+    }
+
+    fn distance(&self) -> f64 {
+        match self.schema.distance {
+            Some(idx) => self.value(idx),
+            None => (self.x().powi(2) + self.y().powi(2) + self.z().powi(2)).sqrt(),
+        }
+    }
+
+    fn azimuthal_angle(&self) -> f64 {
+        match self.schema.azimuthal_angle {
+            Some(idx) => self.value(idx),
+            None => self.y().atan2(self.x()),
+        }
+    }
+
+    fn vertical_angle(&self) -> f64 {
+        match self.schema.vertical_angle {
+            Some(idx) => self.value(idx),
+            None => -self.polar_angle() + f64::consts::FRAC_PI_2,
+        }
+    }
+
+    fn polar_angle(&self) -> f64 {
+        match (self.schema.polar_angle, self.schema.vertical_angle) {
+            (Some(idx), _) => self.value(idx),
+            (None, Some(idx)) => -self.value(idx) + f64::consts::FRAC_PI_2,
+            (None, None) => (self.x().powi(2) + self.y().powi(2)).sqrt().atan2(self.z()),
+        }
+    }
+
+    /// The spherical coordinates are measured only if the schema has
+    /// the distance and both angles.
+    fn has_measured_spherical(&self) -> bool {
+        let DynSchema {
+            distance,
+            azimuthal_angle,
+            vertical_angle,
+            polar_angle,
+            ..
+        } = *self.schema;
+        distance.is_some()
+            && azimuthal_angle.is_some()
+            && (vertical_angle.is_some() || polar_angle.is_some())
+    }
+
+    fn intensity(&self) -> Option<f64> {
+        Some(self.value(self.schema.intensity?))
+    }
+
+    fn laser_id(&self) -> Option<u32> {
+        Some(self.value(self.schema.laser_id?) as u32)
+    }
+
+    fn timestamp_ns(&self) -> Option<u32> {
+        match self.schema.timestamp? {
+            (idx, TimeUnit::Nanoseconds) => Some(self.value(idx) as u64 as u32),
+            _ => Some((self.timestamp()? * 1e9) as u64 as u32),
+        }
+    }
+
+    fn timestamp(&self) -> Option<f64> {
+        let (idx, unit) = self.schema.timestamp?;
+        Some(unit.to_seconds(self.value(idx)))
+    }
+
+    fn normal(&self) -> Option<[f64; 3]> {
+        let [x, y, z] = self.schema.normal?;
+        Some([self.value(x), self.value(y), self.value(z)])
+    }
+
+    fn curvature(&self) -> Option<f64> {
+        Some(self.value(self.schema.curvature?))
+    }
+
+    fn label(&self) -> Option<u32> {
+        Some(self.value(self.schema.label?) as u32)
+    }
+
+    /// The color is read from the packed `rgba` or `rgb` field in
+    /// 0xAARRGGBB order. The alpha is 255 for the `rgb` field.
+    fn rgba(&self) -> Option<[u8; 4]> {
+        let idx = self.schema.rgba?;
+        let [a, r, g, b] = self.raw_u32(idx).to_be_bytes();
+        let a = if self.schema.field_defs.fields[idx].name == "rgb" {
+            255
+        } else {
+            a
+        };
+        Some([r, g, b, a])
+    }
+
+    fn reflectivity(&self) -> Option<f64> {
+        Some(self.value(self.schema.reflectivity?))
+    }
+
+    fn near_ir(&self) -> Option<f64> {
+        Some(self.value(self.schema.near_ir?))
+    }
+
+    fn tag(&self) -> Option<u8> {
+        Some(self.value(self.schema.tag?) as u8)
+    }
+}
@@ -0,0 +1,284 @@
+//! Conversion from points of any schema.
+
+use crate::{common::*, point::*};
+
+/// Builds a point of the schema from a point of any schema.
+///
+/// The `xyz` gives the Cartesian coordinates of the new point, which
+/// can differ from the ones of `point` after a transformation. Other
+/// attributes are taken from `point`, and the ones it lacks default to
+/// zero. Spherical coordinates are taken from `point` if they are
+/// measured by the sensor, so that the measurements are kept, or are
+/// computed from `xyz` otherwise.
+pub trait FromPcdPoint {
+    fn from_pcd_point<S>(point: &S, xyz: [f64; 3]) -> Self
+    where
+        S: PcdPoint;
+}
+
+/// Gives the distance, azimuthal angle and vertical angle of the new
+/// point.
+fn spherical_coordinates<S>(point: &S, [x, y, z]: [f64; 3]) -> (f64, f64, f64)
+where
+    S: PcdPoint,
+{
+    if point.has_measured_spherical() {
+        return (
+            point.distance(),
+            point.azimuthal_angle(),
+            point.vertical_angle(),
+        );
+    }
+
+    let distance = (x.powi(2) + y.powi(2) + z.powi(2)).sqrt();
+    let azimuthal_angle = y.atan2(x);
+    let vertical_angle = z.atan2((x.powi(2) + y.powi(2)).sqrt());
+    (distance, azimuthal_angle, vertical_angle)
+}
+
+impl FromPcdPoint for LibpclPoint {
+    fn from_pcd_point<S>(point: &S, [x, y, z]: [f64; 3]) -> Self
+    where
+        S: PcdPoint,
+    {
+        let [r, g, b, _] = point.rgba().unwrap_or([0; 4]);
+
+        Self {
+            x: x as f32,
+            y: y as f32,
+            z: z as f32,
+            rgb: u32::from_be_bytes([0, r, g, b]),
+        }
+    }
+}
+
+impl FromPcdPoint for LibpclExtPoint {
+    fn from_pcd_point<S>(point: &S, [x, y, z]: [f64; 3]) -> Self
+    where
+        S: PcdPoint,
+    {
+        Self {
+            x: x as f32,
+            y: y as f32,
+            z: z as f32,
+            intensity: point.intensity().unwrap_or(0.0) as f32,
+            timestamp_ms: point.timestamp().map(|ts| ts * 1000.0).unwrap_or(0.0),
+        }
+    }
+}
+
+impl FromPcdPoint for SphericalPoint {
+    fn from_pcd_point<S>(point: &S, xyz: [f64; 3]) -> Self
+    where
+        S: PcdPoint,
+    {
+        let (distance, azimuthal_angle, vertical_angle) = spherical_coordinates(point, xyz);
+
+        Self {
+            distance,
+            azimuthal_angle,
+            polar_angle: f64::consts::FRAC_PI_2 - vertical_angle,
+            intensity: point.intensity().unwrap_or(0.0),
+            laser_id: point.laser_id().unwrap_or(0),
+            timestamp_ns: point.timestamp_ns().unwrap_or(0),
+        }
+    }
+}
+
+impl FromPcdPoint for NewslabV1Point {
+    fn from_pcd_point<S>(point: &S, xyz: [f64; 3]) -> Self
+    where
+        S: PcdPoint,
+    {
+        let [x, y, z] = xyz;
+        let (distance, azimuthal_angle, vertical_angle) = spherical_coordinates(point, xyz);
+
+        Self {
+            x,
+            y,
+            z,
+            distance,
+            azimuthal_angle,
+            vertical_angle,
+            intensity: point.intensity().unwrap_or(0.0),
+            laser_id: point.laser_id().unwrap_or(0),
+            timestamp_ns: point.timestamp_ns().unwrap_or(0),
+        }
+    }
+}
+
+impl FromPcdPoint for NewslabV2Point {
+    fn from_pcd_point<S>(point: &S, xyz: [f64; 3]) -> Self
+    where
+        S: PcdPoint,
+    {
+        let [x, y, z] = xyz;
+        let (distance, azimuthal_angle, vertical_angle) = spherical_coordinates(point, xyz);
+
+        Self {
+            x,
+            y,
+            z,
+            distance,
+            azimuthal_angle,
+            vertical_angle,
+            intensity: point.intensity().unwrap_or(0.0),
+            laser_id: point.laser_id().unwrap_or(0),
+            timestamp: point.timestamp().unwrap_or(0.0),
+        }
+    }
+}
+
+impl FromPcdPoint for LibpclXyzPoint {
+    fn from_pcd_point<S>(_point: &S, [x, y, z]: [f64; 3]) -> Self
+    where
+        S: PcdPoint,
+    {
+        Self {
+            x: x as f32,
+            y: y as f32,
+            z: z as f32,
+        }
+    }
+}
+
+impl FromPcdPoint for LibpclXyziPoint {
+    fn from_pcd_point<S>(point: &S, [x, y, z]: [f64; 3]) -> Self
+    where
+        S: PcdPoint,
+    {
+        Self {
+            x: x as f32,
+            y: y as f32,
+            z: z as f32,
+            intensity: point.intensity().unwrap_or(0.0) as f32,
+        }
+    }
+}
+
+impl FromPcdPoint for LibpclXyzrgbaPoint {
+    fn from_pcd_point<S>(point: &S, [x, y, z]: [f64; 3]) -> Self
+    where
+        S: PcdPoint,
+    {
+        let [r, g, b, a] = point.rgba().unwrap_or([0; 4]);
+
+        Self {
+            x: x as f32,
+            y: y as f32,
+            z: z as f32,
+            rgba: u32::from_be_bytes([a, r, g, b]),
+        }
+    }
+}
+
+impl FromPcdPoint for LibpclXyziNormalPoint {
+    fn from_pcd_point<S>(point: &S, [x, y, z]: [f64; 3]) -> Self
+    where
+        S: PcdPoint,
+    {
+        let [normal_x, normal_y, normal_z] = point.normal().unwrap_or([0.0; 3]);
+
+        Self {
+            x: x as f32,
+            y: y as f32,
+            z: z as f32,
+            intensity: point.intensity().unwrap_or(0.0) as f32,
+            normal_x: normal_x as f32,
+            normal_y: normal_y as f32,
+            normal_z: normal_z as f32,
+            curvature: point.curvature().unwrap_or(0.0) as f32,
+        }
+    }
+}
+
+impl FromPcdPoint for LibpclNormalPoint {
+    fn from_pcd_point<S>(point: &S, [x, y, z]: [f64; 3]) -> Self
+    where
+        S: PcdPoint,
+    {
+        let [normal_x, normal_y, normal_z] = point.normal().unwrap_or([0.0; 3]);
+
+        Self {
+            x: x as f32,
+            y: y as f32,
+            z: z as f32,
+            normal_x: normal_x as f32,
+            normal_y: normal_y as f32,
+            normal_z: normal_z as f32,
+            curvature: point.curvature().unwrap_or(0.0) as f32,
+        }
+    }
+}
+
+impl FromPcdPoint for LibpclXyzlPoint {
+    fn from_pcd_point<S>(point: &S, [x, y, z]: [f64; 3]) -> Self
+    where
+        S: PcdPoint,
+    {
+        Self {
+            x: x as f32,
+            y: y as f32,
+            z: z as f32,
+            label: point.label().unwrap_or(0),
+        }
+    }
+}
+
+/// The `time` is set to zero since the scan start is unknown to a single
+/// point. Callers that know the scan start should fill it in.
+impl FromPcdPoint for XyzirtPoint {
+    fn from_pcd_point<S>(point: &S, [x, y, z]: [f64; 3]) -> Self
+    where
+        S: PcdPoint,
+    {
+        Self {
+            x: x as f32,
+            y: y as f32,
+            z: z as f32,
+            intensity: point.intensity().unwrap_or(0.0) as f32,
+            ring: point.laser_id().unwrap_or(0) as u16,
+            time: 0.0,
+        }
+    }
+}
+
+/// The `t` is taken from the timestamp of the source point, which is
+/// not necessarily relative to the scan start.
+impl FromPcdPoint for OusterPoint {
+    fn from_pcd_point<S>(point: &S, xyz: [f64; 3]) -> Self
+    where
+        S: PcdPoint,
+    {
+        let [x, y, z] = xyz;
+        let (distance, _, _) = spherical_coordinates(point, xyz);
+
+        Self {
+            x: x as f32,
+            y: y as f32,
+            z: z as f32,
+            intensity: point.intensity().unwrap_or(0.0) as f32,
+            t: point.timestamp_ns().unwrap_or(0),
+            reflectivity: point.reflectivity().unwrap_or(0.0) as u16,
+            ring: point.laser_id().unwrap_or(0) as u16,
+            ambient: point.near_ir().unwrap_or(0.0) as u16,
+            range: (distance * 1000.0) as u32,
+        }
+    }
+}
+
+impl FromPcdPoint for LivoxPoint {
+    fn from_pcd_point<S>(point: &S, [x, y, z]: [f64; 3]) -> Self
+    where
+        S: PcdPoint,
+    {
+        Self {
+            x: x as f32,
+            y: y as f32,
+            z: z as f32,
+            intensity: point.intensity().unwrap_or(0.0) as f32,
+            tag: point.tag().unwrap_or(0),
+            timestamp: point.timestamp().unwrap_or(0.0),
+        }
+    }
+}
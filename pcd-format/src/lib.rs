@@ -1,5 +1,11 @@
 /// The crate defines the schema of PCD format.
 mod common;
+pub mod dyn_point;
+pub mod from_point;
 pub mod point;
+pub mod schema;
 
+pub use dyn_point::*;
+pub use from_point::*;
 pub use point::*;
+pub use schema::*;
@@ -22,28 +22,65 @@ pub trait PcdPoint {
     ///
     /// The value is zero at the north pole, and is positive towards south pole.
     fn polar_angle(&self) -> f64;
+    /// Tells whether the distance and angles are measured by the
+    /// sensor rather than computed from x, y and z.
+    fn has_measured_spherical(&self) -> bool {
+        false
+    }
     /// Gives the intensity from sensor. It gives `None` if it lacks the data.
     fn intensity(&self) -> Option<f64>;
     /// Gives the laser index on sensor. It gives `None` if it lacks the data.
     fn laser_id(&self) -> Option<u32>;
-    /// Gives the timestamp in milliseconds. It gives `None` if it lacks the data.
+    /// Gives the timestamp in nanoseconds truncated to 32 bits. It
+    /// gives `None` if it lacks the data.
+    ///
+    /// The value wraps around about every 4.29 seconds. Use
+    /// [timestamp](PcdPoint::timestamp) for the full value.
     fn timestamp_ns(&self) -> Option<u32>;
-}
-
-pub use libpcl::*;
-mod libpcl {
-    use super::*;
-
-    /// The standard schema used by libpcl.
-    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize, PcdSerialize, PcdDeserialize)]
-    pub struct LibpclPoint {
-        pub x: f32,
-        pub y: f32,
-        pub z: f32,
-        pub rgb: u32,
+    /// Gives the timestamp in seconds. It gives `None` if it lacks the data.
+    ///
+    /// Schemas that store absolute time give the seconds since the
+    /// Unix epoch. It defaults to the wrapped nanosecond timestamp.
+    fn timestamp(&self) -> Option<f64> {
+        self.timestamp_ns().map(|ns| ns as f64 / 1e9)
+    }
+    /// Gives the surface normal vector. It gives `None` if it lacks the data.
+    fn normal(&self) -> Option<[f64; 3]> {
+        None
+    }
+    /// Gives the surface curvature. It gives `None` if it lacks the data.
+    fn curvature(&self) -> Option<f64> {
+        None
     }
+    /// Gives the object label. It gives `None` if it lacks the data.
+    fn label(&self) -> Option<u32> {
+        None
+    }
+    /// Gives the color in RGBA order. It gives `None` if it lacks the data.
+    fn rgba(&self) -> Option<[u8; 4]> {
+        None
+    }
+    /// Gives the calibrated reflectivity from sensor. It gives `None`
+    /// if it lacks the data.
+    fn reflectivity(&self) -> Option<f64> {
+        None
+    }
+    /// Gives the near-infrared ambient light from sensor. It gives
+    /// `None` if it lacks the data.
+    fn near_ir(&self) -> Option<f64> {
+        None
+    }
+    /// Gives the tag flags from sensor, which tell the confidence
+    /// against noise. It gives `None` if it lacks the data.
+    fn tag(&self) -> Option<u8> {
+        None
+    }
+}
 
-    impl PcdPoint for LibpclPoint {
+/// Implements the Cartesian and spherical coordinate methods of
+/// [PcdPoint] for schemas having `f32` x, y and z fields.
+macro_rules! impl_xyz_f32_methods {
+    () => {
         fn x(&self) -> f64 {
             self.x as f64
         }
@@ -71,6 +108,24 @@ mod libpcl {
         fn polar_angle(&self) -> f64 {
             (self.x().powi(2) + self.y().powi(2)).sqrt().atan2(self.z())
         }
+    };
+}
+
+pub use libpcl::*;
+mod libpcl {
+    use super::*;
+
+    /// The standard schema used by libpcl.
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize, PcdSerialize, PcdDeserialize)]
+    pub struct LibpclPoint {
+        pub x: f32,
+        pub y: f32,
+        pub z: f32,
+        pub rgb: u32,
+    }
+
+    impl PcdPoint for LibpclPoint {
+        impl_xyz_f32_methods!();
 
         fn intensity(&self) -> Option<f64> {
             None
@@ -83,6 +138,11 @@ mod libpcl {
         fn timestamp_ns(&self) -> Option<u32> {
             None
         }
+
+        fn rgba(&self) -> Option<[u8; 4]> {
+            let [_, r, g, b] = self.rgb.to_be_bytes();
+            Some([r, g, b, u8::MAX])
+        }
     }
 }
 
@@ -90,34 +150,72 @@ pub use libpcl_ext::*;
 mod libpcl_ext {
     use super::*;
 
-    #[derive(Debug, Clone, PartialEq, PcdSerialize)]
+    /// The libpcl schema extended with intensity and timestamp.
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize, PcdSerialize, PcdDeserialize)]
     pub struct LibpclExtPoint {
         pub x: f32,
         pub y: f32,
         pub z: f32,
         pub intensity: f32,
+        /// The timestamp in milliseconds.
         pub timestamp_ms: f64,
     }
 
     impl PcdPoint for LibpclExtPoint {
+        impl_xyz_f32_methods!();
+
+        fn intensity(&self) -> Option<f64> {
+            Some(self.intensity as f64)
+        }
+
+        fn laser_id(&self) -> Option<u32> {
+            None
+        }
+
+        fn timestamp_ns(&self) -> Option<u32> {
+            Some((self.timestamp_ms * 1_000_000.0) as u64 as u32)
+        }
+
+        fn timestamp(&self) -> Option<f64> {
+            Some(self.timestamp_ms / 1000.0)
+        }
+    }
+}
+
+pub use spherical::*;
+mod spherical {
+    use super::*;
+
+    /// The schema that uses spherical coordinates.
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize, PcdSerialize, PcdDeserialize)]
+    pub struct SphericalPoint {
+        pub distance: f64,
+        pub azimuthal_angle: f64,
+        pub polar_angle: f64,
+        pub intensity: f64,
+        pub laser_id: u32,
+        pub timestamp_ns: u32,
+    }
+
+    impl PcdPoint for SphericalPoint {
         fn x(&self) -> f64 {
-            self.x as f64
+            self.distance * self.polar_angle.sin() * self.azimuthal_angle.cos()
         }
 
         fn y(&self) -> f64 {
-            self.y as f64
+            self.distance * self.polar_angle.sin() * self.azimuthal_angle.sin()
         }
 
         fn z(&self) -> f64 {
-            self.z as f64
+            self.distance * self.polar_angle.cos()
         }
 
         fn distance(&self) -> f64 {
-            (self.x().powi(2) + self.y().powi(2) + self.z().powi(2)).sqrt()
+            self.distance
         }
 
         fn azimuthal_angle(&self) -> f64 {
-            self.y().atan2(self.x())
+            self.azimuthal_angle
         }
 
         fn vertical_angle(&self) -> f64 {
@@ -125,49 +223,56 @@ mod libpcl_ext {
         }
 
         fn polar_angle(&self) -> f64 {
-            (self.x().powi(2) + self.y().powi(2)).sqrt().atan2(self.z())
+            self.polar_angle
+        }
+
+        fn has_measured_spherical(&self) -> bool {
+            true
         }
 
         fn intensity(&self) -> Option<f64> {
-            Some(self.intensity as f64)
+            self.intensity.into()
         }
 
         fn laser_id(&self) -> Option<u32> {
-            None
+            self.laser_id.into()
         }
 
         fn timestamp_ns(&self) -> Option<u32> {
-            Some(self.timestamp_ms as u32 * 1000)
+            self.timestamp_ns.into()
         }
     }
 }
 
-pub use spherical::*;
-mod spherical {
+pub use newslab_v1::*;
+mod newslab_v1 {
     use super::*;
 
-    /// The schema that uses spherical coordinates.
+    /// The custom schema used by NEWSLAB.
     #[derive(Debug, Clone, PartialEq, Serialize, Deserialize, PcdSerialize, PcdDeserialize)]
-    pub struct SphericalPoint {
+    pub struct NewslabV1Point {
+        pub x: f64,
+        pub y: f64,
+        pub z: f64,
         pub distance: f64,
         pub azimuthal_angle: f64,
-        pub polar_angle: f64,
+        pub vertical_angle: f64,
         pub intensity: f64,
         pub laser_id: u32,
         pub timestamp_ns: u32,
     }
 
-    impl PcdPoint for SphericalPoint {
+    impl PcdPoint for NewslabV1Point {
         fn x(&self) -> f64 {
-            self.distance * self.polar_angle.sin() * self.azimuthal_angle.cos()
+            self.x
         }
 
         fn y(&self) -> f64 {
-            self.distance * self.polar_angle.sin() * self.azimuthal_angle.sin()
+            self.y
         }
 
         fn z(&self) -> f64 {
-            self.distance * self.polar_angle.cos()
+            self.z
         }
 
         fn distance(&self) -> f64 {
@@ -179,11 +284,15 @@ mod spherical {
         }
 
         fn vertical_angle(&self) -> f64 {
-            -self.polar_angle() + f64::consts::FRAC_PI_2
+            self.vertical_angle
         }
 
         fn polar_angle(&self) -> f64 {
-            self.polar_angle
+            -self.vertical_angle + f64::consts::FRAC_PI_2
+        }
+
+        fn has_measured_spherical(&self) -> bool {
+            true
         }
 
         fn intensity(&self) -> Option<f64> {
@@ -200,13 +309,13 @@ mod spherical {
     }
 }
 
-pub use newslab_v1::*;
-mod newslab_v1 {
+pub use newslab_v2::*;
+mod newslab_v2 {
     use super::*;
 
-    /// The custom schema used by NEWSLAB.
+    /// The custom schema used by NEWSLAB with 64-bit timestamps.
     #[derive(Debug, Clone, PartialEq, Serialize, Deserialize, PcdSerialize, PcdDeserialize)]
-    pub struct NewslabV1Point {
+    pub struct NewslabV2Point {
         pub x: f64,
         pub y: f64,
         pub z: f64,
@@ -215,10 +324,13 @@ mod newslab_v1 {
         pub vertical_angle: f64,
         pub intensity: f64,
         pub laser_id: u32,
-        pub timestamp_ns: u32,
+        /// The timestamp in seconds since the Unix epoch. Points
+        /// migrated from V1 keep the wrapped timestamp of V1 instead,
+        /// which is within 4.29 seconds.
+        pub timestamp: f64,
     }
 
-    impl PcdPoint for NewslabV1Point {
+    impl PcdPoint for NewslabV2Point {
         fn x(&self) -> f64 {
             self.x
         }
@@ -247,6 +359,10 @@ mod newslab_v1 {
             -self.vertical_angle + f64::consts::FRAC_PI_2
         }
 
+        fn has_measured_spherical(&self) -> bool {
+            true
+        }
+
         fn intensity(&self) -> Option<f64> {
             self.intensity.into()
         }
@@ -256,7 +372,400 @@ mod newslab_v1 {
         }
 
         fn timestamp_ns(&self) -> Option<u32> {
-            self.timestamp_ns.into()
+            Some((self.timestamp * 1e9) as u64 as u32)
+        }
+
+        fn timestamp(&self) -> Option<f64> {
+            self.timestamp.into()
+        }
+    }
+
+    /// Migrates a V1 point.
+    ///
+    /// The 32-bit nanosecond timestamp of V1 wraps around and lacks
+    /// the time it is relative to, so it cannot be recovered as an
+    /// absolute time. It is kept as is in seconds, and is relative
+    /// and wrapped rather than since the Unix epoch.
+    impl From<NewslabV1Point> for NewslabV2Point {
+        fn from(from: NewslabV1Point) -> Self {
+            let NewslabV1Point {
+                x,
+                y,
+                z,
+                distance,
+                azimuthal_angle,
+                vertical_angle,
+                intensity,
+                laser_id,
+                timestamp_ns,
+            } = from;
+
+            Self {
+                x,
+                y,
+                z,
+                distance,
+                azimuthal_angle,
+                vertical_angle,
+                intensity,
+                laser_id,
+                timestamp: timestamp_ns as f64 / 1e9,
+            }
+        }
+    }
+}
+
+pub use libpcl_xyz::*;
+mod libpcl_xyz {
+    use super::*;
+
+    /// The PointXYZ schema used by libpcl.
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize, PcdSerialize, PcdDeserialize)]
+    pub struct LibpclXyzPoint {
+        pub x: f32,
+        pub y: f32,
+        pub z: f32,
+    }
+
+    impl PcdPoint for LibpclXyzPoint {
+        impl_xyz_f32_methods!();
+
+        fn intensity(&self) -> Option<f64> {
+            None
+        }
+
+        fn laser_id(&self) -> Option<u32> {
+            None
+        }
+
+        fn timestamp_ns(&self) -> Option<u32> {
+            None
+        }
+    }
+}
+
+pub use libpcl_xyzi::*;
+mod libpcl_xyzi {
+    use super::*;
+
+    /// The PointXYZI schema used by libpcl.
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize, PcdSerialize, PcdDeserialize)]
+    pub struct LibpclXyziPoint {
+        pub x: f32,
+        pub y: f32,
+        pub z: f32,
+        pub intensity: f32,
+    }
+
+    impl PcdPoint for LibpclXyziPoint {
+        impl_xyz_f32_methods!();
+
+        fn intensity(&self) -> Option<f64> {
+            Some(self.intensity as f64)
+        }
+
+        fn laser_id(&self) -> Option<u32> {
+            None
+        }
+
+        fn timestamp_ns(&self) -> Option<u32> {
+            None
+        }
+    }
+}
+
+pub use libpcl_xyzrgba::*;
+mod libpcl_xyzrgba {
+    use super::*;
+
+    /// The PointXYZRGBA schema used by libpcl.
+    ///
+    /// The color is packed as `0xAARRGGBB`.
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize, PcdSerialize, PcdDeserialize)]
+    pub struct LibpclXyzrgbaPoint {
+        pub x: f32,
+        pub y: f32,
+        pub z: f32,
+        pub rgba: u32,
+    }
+
+    impl PcdPoint for LibpclXyzrgbaPoint {
+        impl_xyz_f32_methods!();
+
+        fn intensity(&self) -> Option<f64> {
+            None
+        }
+
+        fn laser_id(&self) -> Option<u32> {
+            None
+        }
+
+        fn timestamp_ns(&self) -> Option<u32> {
+            None
+        }
+
+        fn rgba(&self) -> Option<[u8; 4]> {
+            let [a, r, g, b] = self.rgba.to_be_bytes();
+            Some([r, g, b, a])
+        }
+    }
+}
+
+pub use libpcl_xyzi_normal::*;
+mod libpcl_xyzi_normal {
+    use super::*;
+
+    /// The PointXYZINormal schema used by libpcl.
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize, PcdSerialize, PcdDeserialize)]
+    pub struct LibpclXyziNormalPoint {
+        pub x: f32,
+        pub y: f32,
+        pub z: f32,
+        pub intensity: f32,
+        pub normal_x: f32,
+        pub normal_y: f32,
+        pub normal_z: f32,
+        pub curvature: f32,
+    }
+
+    impl PcdPoint for LibpclXyziNormalPoint {
+        impl_xyz_f32_methods!();
+
+        fn intensity(&self) -> Option<f64> {
+            Some(self.intensity as f64)
+        }
+
+        fn laser_id(&self) -> Option<u32> {
+            None
+        }
+
+        fn timestamp_ns(&self) -> Option<u32> {
+            None
+        }
+
+        fn normal(&self) -> Option<[f64; 3]> {
+            Some([
+                self.normal_x as f64,
+                self.normal_y as f64,
+                self.normal_z as f64,
+            ])
+        }
+
+        fn curvature(&self) -> Option<f64> {
+            Some(self.curvature as f64)
+        }
+    }
+}
+
+pub use libpcl_normal::*;
+mod libpcl_normal {
+    use super::*;
+
+    /// The PointNormal schema used by libpcl.
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize, PcdSerialize, PcdDeserialize)]
+    pub struct LibpclNormalPoint {
+        pub x: f32,
+        pub y: f32,
+        pub z: f32,
+        pub normal_x: f32,
+        pub normal_y: f32,
+        pub normal_z: f32,
+        pub curvature: f32,
+    }
+
+    impl PcdPoint for LibpclNormalPoint {
+        impl_xyz_f32_methods!();
+
+        fn intensity(&self) -> Option<f64> {
+            None
+        }
+
+        fn laser_id(&self) -> Option<u32> {
+            None
+        }
+
+        fn timestamp_ns(&self) -> Option<u32> {
+            None
+        }
+
+        fn normal(&self) -> Option<[f64; 3]> {
+            Some([
+                self.normal_x as f64,
+                self.normal_y as f64,
+                self.normal_z as f64,
+            ])
+        }
+
+        fn curvature(&self) -> Option<f64> {
+            Some(self.curvature as f64)
+        }
+    }
+}
+
+pub use libpcl_xyzl::*;
+mod libpcl_xyzl {
+    use super::*;
+
+    /// The PointXYZL schema used by libpcl.
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize, PcdSerialize, PcdDeserialize)]
+    pub struct LibpclXyzlPoint {
+        pub x: f32,
+        pub y: f32,
+        pub z: f32,
+        pub label: u32,
+    }
+
+    impl PcdPoint for LibpclXyzlPoint {
+        impl_xyz_f32_methods!();
+
+        fn intensity(&self) -> Option<f64> {
+            None
+        }
+
+        fn laser_id(&self) -> Option<u32> {
+            None
+        }
+
+        fn timestamp_ns(&self) -> Option<u32> {
+            None
+        }
+
+        fn label(&self) -> Option<u32> {
+            Some(self.label)
+        }
+    }
+}
+
+pub use xyzirt::*;
+mod xyzirt {
+    use super::*;
+
+    /// The PointXYZIRT schema used by ROS velodyne_pointcloud and
+    /// Autoware.
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize, PcdSerialize, PcdDeserialize)]
+    pub struct XyzirtPoint {
+        pub x: f32,
+        pub y: f32,
+        pub z: f32,
+        pub intensity: f32,
+        /// The laser index on the sensor.
+        pub ring: u16,
+        /// The time in seconds relative to the scan start.
+        pub time: f32,
+    }
+
+    impl PcdPoint for XyzirtPoint {
+        impl_xyz_f32_methods!();
+
+        fn intensity(&self) -> Option<f64> {
+            Some(self.intensity as f64)
+        }
+
+        fn laser_id(&self) -> Option<u32> {
+            Some(self.ring as u32)
+        }
+
+        fn timestamp_ns(&self) -> Option<u32> {
+            Some((self.time as f64 * 1e9) as u64 as u32)
+        }
+
+        fn timestamp(&self) -> Option<f64> {
+            Some(self.time as f64)
+        }
+    }
+}
+
+pub use ouster::*;
+mod ouster {
+    use super::*;
+
+    /// The point schema used by the Ouster ROS driver.
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize, PcdSerialize, PcdDeserialize)]
+    pub struct OusterPoint {
+        pub x: f32,
+        pub y: f32,
+        pub z: f32,
+        /// The signal photons of the return.
+        pub intensity: f32,
+        /// The time in nanoseconds relative to the scan start.
+        pub t: u32,
+        pub reflectivity: u16,
+        /// The beam index on the sensor.
+        pub ring: u16,
+        /// The near-infrared photons of the ambient light.
+        pub ambient: u16,
+        /// The range in millimeters.
+        pub range: u32,
+    }
+
+    impl PcdPoint for OusterPoint {
+        impl_xyz_f32_methods!();
+
+        fn intensity(&self) -> Option<f64> {
+            Some(self.intensity as f64)
+        }
+
+        fn laser_id(&self) -> Option<u32> {
+            Some(self.ring as u32)
+        }
+
+        fn timestamp_ns(&self) -> Option<u32> {
+            Some(self.t)
+        }
+
+        fn reflectivity(&self) -> Option<f64> {
+            Some(self.reflectivity as f64)
+        }
+
+        fn near_ir(&self) -> Option<f64> {
+            Some(self.ambient as f64)
+        }
+    }
+}
+
+pub use livox::*;
+mod livox {
+    use super::*;
+
+    /// The point schema used by the Livox ROS driver, without the
+    /// line number.
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize, PcdSerialize, PcdDeserialize)]
+    pub struct LivoxPoint {
+        pub x: f32,
+        pub y: f32,
+        pub z: f32,
+        /// The reflectivity of the return.
+        pub intensity: f32,
+        pub tag: u8,
+        /// The time in seconds as reported by the sensor.
+        pub timestamp: f64,
+    }
+
+    impl PcdPoint for LivoxPoint {
+        impl_xyz_f32_methods!();
+
+        fn intensity(&self) -> Option<f64> {
+            Some(self.intensity as f64)
+        }
+
+        fn laser_id(&self) -> Option<u32> {
+            None
+        }
+
+        fn timestamp_ns(&self) -> Option<u32> {
+            Some((self.timestamp * 1e9) as u64 as u32)
+        }
+
+        fn timestamp(&self) -> Option<f64> {
+            Some(self.timestamp)
+        }
+
+        fn reflectivity(&self) -> Option<f64> {
+            Some(self.intensity as f64)
+        }
+
+        fn tag(&self) -> Option<u8> {
+            Some(self.tag)
         }
     }
 }
@@ -0,0 +1,21 @@
+//! Matching of PCD header fields against the schemas.
+
+use crate::common::*;
+use pcd_rs::{FieldDef, Schema, ValueKind};
+
+/// Checks if the fields in a PCD header are exactly the fields of
+/// schema `T`, comparing names, types, counts and order.
+pub fn schema_matches<T>(field_defs: &Schema) -> bool
+where
+    T: PcdSerialize,
+{
+    let expect = T::write_spec();
+    expect
+        .iter()
+        .map(field_key)
+        .eq(field_defs.iter().map(field_key))
+}
+
+fn field_key(field: &FieldDef) -> (&str, ValueKind, u64) {
+    (field.name.as_str(), field.kind, field.count)
+}
@@ -0,0 +1,454 @@
+//! Per-unit calibration files of Velodyne LiDARs.
+
+use crate::velodyne::VelodynePoint;
+use eyre::{bail, ensure, format_err, Context, Result};
+use itertools::{chain, Itertools};
+use measurements::Angle;
+use serde::Deserialize;
+use std::{f64::consts::TAU, fs, path::Path};
+
+/// The corrections of a laser. Lengths are in meters and angles are in
+/// radians.
+#[derive(Debug, Clone)]
+pub struct LaserCorrection {
+    pub laser_id: usize,
+    /// The azimuth offset of the laser, subtracted from the encoder
+    /// azimuth.
+    pub rot_correction: f64,
+    /// The elevation angle of the laser.
+    pub vert_correction: f64,
+    /// The offset added to the measured distance.
+    pub dist_correction: f64,
+    /// The distance correction at 2.4 m along the x axis.
+    pub dist_correction_x: f64,
+    /// The distance correction at 1.93 m along the y axis.
+    pub dist_correction_y: f64,
+    /// Whether the x and y distance corrections are applied.
+    pub two_pt_correction_available: bool,
+    /// The vertical offset of the laser from the sensor origin.
+    pub vert_offset_correction: f64,
+    /// The horizontal offset of the laser from the sensor origin.
+    pub horiz_offset_correction: f64,
+}
+
+impl LaserCorrection {
+    /// Computes the corrected distance and Cartesian coordinates of a
+    /// return with the formulas of ROS velodyne_pointcloud.
+    ///
+    /// The `azimuth` is the rotation corrected azimuth in the Velodyne
+    /// convention. The two-point distance correction is applied only if
+    /// the calibration marks it available.
+    pub fn correct(&self, distance: f64, azimuth: f64) -> (f64, [f64; 3]) {
+        let Self {
+            dist_correction,
+            dist_correction_x,
+            dist_correction_y,
+            two_pt_correction_available,
+            vert_offset_correction: vert_offset,
+            horiz_offset_correction: horiz_offset,
+            ..
+        } = *self;
+
+        let distance = distance + dist_correction;
+        let (sin_vert, cos_vert) = self.vert_correction.sin_cos();
+        let (sin_rot, cos_rot) = azimuth.sin_cos();
+
+        let (distance_x, distance_y) = if two_pt_correction_available {
+            let xy_distance = distance * cos_vert - vert_offset * sin_vert;
+            let xx = (xy_distance * sin_rot - horiz_offset * cos_rot).abs();
+            let yy = (xy_distance * cos_rot + horiz_offset * sin_rot).abs();
+
+            let correction_x = (dist_correction - dist_correction_x) * (xx - 2.4) / (25.04 - 2.4)
+                + dist_correction_x
+                - dist_correction;
+            let correction_y = (dist_correction - dist_correction_y) * (yy - 1.93) / (25.04 - 1.93)
+                + dist_correction_y
+                - dist_correction;
+            (distance + correction_x, distance + correction_y)
+        } else {
+            (distance, distance)
+        };
+
+        let xy_distance_x = distance_x * cos_vert - vert_offset * sin_vert;
+        let xy_distance_y = distance_y * cos_vert - vert_offset * sin_vert;
+        let x = xy_distance_x * sin_rot - horiz_offset * cos_rot;
+        let y = xy_distance_y * cos_rot + horiz_offset * sin_rot;
+        let z = distance_y * sin_vert + vert_offset * cos_vert;
+
+        (distance, [x, y, z])
+    }
+}
+
+/// The calibration of a Velodyne LiDAR unit, indexed by laser ID.
+#[derive(Debug, Clone)]
+pub struct VelodyneCalibration {
+    pub lasers: Vec<LaserCorrection>,
+}
+
+impl VelodyneCalibration {
+    /// Loads a VeloView db.xml file or a ROS velodyne_pointcloud YAML
+    /// file. The format is told by the file extension, or by the
+    /// content if the extension is not known.
+    pub fn load<P>(path: P) -> Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        let path = path.as_ref();
+        let text = fs::read_to_string(path)?;
+        let extension = path.extension().and_then(|ext| ext.to_str());
+
+        let is_xml = match extension {
+            Some("xml") => true,
+            Some("yaml" | "yml") => false,
+            _ => text.trim_start().starts_with('<'),
+        };
+        let lasers = if is_xml {
+            parse_veloview_xml(&text)
+        } else {
+            parse_ros_yaml(&text)
+        };
+        let mut lasers = lasers
+            .wrap_err_with(|| format!("unable to read the calibration file {}", path.display()))?;
+
+        lasers.sort_by_key(|laser| laser.laser_id);
+        for (index, laser) in lasers.iter().enumerate() {
+            if laser.laser_id != index {
+                bail!(
+                    "the calibration file {} does not have exactly one entry for laser {}",
+                    path.display(),
+                    index
+                );
+            }
+        }
+
+        Ok(Self { lasers })
+    }
+
+    pub fn num_lasers(&self) -> usize {
+        self.lasers.len()
+    }
+
+    /// Checks that the calibration covers the lasers of the sensor.
+    pub fn check_num_lasers(&self, num_lasers: usize) -> Result<()> {
+        ensure!(
+            self.num_lasers() == num_lasers,
+            "the calibration file has {} lasers, but the sensor has {}",
+            self.num_lasers(),
+            num_lasers
+        );
+        Ok(())
+    }
+
+    /// Gives the elevation angles in degrees of the lasers.
+    pub fn elevations(&self) -> Vec<f64> {
+        self.lasers
+            .iter()
+            .map(|laser| laser.vert_correction.to_degrees())
+            .collect()
+    }
+
+    /// Recomputes the azimuth, elevation and returns of a point from
+    /// the measured distances.
+    pub fn correct_point(&self, point: &mut VelodynePoint) {
+        let laser = &self.lasers[point.laser_id as usize];
+        let azimuth = (point.azimuth.as_radians() - laser.rot_correction).rem_euclid(TAU);
+
+        point.azimuth = Angle::from_radians(azimuth);
+        point.elevation = Angle::from_radians(laser.vert_correction);
+
+        for measurement in chain!(&mut point.strongest, &mut point.last) {
+            // A zero distance means no return.
+            if measurement.distance == 0.0 {
+                continue;
+            }
+
+            let (distance, xyz) = laser.correct(measurement.distance, azimuth);
+            measurement.distance = distance;
+            measurement.xyz = xyz;
+        }
+    }
+}
+
+/// Reads the `points_` of a VeloView db.xml file, which gives lengths
+/// in centimeters and angles in degrees. The file has no flag for the
+/// two-point correction, which is taken as available for the lasers
+/// giving both x and y corrections.
+fn parse_veloview_xml(text: &str) -> Result<Vec<LaserCorrection>> {
+    let doc = roxmltree::Document::parse(text)?;
+
+    let lasers: Vec<_> = doc
+        .descendants()
+        .filter(|node| node.has_tag_name("px"))
+        .map(|px| -> Result<_> {
+            let get = |name: &str| -> Result<Option<f64>> {
+                let Some(node) = px.children().find(|node| node.has_tag_name(name)) else {
+                    return Ok(None);
+                };
+                let text = node.text().unwrap_or("").trim();
+                let value = text
+                    .parse()
+                    .wrap_err_with(|| format!("invalid value '{text}' in <{name}>"))?;
+                Ok(Some(value))
+            };
+            let cm = |name: &str| -> Result<f64> { Ok(get(name)?.unwrap_or(0.0) / 100.0) };
+            let deg = |name: &str| -> Result<f64> { Ok(get(name)?.unwrap_or(0.0).to_radians()) };
+
+            let laser_id = get("id_")?.ok_or_else(|| format_err!("<id_> is missing in <px>"))?;
+            let two_pt_correction_available =
+                get("distCorrectionX_")?.is_some() && get("distCorrectionY_")?.is_some();
+
+            Ok(LaserCorrection {
+                laser_id: laser_id as usize,
+                rot_correction: deg("rotCorrection_")?,
+                vert_correction: deg("vertCorrection_")?,
+                dist_correction: cm("distCorrection_")?,
+                dist_correction_x: cm("distCorrectionX_")?,
+                dist_correction_y: cm("distCorrectionY_")?,
+                two_pt_correction_available,
+                vert_offset_correction: cm("vertOffsetCorrection_")?,
+                horiz_offset_correction: cm("horizOffsetCorrection_")?,
+            })
+        })
+        .try_collect()?;
+
+    ensure!(!lasers.is_empty(), "no <px> entries are found");
+    Ok(lasers)
+}
+
+/// The laser entry of a ROS velodyne_pointcloud YAML file, which gives
+/// lengths in meters and angles in radians.
+#[derive(Debug, Deserialize)]
+struct RosLaser {
+    laser_id: usize,
+    #[serde(default)]
+    rot_correction: f64,
+    vert_correction: f64,
+    #[serde(default)]
+    dist_correction: f64,
+    #[serde(default)]
+    dist_correction_x: f64,
+    #[serde(default)]
+    dist_correction_y: f64,
+    #[serde(default)]
+    two_pt_correction_available: bool,
+    #[serde(default)]
+    vert_offset_correction: f64,
+    #[serde(default)]
+    horiz_offset_correction: f64,
+}
+
+#[derive(Debug, Deserialize)]
+struct RosCalibration {
+    lasers: Vec<RosLaser>,
+}
+
+fn parse_ros_yaml(text: &str) -> Result<Vec<LaserCorrection>> {
+    let RosCalibration { lasers } = serde_yaml::from_str(text)?;
+    ensure!(!lasers.is_empty(), "no lasers are found");
+
+    let lasers = lasers
+        .into_iter()
+        .map(|laser| LaserCorrection {
+            laser_id: laser.laser_id,
+            rot_correction: laser.rot_correction,
+            vert_correction: laser.vert_correction,
+            dist_correction: laser.dist_correction,
+            dist_correction_x: laser.dist_correction_x,
+            dist_correction_y: laser.dist_correction_y,
+            two_pt_correction_available: laser.two_pt_correction_available,
+            vert_offset_correction: laser.vert_offset_correction,
+            horiz_offset_correction: laser.horiz_offset_correction,
+        })
+        .collect();
+    Ok(lasers)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::velodyne::VelodyneMeasurement;
+    use approx::assert_abs_diff_eq;
+    use std::{f64::consts::FRAC_PI_2, time::Duration};
+
+    /// A laser pointing horizontally from the sensor origin with the
+    /// distance correction of 10 cm.
+    fn laser(two_pt_correction_available: bool) -> LaserCorrection {
+        LaserCorrection {
+            laser_id: 0,
+            rot_correction: 0.0,
+            vert_correction: 0.0,
+            dist_correction: 0.1,
+            dist_correction_x: 0.3,
+            dist_correction_y: 0.2,
+            two_pt_correction_available,
+            vert_offset_correction: 0.0,
+            horiz_offset_correction: 0.0,
+        }
+    }
+
+    #[test]
+    fn parse_ros_yaml_lasers() {
+        let text = "\
+lasers:
+- {laser_id: 1, rot_correction: -0.1, vert_correction: 0.2, dist_correction: 1.2,
+   dist_correction_x: 1.3, dist_correction_y: 1.25, two_pt_correction_available: true,
+   vert_offset_correction: 0.2, horiz_offset_correction: 0.026}
+- {laser_id: 0, vert_correction: -0.3}
+num_lasers: 2
+distance_resolution: 0.002
+";
+        let lasers = parse_ros_yaml(text).unwrap();
+        assert_eq!(lasers.len(), 2);
+
+        let laser = &lasers[0];
+        assert_eq!(laser.laser_id, 1);
+        assert_eq!(laser.rot_correction, -0.1);
+        assert_eq!(laser.vert_correction, 0.2);
+        assert_eq!(laser.dist_correction, 1.2);
+        assert_eq!(laser.dist_correction_x, 1.3);
+        assert_eq!(laser.dist_correction_y, 1.25);
+        assert!(laser.two_pt_correction_available);
+        assert_eq!(laser.vert_offset_correction, 0.2);
+        assert_eq!(laser.horiz_offset_correction, 0.026);
+
+        // The omitted corrections default to zero and the two-point
+        // correction to unavailable.
+        let laser = &lasers[1];
+        assert_eq!(laser.laser_id, 0);
+        assert_eq!(laser.vert_correction, -0.3);
+        assert_eq!(laser.dist_correction, 0.0);
+        assert!(!laser.two_pt_correction_available);
+
+        assert!(parse_ros_yaml("lasers: []\n").is_err());
+        assert!(parse_ros_yaml("lasers:\n- {laser_id: 0}\n").is_err());
+    }
+
+    #[test]
+    fn parse_veloview_xml_points() {
+        let text = r#"<?xml version="1.0" encoding="UTF-8"?>
+<boost_serialization>
+<DB>
+  <points_>
+    <item>
+      <px>
+        <id_>0</id_>
+        <rotCorrection_>-5</rotCorrection_>
+        <vertCorrection_>-7.5</vertCorrection_>
+        <distCorrection_>120</distCorrection_>
+        <distCorrectionX_>130</distCorrectionX_>
+        <distCorrectionY_>125</distCorrectionY_>
+        <vertOffsetCorrection_>20</vertOffsetCorrection_>
+        <horizOffsetCorrection_>2.6</horizOffsetCorrection_>
+      </px>
+    </item>
+    <item>
+      <px>
+        <id_>1</id_>
+        <vertCorrection_>3</vertCorrection_>
+        <distCorrection_>110</distCorrection_>
+      </px>
+    </item>
+  </points_>
+</DB>
+</boost_serialization>
+"#;
+        let lasers = parse_veloview_xml(text).unwrap();
+        assert_eq!(lasers.len(), 2);
+
+        // Lengths are converted from centimeters and angles from
+        // degrees.
+        let laser = &lasers[0];
+        assert_eq!(laser.laser_id, 0);
+        assert_abs_diff_eq!(laser.rot_correction, (-5.0f64).to_radians());
+        assert_abs_diff_eq!(laser.vert_correction, (-7.5f64).to_radians());
+        assert_abs_diff_eq!(laser.dist_correction, 1.2);
+        assert_abs_diff_eq!(laser.dist_correction_x, 1.3);
+        assert_abs_diff_eq!(laser.dist_correction_y, 1.25);
+        assert_abs_diff_eq!(laser.vert_offset_correction, 0.2);
+        assert_abs_diff_eq!(laser.horiz_offset_correction, 0.026);
+        assert!(laser.two_pt_correction_available);
+
+        let laser = &lasers[1];
+        assert_eq!(laser.laser_id, 1);
+        assert_abs_diff_eq!(laser.vert_correction, 3f64.to_radians());
+        assert_eq!(laser.rot_correction, 0.0);
+        assert!(!laser.two_pt_correction_available);
+
+        assert!(parse_veloview_xml("<DB><points_></points_></DB>").is_err());
+        assert!(parse_veloview_xml("<px><id_>zero</id_></px>").is_err());
+        assert!(parse_veloview_xml("<px><vertCorrection_>1</vertCorrection_></px>").is_err());
+    }
+
+    #[test]
+    fn correct_without_two_point_correction() {
+        let laser = laser(false);
+
+        let (distance, xyz) = laser.correct(1.83, 0.0);
+        assert_abs_diff_eq!(distance, 1.93, epsilon = 1e-12);
+        assert_abs_diff_eq!(xyz[0], 0.0, epsilon = 1e-12);
+        assert_abs_diff_eq!(xyz[1], 1.93, epsilon = 1e-12);
+        assert_abs_diff_eq!(xyz[2], 0.0, epsilon = 1e-12);
+
+        let (_, xyz) = laser.correct(2.3, FRAC_PI_2);
+        assert_abs_diff_eq!(xyz[0], 2.4, epsilon = 1e-12);
+        assert_abs_diff_eq!(xyz[1], 0.0, epsilon = 1e-12);
+    }
+
+    #[test]
+    fn correct_with_two_point_correction() {
+        let laser = laser(true);
+
+        // The y correction is dist_correction_y at 1.93 m and fades to
+        // dist_correction at 25.04 m.
+        let (distance, xyz) = laser.correct(1.83, 0.0);
+        assert_abs_diff_eq!(distance, 1.93, epsilon = 1e-12);
+        assert_abs_diff_eq!(xyz[1], 2.03, epsilon = 1e-12);
+        assert_abs_diff_eq!(xyz[2], 0.0, epsilon = 1e-12);
+
+        let (_, xyz) = laser.correct(24.94, 0.0);
+        assert_abs_diff_eq!(xyz[1], 25.04, epsilon = 1e-12);
+
+        // Likewise the x correction from 2.4 m.
+        let (_, xyz) = laser.correct(2.3, FRAC_PI_2);
+        assert_abs_diff_eq!(xyz[0], 2.6, epsilon = 1e-12);
+    }
+
+    #[test]
+    fn correct_point_skips_missing_returns() {
+        let mut laser = laser(false);
+        laser.rot_correction = 5f64.to_radians();
+        laser.vert_correction = 2f64.to_radians();
+        let calibration = VelodyneCalibration {
+            lasers: vec![laser],
+        };
+
+        let measurement = |distance| VelodyneMeasurement {
+            distance,
+            intensity: 0,
+            xyz: [0.0; 3],
+        };
+        let mut point = VelodynePoint {
+            laser_id: 0,
+            toh: Duration::ZERO,
+            azimuth: Angle::from_degrees(95.0),
+            elevation: Angle::from_degrees(0.0),
+            strongest: Some(measurement(0.0)),
+            last: Some(measurement(9.9)),
+        };
+        calibration.correct_point(&mut point);
+
+        assert_abs_diff_eq!(point.azimuth.as_degrees(), 90.0, epsilon = 1e-9);
+        assert_abs_diff_eq!(point.elevation.as_degrees(), 2.0, epsilon = 1e-9);
+
+        let strongest = point.strongest.unwrap();
+        assert_eq!(strongest.distance, 0.0);
+        assert_eq!(strongest.xyz, [0.0; 3]);
+
+        let last = point.last.unwrap();
+        let (sin_vert, cos_vert) = 2f64.to_radians().sin_cos();
+        assert_abs_diff_eq!(last.distance, 10.0, epsilon = 1e-9);
+        assert_abs_diff_eq!(last.xyz[0], 10.0 * cos_vert, epsilon = 1e-9);
+        assert_abs_diff_eq!(last.xyz[1], 0.0, epsilon = 1e-9);
+        assert_abs_diff_eq!(last.xyz[2], 10.0 * sin_vert, epsilon = 1e-9);
+    }
+}
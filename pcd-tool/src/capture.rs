@@ -0,0 +1,1097 @@
+//! Reading of the packets in pcap and pcapng capture files.
+//!
+//! Both formats are parsed here rather than by libpcap, so that the
+//! interfaces and comments of pcapng captures are available and
+//! packets can be selected by interface.
+
+use crate::packet::UdpPacket;
+use eyre::{bail, ensure, format_err, Result, WrapErr};
+use std::{
+    fmt,
+    fs::File,
+    io::{self, prelude::*, BufReader, SeekFrom},
+    net::Ipv4Addr,
+    path::Path,
+    str::FromStr,
+    time::Duration,
+};
+
+/// The magic numbers of classic pcap files in the file byte order,
+/// with microsecond and nanosecond timestamp resolution.
+const PCAP_MAGIC_MICROS: u32 = 0xa1b2c3d4;
+const PCAP_MAGIC_NANOS: u32 = 0xa1b23c4d;
+
+/// The block type of a pcapng section header, which reads the same in
+/// both byte orders.
+const PCAPNG_SECTION_HEADER: u32 = 0x0a0d0d0a;
+/// The byte-order magic in a pcapng section header.
+const PCAPNG_BYTE_ORDER_MAGIC: u32 = 0x1a2b3c4d;
+
+const PCAPNG_INTERFACE_DESCRIPTION: u32 = 1;
+const PCAPNG_OBSOLETE_PACKET: u32 = 2;
+const PCAPNG_SIMPLE_PACKET: u32 = 3;
+const PCAPNG_ENHANCED_PACKET: u32 = 6;
+
+const OPT_END_OF_OPT: u16 = 0;
+const OPT_COMMENT: u16 = 1;
+const IF_NAME: u16 = 2;
+const IF_DESCRIPTION: u16 = 3;
+const IF_TSRESOL: u16 = 9;
+const IF_TSOFFSET: u16 = 14;
+
+/// The upper bound of the size of a packet or a block, which guards
+/// against allocating for corrupted lengths.
+const MAX_BLOCK_SIZE: u32 = 16 * 1024 * 1024;
+
+/// Tells whether the leading bytes of a file are the magic number of a
+/// pcap or pcapng file.
+pub fn is_capture_file(head: &[u8]) -> bool {
+    let Some(magic) = head.get(..4) else {
+        return false;
+    };
+    let magic: [u8; 4] = magic.try_into().unwrap();
+
+    [u32::from_le_bytes(magic), u32::from_be_bytes(magic)]
+        .iter()
+        .any(|&magic| {
+            matches!(
+                magic,
+                PCAP_MAGIC_MICROS | PCAP_MAGIC_NANOS | PCAPNG_SECTION_HEADER
+            )
+        })
+}
+
+/// Selects an interface of a capture by the index or by the name.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InterfaceSelector {
+    Index(u32),
+    Name(String),
+}
+
+impl InterfaceSelector {
+    fn matches(&self, interface: &Interface) -> bool {
+        match self {
+            Self::Index(index) => interface.id == *index,
+            Self::Name(name) => interface.name.as_deref() == Some(name.as_str()),
+        }
+    }
+}
+
+impl FromStr for InterfaceSelector {
+    type Err = eyre::Error;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        ensure!(!text.is_empty(), "the interface must not be empty");
+
+        let selector = match text.parse() {
+            Ok(index) => Self::Index(index),
+            Err(_) => Self::Name(text.to_string()),
+        };
+        Ok(selector)
+    }
+}
+
+impl fmt::Display for InterfaceSelector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Index(index) => write!(f, "{index}"),
+            Self::Name(name) => write!(f, "{name}"),
+        }
+    }
+}
+
+/// The criteria of the packets to be read from a capture.
+#[derive(Debug, Clone, Default)]
+pub struct PacketFilter {
+    /// Reads only the packets captured on the interface. All
+    /// interfaces are read if it is `None`.
+    pub interface: Option<InterfaceSelector>,
+    /// Reads only the UDP datagrams sent from the IP address.
+    pub source_ip: Option<Ipv4Addr>,
+    /// Reads only the UDP datagrams sent to the port.
+    pub port: Option<u16>,
+}
+
+impl PacketFilter {
+    fn matches_interface(&self, interface: &Interface) -> bool {
+        match &self.interface {
+            Some(selector) => selector.matches(interface),
+            None => true,
+        }
+    }
+
+    /// Tells whether the filter selects UDP datagrams by the source
+    /// or the port, in which case packets other than UDP are dropped.
+    fn filters_udp(&self) -> bool {
+        self.source_ip.is_some() || self.port.is_some()
+    }
+
+    fn matches_udp(&self, packet: &UdpPacket<'_>) -> bool {
+        let ip_matches = self.source_ip.map_or(true, |ip| *packet.source.ip() == ip);
+        let port_matches = self
+            .port
+            .map_or(true, |port| packet.destination.port() == port);
+        ip_matches && port_matches
+    }
+}
+
+/// A capture interface. Classic pcap files have a single unnamed
+/// interface.
+#[derive(Debug, Clone)]
+pub struct Interface {
+    /// The index of the interface in the whole file. The interfaces
+    /// of later pcapng sections follow those of earlier sections.
+    pub id: u32,
+    pub linktype: u16,
+    pub name: Option<String>,
+    pub description: Option<String>,
+    /// The number of timestamp units per second.
+    units_per_sec: u64,
+    /// The offset in seconds added to the timestamps.
+    offset_secs: i64,
+}
+
+impl Interface {
+    fn timestamp(&self, units: u64) -> Duration {
+        let secs = units / self.units_per_sec;
+        let nanos =
+            (units % self.units_per_sec) as u128 * 1_000_000_000 / self.units_per_sec as u128;
+        let time = Duration::new(secs, nanos as u32);
+        let offset = Duration::from_secs(self.offset_secs.unsigned_abs());
+
+        if self.offset_secs >= 0 {
+            time + offset
+        } else {
+            time.saturating_sub(offset)
+        }
+    }
+}
+
+/// A packet read from a capture.
+#[derive(Debug, Clone)]
+pub struct CapturePacket {
+    /// The byte offset of the packet record or block in the file.
+    pub offset: u64,
+    /// The index of the interface the packet was captured on.
+    pub interface: u32,
+    pub linktype: u16,
+    /// The capture time since the Unix epoch.
+    pub time: Duration,
+    pub data: Vec<u8>,
+    /// The comments attached to the packet.
+    pub comments: Vec<String>,
+}
+
+impl CapturePacket {
+    /// Parses the packet as a UDP datagram over IPv4.
+    pub fn udp(&self) -> Option<UdpPacket<'_>> {
+        UdpPacket::parse(self.linktype, self.time, &self.data)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum CaptureKind {
+    Pcap { big_endian: bool, nanos: bool },
+    Pcapng { big_endian: bool },
+}
+
+/// Reads the packets in a pcap or pcapng file in order, keeping those
+/// accepted by a [PacketFilter].
+pub struct CaptureReader<R = BufReader<File>> {
+    reader: OffsetReader<R>,
+    kind: CaptureKind,
+    filter: PacketFilter,
+    interfaces: Vec<Interface>,
+    /// The index of the first interface of the current pcapng section.
+    section_base: usize,
+    section_comments: Vec<String>,
+    /// Whether an interface is accepted by the filter.
+    interface_matched: bool,
+    finished: bool,
+}
+
+impl CaptureReader {
+    pub fn open<P>(path: P, filter: &PacketFilter) -> Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        let path = path.as_ref();
+        let reader = BufReader::new(File::open(path)?);
+        Self::new(reader, filter)
+            .wrap_err_with(|| format!("unable to read the capture file '{}'", path.display()))
+    }
+}
+
+impl<R> CaptureReader<R>
+where
+    R: Read + Seek,
+{
+    /// Reads a capture from the start of the reader.
+    pub fn new(reader: R, filter: &PacketFilter) -> Result<Self> {
+        let mut reader = OffsetReader {
+            inner: reader,
+            offset: 0,
+        };
+
+        let mut magic = [0u8; 4];
+        reader
+            .read_exact(&mut magic)
+            .wrap_err("not a capture file")?;
+
+        let (le, be) = (u32::from_le_bytes(magic), u32::from_be_bytes(magic));
+        let kind = if le == PCAPNG_SECTION_HEADER {
+            // The byte order is read with the section header below.
+            CaptureKind::Pcapng { big_endian: false }
+        } else if le == PCAP_MAGIC_MICROS || le == PCAP_MAGIC_NANOS {
+            CaptureKind::Pcap {
+                big_endian: false,
+                nanos: le == PCAP_MAGIC_NANOS,
+            }
+        } else if be == PCAP_MAGIC_MICROS || be == PCAP_MAGIC_NANOS {
+            CaptureKind::Pcap {
+                big_endian: true,
+                nanos: be == PCAP_MAGIC_NANOS,
+            }
+        } else {
+            bail!("not a pcap or pcapng file");
+        };
+
+        let mut capture = Self {
+            reader,
+            kind,
+            filter: filter.clone(),
+            interfaces: vec![],
+            section_base: 0,
+            section_comments: vec![],
+            interface_matched: false,
+            finished: false,
+        };
+
+        match kind {
+            CaptureKind::Pcap { big_endian, nanos } => {
+                let mut header = [0u8; 20];
+                capture
+                    .reader
+                    .read_exact(&mut header)
+                    .wrap_err("truncated pcap file header")?;
+                let network = read_u32(&header[16..], big_endian);
+
+                capture.push_interface(Interface {
+                    id: 0,
+                    linktype: network as u16,
+                    name: None,
+                    description: None,
+                    units_per_sec: if nanos { 1_000_000_000 } else { 1_000_000 },
+                    offset_secs: 0,
+                });
+            }
+            CaptureKind::Pcapng { .. } => capture.read_section_header()?,
+        }
+
+        Ok(capture)
+    }
+
+    /// Gives the interfaces found so far. The list is complete once
+    /// all packets are read.
+    pub fn interfaces(&self) -> &[Interface] {
+        &self.interfaces
+    }
+
+    /// Gives the comments of the pcapng section headers found so far.
+    pub fn section_comments(&self) -> &[String] {
+        &self.section_comments
+    }
+
+    /// Gives the byte offset in the file where the next read starts.
+    pub fn offset(&self) -> u64 {
+        self.reader.offset
+    }
+
+    /// Moves to the packet at the byte offset, given the number of
+    /// interfaces declared before the packet.
+    ///
+    /// The pcapng interfaces are usually declared ahead of all packets.
+    /// The blocks up to the first packet are read, and the reader jumps
+    /// to the offset if all the interfaces are known by then. Otherwise
+    /// the blocks are read on up to the offset.
+    pub fn seek(&mut self, offset: u64, num_interfaces: usize) -> Result<()> {
+        if let CaptureKind::Pcapng { .. } = self.kind {
+            if self.interfaces.len() < num_interfaces {
+                self.read_pcapng_block()?;
+            }
+            while self.interfaces.len() < num_interfaces && self.reader.offset < offset {
+                if self.read_pcapng_block()?.is_none() {
+                    break;
+                }
+            }
+            ensure!(
+                self.interfaces.len() >= num_interfaces,
+                "the capture declares fewer interfaces than expected"
+            );
+        }
+
+        self.reader.seek_to(offset)?;
+        Ok(())
+    }
+
+    /// Reads the next packet accepted by the filter. It gives `None`
+    /// at the end of the file.
+    pub fn next_packet(&mut self) -> Result<Option<CapturePacket>> {
+        loop {
+            let packet = match self.kind {
+                CaptureKind::Pcap { big_endian, .. } => self.read_pcap_record(big_endian)?,
+                CaptureKind::Pcapng { .. } => self.read_pcapng_block()?,
+            };
+
+            let Some(packet) = packet else {
+                if let Some(selector) = &self.filter.interface {
+                    ensure!(
+                        self.interface_matched,
+                        "no interface in the capture matches '{selector}'"
+                    );
+                }
+                return Ok(None);
+            };
+
+            let interface = &self.interfaces[packet.interface as usize];
+            if !self.filter.matches_interface(interface) {
+                continue;
+            }
+
+            if self.filter.filters_udp() {
+                match packet.udp() {
+                    Some(udp) if self.filter.matches_udp(&udp) => {}
+                    _ => continue,
+                }
+            }
+
+            return Ok(Some(packet));
+        }
+    }
+
+    fn push_interface(&mut self, interface: Interface) {
+        if self.filter.matches_interface(&interface) {
+            self.interface_matched = true;
+        }
+        self.interfaces.push(interface);
+    }
+
+    fn read_pcap_record(&mut self, big_endian: bool) -> Result<Option<CapturePacket>> {
+        let offset = self.reader.offset;
+        let mut header = [0u8; 16];
+        if !read_exact_or_eof(&mut self.reader, &mut header)? {
+            return Ok(None);
+        }
+
+        let secs = read_u32(&header[0..], big_endian) as u64;
+        let frac = read_u32(&header[4..], big_endian) as u64;
+        let caplen = read_u32(&header[8..], big_endian);
+        ensure!(
+            caplen <= MAX_BLOCK_SIZE,
+            "invalid packet length {caplen} in the pcap file"
+        );
+
+        let mut data = vec![0u8; caplen as usize];
+        self.reader
+            .read_exact(&mut data)
+            .wrap_err("truncated packet in the pcap file")?;
+
+        let interface = &self.interfaces[0];
+        Ok(Some(CapturePacket {
+            offset,
+            interface: 0,
+            linktype: interface.linktype,
+            time: interface.timestamp(secs * interface.units_per_sec + frac),
+            data,
+            comments: vec![],
+        }))
+    }
+
+    /// Reads the rest of a pcapng section header after the block type.
+    fn read_section_header(&mut self) -> Result<()> {
+        let mut head = [0u8; 8];
+        self.reader
+            .read_exact(&mut head)
+            .wrap_err("truncated pcapng section header")?;
+
+        let big_endian = match u32::from_le_bytes(head[4..8].try_into().unwrap()) {
+            PCAPNG_BYTE_ORDER_MAGIC => false,
+            magic if magic.swap_bytes() == PCAPNG_BYTE_ORDER_MAGIC => true,
+            _ => bail!("invalid byte-order magic in the pcapng section header"),
+        };
+        self.kind = CaptureKind::Pcapng { big_endian };
+
+        let len = read_u32(&head[0..], big_endian);
+        ensure!(
+            (28..=MAX_BLOCK_SIZE).contains(&len) && len % 4 == 0,
+            "invalid pcapng section header length {len}"
+        );
+
+        // The version and the section length are not used.
+        let mut body = vec![0u8; len as usize - 12];
+        self.reader
+            .read_exact(&mut body)
+            .wrap_err("truncated pcapng section header")?;
+        let options = &body[12..body.len() - 4];
+
+        for (code, value) in parse_options(options, big_endian) {
+            if code == OPT_COMMENT {
+                self.section_comments.push(read_string(value));
+            }
+        }
+
+        // Interface IDs are local to a section.
+        self.section_base = self.interfaces.len();
+        Ok(())
+    }
+
+    /// Reads pcapng blocks until a packet is found.
+    fn read_pcapng_block(&mut self) -> Result<Option<CapturePacket>> {
+        loop {
+            let offset = self.reader.offset;
+            let mut head = [0u8; 4];
+            if !read_exact_or_eof(&mut self.reader, &mut head)? {
+                return Ok(None);
+            }
+
+            let block_type = u32::from_le_bytes(head);
+            if block_type == PCAPNG_SECTION_HEADER {
+                self.read_section_header()?;
+                continue;
+            }
+
+            let CaptureKind::Pcapng { big_endian } = self.kind else {
+                unreachable!();
+            };
+            let block_type = read_u32(&head, big_endian);
+
+            let mut len = [0u8; 4];
+            self.reader
+                .read_exact(&mut len)
+                .wrap_err("truncated pcapng block")?;
+            let len = read_u32(&len, big_endian);
+            ensure!(
+                (12..=MAX_BLOCK_SIZE).contains(&len) && len % 4 == 0,
+                "invalid pcapng block length {len}"
+            );
+
+            let mut body = vec![0u8; len as usize - 8];
+            self.reader
+                .read_exact(&mut body)
+                .wrap_err("truncated pcapng block")?;
+            let body = &body[..body.len() - 4];
+
+            match block_type {
+                PCAPNG_INTERFACE_DESCRIPTION => self.parse_interface(body, big_endian)?,
+                PCAPNG_ENHANCED_PACKET => {
+                    ensure!(body.len() >= 20, "truncated pcapng enhanced packet block");
+                    let interface = read_u32(&body[0..], big_endian);
+                    let ts_high = read_u32(&body[4..], big_endian) as u64;
+                    let ts_low = read_u32(&body[8..], big_endian) as u64;
+                    let caplen = read_u32(&body[12..], big_endian) as usize;
+                    let packet = self.parse_packet(
+                        offset,
+                        interface,
+                        (ts_high << 32) | ts_low,
+                        &body[20..],
+                        caplen,
+                    )?;
+                    return Ok(Some(packet));
+                }
+                PCAPNG_OBSOLETE_PACKET => {
+                    ensure!(body.len() >= 20, "truncated pcapng packet block");
+                    let interface = read_u16(&body[0..], big_endian) as u32;
+                    let ts_high = read_u32(&body[4..], big_endian) as u64;
+                    let ts_low = read_u32(&body[8..], big_endian) as u64;
+                    let caplen = read_u32(&body[12..], big_endian) as usize;
+                    let packet = self.parse_packet(
+                        offset,
+                        interface,
+                        (ts_high << 32) | ts_low,
+                        &body[20..],
+                        caplen,
+                    )?;
+                    return Ok(Some(packet));
+                }
+                PCAPNG_SIMPLE_PACKET => {
+                    // Simple packets belong to the first interface and
+                    // have no timestamp.
+                    ensure!(body.len() >= 4, "truncated pcapng simple packet block");
+                    let len = read_u32(&body[0..], big_endian) as usize;
+                    let data = &body[4..];
+                    let interface = self.local_interface(0)?;
+
+                    return Ok(Some(CapturePacket {
+                        offset,
+                        interface: interface.id,
+                        linktype: interface.linktype,
+                        time: Duration::ZERO,
+                        data: data[..len.min(data.len())].to_vec(),
+                        comments: vec![],
+                    }));
+                }
+                // Statistics, name resolution and other blocks are
+                // skipped.
+                _ => {}
+            }
+        }
+    }
+
+    fn parse_interface(&mut self, body: &[u8], big_endian: bool) -> Result<()> {
+        ensure!(
+            body.len() >= 8,
+            "truncated pcapng interface description block"
+        );
+
+        let mut interface = Interface {
+            id: self.interfaces.len() as u32,
+            linktype: read_u16(&body[0..], big_endian),
+            name: None,
+            description: None,
+            units_per_sec: 1_000_000,
+            offset_secs: 0,
+        };
+
+        for (code, value) in parse_options(&body[8..], big_endian) {
+            match code {
+                IF_NAME => interface.name = Some(read_string(value)),
+                IF_DESCRIPTION => interface.description = Some(read_string(value)),
+                IF_TSRESOL => {
+                    let Some(&resol) = value.first() else {
+                        continue;
+                    };
+                    let exp = (resol & 0x7f) as u32;
+                    let units_per_sec = if resol & 0x80 == 0 {
+                        10u64.checked_pow(exp)
+                    } else {
+                        1u64.checked_shl(exp)
+                    };
+                    interface.units_per_sec =
+                        units_per_sec.filter(|&units| units > 0).ok_or_else(|| {
+                            format_err!("unsupported timestamp resolution {resol:#x}")
+                        })?;
+                }
+                IF_TSOFFSET if value.len() >= 8 => {
+                    let bytes: [u8; 8] = value[..8].try_into().unwrap();
+                    interface.offset_secs = if big_endian {
+                        i64::from_be_bytes(bytes)
+                    } else {
+                        i64::from_le_bytes(bytes)
+                    };
+                }
+                _ => {}
+            }
+        }
+
+        self.push_interface(interface);
+        Ok(())
+    }
+
+    fn parse_packet(
+        &self,
+        offset: u64,
+        local_id: u32,
+        timestamp: u64,
+        rest: &[u8],
+        caplen: usize,
+    ) -> Result<CapturePacket> {
+        let interface = self.local_interface(local_id)?;
+        ensure!(caplen <= rest.len(), "truncated pcapng packet data");
+
+        let (big_endian, padded) = match self.kind {
+            CaptureKind::Pcapng { big_endian } => (big_endian, (caplen + 3) / 4 * 4),
+            CaptureKind::Pcap { .. } => unreachable!(),
+        };
+        let comments = parse_options(rest.get(padded..).unwrap_or(&[]), big_endian)
+            .filter(|&(code, _)| code == OPT_COMMENT)
+            .map(|(_, value)| read_string(value))
+            .collect();
+
+        Ok(CapturePacket {
+            offset,
+            interface: interface.id,
+            linktype: interface.linktype,
+            time: interface.timestamp(timestamp),
+            data: rest[..caplen].to_vec(),
+            comments,
+        })
+    }
+
+    /// Finds an interface by the ID local to the current section.
+    fn local_interface(&self, local_id: u32) -> Result<&Interface> {
+        self.interfaces
+            .get(self.section_base + local_id as usize)
+            .ok_or_else(|| format_err!("packet refers to unknown interface {local_id}"))
+    }
+}
+
+impl<R> Iterator for CaptureReader<R>
+where
+    R: Read + Seek,
+{
+    type Item = Result<CapturePacket>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.finished {
+            return None;
+        }
+
+        let result = self.next_packet().transpose();
+        if !matches!(result, Some(Ok(_))) {
+            self.finished = true;
+        }
+        result
+    }
+}
+
+/// A reader keeping track of the byte offset, so that packets can be
+/// located in the file.
+struct OffsetReader<R> {
+    inner: R,
+    offset: u64,
+}
+
+impl<R> OffsetReader<R>
+where
+    R: Seek,
+{
+    fn seek_to(&mut self, offset: u64) -> io::Result<()> {
+        self.inner.seek(SeekFrom::Start(offset))?;
+        self.offset = offset;
+        Ok(())
+    }
+}
+
+impl<R> Read for OffsetReader<R>
+where
+    R: Read,
+{
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = self.inner.read(buf)?;
+        self.offset += len as u64;
+        Ok(len)
+    }
+}
+
+/// Iterates over the options of a pcapng block as pairs of the option
+/// code and the value.
+fn parse_options(mut bytes: &[u8], big_endian: bool) -> impl Iterator<Item = (u16, &[u8])> {
+    std::iter::from_fn(move || {
+        if bytes.len() < 4 {
+            return None;
+        }
+        let code = read_u16(&bytes[0..], big_endian);
+        let len = read_u16(&bytes[2..], big_endian) as usize;
+        if code == OPT_END_OF_OPT || bytes.len() < 4 + len {
+            return None;
+        }
+
+        let value = &bytes[4..4 + len];
+        let padded = (len + 3) / 4 * 4;
+        bytes = bytes.get(4 + padded..).unwrap_or(&[]);
+        Some((code, value))
+    })
+}
+
+fn read_string(value: &[u8]) -> String {
+    String::from_utf8_lossy(value)
+        .trim_end_matches('\0')
+        .to_string()
+}
+
+fn read_u16(bytes: &[u8], big_endian: bool) -> u16 {
+    let bytes = [bytes[0], bytes[1]];
+    if big_endian {
+        u16::from_be_bytes(bytes)
+    } else {
+        u16::from_le_bytes(bytes)
+    }
+}
+
+fn read_u32(bytes: &[u8], big_endian: bool) -> u32 {
+    let bytes = [bytes[0], bytes[1], bytes[2], bytes[3]];
+    if big_endian {
+        u32::from_be_bytes(bytes)
+    } else {
+        u32::from_le_bytes(bytes)
+    }
+}
+
+/// Fills the buffer from the reader. It gives `false` if the reader is
+/// at the end, and fails if the data ends in the middle of the buffer.
+fn read_exact_or_eof<R>(reader: &mut R, buf: &mut [u8]) -> Result<bool>
+where
+    R: Read,
+{
+    let mut len = 0;
+
+    while len < buf.len() {
+        match reader.read(&mut buf[len..]) {
+            Ok(0) => break,
+            Ok(cnt) => len += cnt,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+            Err(err) => return Err(err.into()),
+        }
+    }
+
+    match len {
+        0 => Ok(false),
+        len if len == buf.len() => Ok(true),
+        _ => bail!("the capture file is truncated"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    const SECS: u64 = 1_700_000_000;
+
+    fn read_all(bytes: &[u8], filter: &PacketFilter) -> Result<Vec<CapturePacket>> {
+        CaptureReader::new(Cursor::new(bytes.to_vec()), filter)?.collect()
+    }
+
+    /// Builds the bytes of a capture in either byte order.
+    struct Builder {
+        big_endian: bool,
+        bytes: Vec<u8>,
+    }
+
+    impl Builder {
+        fn new(big_endian: bool) -> Self {
+            Self {
+                big_endian,
+                bytes: vec![],
+            }
+        }
+
+        fn u16(mut self, value: u16) -> Self {
+            let bytes = if self.big_endian {
+                value.to_be_bytes()
+            } else {
+                value.to_le_bytes()
+            };
+            self.bytes.extend(bytes);
+            self
+        }
+
+        fn u32(mut self, value: u32) -> Self {
+            let bytes = if self.big_endian {
+                value.to_be_bytes()
+            } else {
+                value.to_le_bytes()
+            };
+            self.bytes.extend(bytes);
+            self
+        }
+
+        fn u64(mut self, value: u64) -> Self {
+            let bytes = if self.big_endian {
+                value.to_be_bytes()
+            } else {
+                value.to_le_bytes()
+            };
+            self.bytes.extend(bytes);
+            self
+        }
+
+        fn raw(mut self, bytes: &[u8]) -> Self {
+            self.bytes.extend(bytes);
+            self
+        }
+
+        /// Appends bytes padded to 32 bits.
+        fn padded(self, bytes: &[u8]) -> Self {
+            let padding = (4 - bytes.len() % 4) % 4;
+            self.raw(bytes).raw(&[0; 3][..padding])
+        }
+
+        fn option(self, code: u16, value: &[u8]) -> Self {
+            self.u16(code).u16(value.len() as u16).padded(value)
+        }
+
+        fn end_of_options(self) -> Self {
+            self.u16(OPT_END_OF_OPT).u16(0)
+        }
+
+        /// Appends a pcapng block with the body built by `body`.
+        fn block(self, block_type: u32, body: impl FnOnce(Self) -> Self) -> Self {
+            let body = body(Self::new(self.big_endian)).bytes;
+            let len = body.len() as u32 + 12;
+            self.u32(block_type).u32(len).raw(&body).u32(len)
+        }
+
+        fn section_header(self, comment: Option<&str>) -> Self {
+            self.block(PCAPNG_SECTION_HEADER, |body| {
+                let body = body
+                    .u32(PCAPNG_BYTE_ORDER_MAGIC)
+                    .u16(1)
+                    .u16(0)
+                    .u64(u64::MAX);
+                match comment {
+                    Some(comment) => body
+                        .option(OPT_COMMENT, comment.as_bytes())
+                        .end_of_options(),
+                    None => body,
+                }
+            })
+        }
+
+        fn interface(self, name: &str, tsresol: Option<u8>, tsoffset: Option<i64>) -> Self {
+            let big_endian = self.big_endian;
+            self.block(PCAPNG_INTERFACE_DESCRIPTION, |body| {
+                let mut body = body
+                    .u16(1)
+                    .u16(0)
+                    .u32(65535)
+                    .option(IF_NAME, name.as_bytes());
+                if let Some(resol) = tsresol {
+                    body = body.option(IF_TSRESOL, &[resol]);
+                }
+                if let Some(offset) = tsoffset {
+                    let offset = if big_endian {
+                        offset.to_be_bytes()
+                    } else {
+                        offset.to_le_bytes()
+                    };
+                    body = body.option(IF_TSOFFSET, &offset);
+                }
+                body.end_of_options()
+            })
+        }
+
+        fn enhanced_packet(
+            self,
+            interface: u32,
+            timestamp: u64,
+            data: &[u8],
+            comment: Option<&str>,
+        ) -> Self {
+            self.block(PCAPNG_ENHANCED_PACKET, |body| {
+                let body = body
+                    .u32(interface)
+                    .u32((timestamp >> 32) as u32)
+                    .u32(timestamp as u32)
+                    .u32(data.len() as u32)
+                    .u32(data.len() as u32)
+                    .padded(data);
+                match comment {
+                    Some(comment) => body
+                        .option(OPT_COMMENT, comment.as_bytes())
+                        .end_of_options(),
+                    None => body,
+                }
+            })
+        }
+
+        fn pcap_header(self, nanos: bool) -> Self {
+            let magic = if nanos {
+                PCAP_MAGIC_NANOS
+            } else {
+                PCAP_MAGIC_MICROS
+            };
+            self.u32(magic)
+                .u16(2)
+                .u16(4)
+                .u32(0)
+                .u32(0)
+                .u32(65535)
+                .u32(1)
+        }
+
+        fn pcap_record(self, secs: u32, frac: u32, data: &[u8]) -> Self {
+            self.u32(secs)
+                .u32(frac)
+                .u32(data.len() as u32)
+                .u32(data.len() as u32)
+                .raw(data)
+        }
+    }
+
+    /// A section with a comment and two interfaces. The second one
+    /// has nanosecond timestamps and an offset of 10 seconds.
+    fn two_interface_pcapng(big_endian: bool) -> Vec<u8> {
+        Builder::new(big_endian)
+            .section_header(Some("lab run"))
+            .interface("eth0", None, None)
+            .interface("eth1", Some(9), Some(10))
+            .enhanced_packet(
+                1,
+                SECS * 1_000_000_000 + 123_456_789,
+                b"hello",
+                Some("first"),
+            )
+            .enhanced_packet(0, SECS * 1_000_000 + 250_000, b"world!!!", None)
+            .bytes
+    }
+
+    fn check_two_interface_pcapng(big_endian: bool) {
+        let bytes = two_interface_pcapng(big_endian);
+
+        let mut reader =
+            CaptureReader::new(Cursor::new(bytes.clone()), &PacketFilter::default()).unwrap();
+        let packets: Vec<_> = reader.by_ref().map(Result::unwrap).collect();
+        assert_eq!(reader.section_comments(), ["lab run"]);
+        let names: Vec<_> = reader
+            .interfaces()
+            .iter()
+            .map(|interface| interface.name.as_deref())
+            .collect();
+        assert_eq!(names, [Some("eth0"), Some("eth1")]);
+
+        assert_eq!(packets.len(), 2);
+        assert_eq!(packets[0].interface, 1);
+        assert_eq!(packets[0].time, Duration::new(SECS + 10, 123_456_789));
+        assert_eq!(packets[0].data, b"hello");
+        assert_eq!(packets[0].comments, ["first"]);
+        assert_eq!(packets[1].interface, 0);
+        assert_eq!(packets[1].time, Duration::new(SECS, 250_000_000));
+        assert_eq!(packets[1].data, b"world!!!");
+        assert!(packets[1].comments.is_empty());
+
+        let filter = PacketFilter {
+            interface: Some(InterfaceSelector::Name("eth1".to_string())),
+            ..Default::default()
+        };
+        let packets = read_all(&bytes, &filter).unwrap();
+        assert_eq!(packets.len(), 1);
+        assert_eq!(packets[0].data, b"hello");
+
+        let filter = PacketFilter {
+            interface: Some(InterfaceSelector::Index(0)),
+            ..Default::default()
+        };
+        let packets = read_all(&bytes, &filter).unwrap();
+        assert_eq!(packets.len(), 1);
+        assert_eq!(packets[0].data, b"world!!!");
+    }
+
+    #[test]
+    fn pcapng_little_endian() {
+        check_two_interface_pcapng(false);
+    }
+
+    #[test]
+    fn pcapng_big_endian() {
+        check_two_interface_pcapng(true);
+    }
+
+    #[test]
+    fn pcapng_interface_ids_are_local_to_sections() {
+        let bytes = Builder::new(false)
+            .section_header(None)
+            .interface("eth0", None, None)
+            .enhanced_packet(0, SECS * 1_000_000, b"a", None)
+            .section_header(None)
+            .interface("eth1", Some(3), None)
+            .enhanced_packet(0, SECS * 1_000 + 500, b"b", None)
+            .bytes;
+
+        let packets = read_all(&bytes, &PacketFilter::default()).unwrap();
+        assert_eq!(packets.len(), 2);
+        assert_eq!(packets[0].interface, 0);
+        assert_eq!(packets[1].interface, 1);
+        assert_eq!(packets[1].time, Duration::new(SECS, 500_000_000));
+    }
+
+    #[test]
+    fn pcapng_simple_and_obsolete_packets() {
+        let bytes = Builder::new(false)
+            .section_header(None)
+            .interface("eth0", None, None)
+            .block(PCAPNG_SIMPLE_PACKET, |body| body.u32(3).padded(b"abc"))
+            .block(PCAPNG_OBSOLETE_PACKET, |body| {
+                body.u16(0)
+                    .u16(0)
+                    .u32(0)
+                    .u32(1_000_000)
+                    .u32(2)
+                    .u32(2)
+                    .padded(b"de")
+            })
+            .bytes;
+
+        let packets = read_all(&bytes, &PacketFilter::default()).unwrap();
+        assert_eq!(packets.len(), 2);
+        assert_eq!(packets[0].data, b"abc");
+        assert_eq!(packets[0].time, Duration::ZERO);
+        assert_eq!(packets[1].data, b"de");
+        assert_eq!(packets[1].time, Duration::from_secs(1));
+    }
+
+    #[test]
+    fn pcapng_unmatched_interface() {
+        let bytes = two_interface_pcapng(false);
+        let filter = PacketFilter {
+            interface: Some(InterfaceSelector::Name("wlan0".to_string())),
+            ..Default::default()
+        };
+        assert!(read_all(&bytes, &filter).is_err());
+    }
+
+    #[test]
+    fn pcapng_seek_to_packet() {
+        let bytes = two_interface_pcapng(false);
+        let packets = read_all(&bytes, &PacketFilter::default()).unwrap();
+
+        let mut reader = CaptureReader::new(Cursor::new(bytes), &PacketFilter::default()).unwrap();
+        reader.seek(packets[1].offset, 2).unwrap();
+        let packet = reader.next_packet().unwrap().unwrap();
+        assert_eq!(packet.data, b"world!!!");
+        assert_eq!(packet.time, Duration::new(SECS, 250_000_000));
+        assert!(reader.next_packet().unwrap().is_none());
+    }
+
+    #[test]
+    fn pcapng_truncated_block() {
+        let mut bytes = two_interface_pcapng(false);
+        bytes.truncate(bytes.len() - 6);
+
+        let mut reader = CaptureReader::new(Cursor::new(bytes), &PacketFilter::default()).unwrap();
+        assert!(reader.next().unwrap().is_ok());
+        assert!(reader.next().unwrap().is_err());
+        assert!(reader.next().is_none());
+    }
+
+    #[test]
+    fn pcap_microseconds() {
+        let bytes = Builder::new(false)
+            .pcap_header(false)
+            .pcap_record(SECS as u32, 123_456, b"abc")
+            .pcap_record(SECS as u32 + 1, 0, b"de")
+            .bytes;
+
+        let packets = read_all(&bytes, &PacketFilter::default()).unwrap();
+        assert_eq!(packets.len(), 2);
+        assert_eq!(packets[0].time, Duration::new(SECS, 123_456_000));
+        assert_eq!(packets[0].data, b"abc");
+        assert_eq!(packets[0].offset, 24);
+        assert_eq!(packets[1].time, Duration::from_secs(SECS + 1));
+        assert_eq!(packets[1].offset, 24 + 16 + 3);
+    }
+
+    #[test]
+    fn pcap_nanoseconds_big_endian() {
+        let bytes = Builder::new(true)
+            .pcap_header(true)
+            .pcap_record(SECS as u32, 123_456_789, b"abc")
+            .bytes;
+
+        let packets = read_all(&bytes, &PacketFilter::default()).unwrap();
+        assert_eq!(packets.len(), 1);
+        assert_eq!(packets[0].time, Duration::new(SECS, 123_456_789));
+        assert_eq!(packets[0].linktype, 1);
+    }
+
+    #[test]
+    fn pcap_truncated_record() {
+        let mut bytes = Builder::new(false)
+            .pcap_header(false)
+            .pcap_record(SECS as u32, 0, b"abcd")
+            .bytes;
+        bytes.pop();
+
+        assert!(read_all(&bytes, &PacketFilter::default()).is_err());
+    }
+}
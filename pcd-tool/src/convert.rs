@@ -1,40 +1,70 @@
 use crate::{
+    capture::PacketFilter,
+    index::{
+        frame_index, indexed_frame_iter, load_frame_index, store_frame_index, FrameIndexBuilder,
+    },
     io::{
-        count_frames_in_velodyne_pcap, create_libpcl_pcd_file_dual, create_libpcl_pcd_file_single,
-        create_pcd_reader, create_raw_bin_file_dual, create_raw_bin_file_single, load_bin_iter,
+        create_dyn_point_reader, create_pcd_file_single, create_raw_bin_file_single, load_bin_iter,
         RawBinWriter,
     },
+    livox::{livox_frame_iter_from_file, livox_header_from_file, LivoxFrame, LivoxSample},
     opts::{Convert, EndFrame, StartFrame, VelodyneReturnMode},
+    ouster::{ouster_frame_iter_from_file, OusterFrame, OusterMetadata, OusterSample},
+    position::{FrameTimeLog, TimeSource},
+    select::{select_frames, FrameSelector, Selection},
+    sources::{
+        find_source_value, list_sources, scan_frame_sources, warn_unmatched_source_values,
+        SourceLidar, SourceSelector,
+    },
+    spinning::SpinningLidar,
+    split::FrameSplit,
+    template::{FrameFields, OutputTemplate, Placeholder},
     types::{BinPoint, FileFormat},
-    utils::{build_velodyne_config, guess_file_format},
+    utils::{
+        detect_file_format, guess_file_format, resolve_hesai_lidar, resolve_ouster_metadata,
+        resolve_velodyne_lidar, TohClock,
+    },
+    velodyne::{VelodyneFrame, VelodyneMeasurement, VelodynePoint},
 };
-use approx::abs_diff_eq;
 use eyre::{bail, ensure, format_err, Context, Result};
 use itertools::Itertools;
+use measurements::Angle;
 use nalgebra as na;
-use pcd_format::{LibpclPoint, NewslabV1Point};
-use rayon::prelude::*;
+use pcd_format::{
+    DynPoint, FromPcdPoint, LibpclExtPoint, LibpclNormalPoint, LibpclPoint, LibpclXyzPoint,
+    LibpclXyziNormalPoint, LibpclXyziPoint, LibpclXyzlPoint, LibpclXyzrgbaPoint, LivoxPoint,
+    NewslabV1Point, NewslabV2Point, OusterPoint, PcdPoint, XyzirtPoint,
+};
+use pcd_rs::PcdSerialize;
+use rayon::{prelude::*, ThreadPoolBuilder};
 use std::{
+    cell::Cell,
     f64::{
         self,
         consts::{FRAC_PI_2, PI},
     },
     fs::{self, File},
     io::BufReader,
-    path::Path,
+    iter,
+    num::NonZeroUsize,
+    ops::Range,
+    panic,
+    path::{Path, PathBuf},
+    sync::mpsc,
+    thread,
+    time::Duration,
 };
 use tf_format::MaybeTransform;
-use velodyne_lidar::{
-    iter::frame_xyz_iter_from_file,
-    types::{
-        format::FormatKind,
-        measurements::{Measurement, MeasurementDual},
-        point::{PointD, PointS},
-    },
-    ProductID, ReturnMode,
-};
+use velodyne_lidar::ReturnMode;
 
 pub fn convert(opts: Convert) -> Result<()> {
+    if opts.list_sources {
+        return list_sources(&opts.input, &packet_filter(&opts), false);
+    }
+    if opts.split_sources {
+        return convert_each_source(opts);
+    }
+
     let input_path = &opts.input;
     let output_path = &opts.output;
 
@@ -54,7 +84,7 @@ pub fn convert(opts: Convert) -> Result<()> {
 
     let input_format = match opts.from {
         Some(format) => format,
-        None => guess_file_format(input_path).ok_or_else(|| {
+        None => detect_file_format(input_path)?.ok_or_else(|| {
             format_err!("cannot guess format of input '{}'", input_path.display())
         })?,
     };
@@ -64,97 +94,235 @@ pub fn convert(opts: Convert) -> Result<()> {
             format_err!("cannot guess format of output '{}'", output_path.display())
         })?,
     };
+    let split = frame_split(&opts);
+    split.check_format(input_format)?;
+    let selector = frame_selector(&opts);
 
     use FileFormat as F;
 
+    ensure!(
+        opts.output_template.is_none()
+            || matches!(
+                input_format,
+                F::VelodynePcap | F::HesaiPcap | F::OusterPcap | F::LivoxLvx
+            ),
+        "--output-template is only supported for pcap and lvx inputs"
+    );
+
     match (input_format, output_format) {
-        (F::LibpclPcd, F::NewslabPcd) => {
-            libpcl_pcd_to_newslab_pcd(input_path, output_path, tf)?;
+        (F::NewslabV2Pcd, F::NewslabPcd) => {
+            bail!(
+                "converting from pcd.newslab-v2 to pcd.newslab is not supported, \
+                 since the 32-bit nanosecond timestamps of pcd.newslab would lose the time"
+            );
+        }
+        (input_format, output_format) if input_format == output_format && tf.is_none() => {
+            match (opts.start, opts.end) {
+                (StartFrame::Forward(1), EndFrame::Backward(1)) => {}
+                _ => {
+                    bail!("--start and --end are not supported ");
+                }
+            }
+            ensure!(
+                selector.is_default(),
+                "--start-time, --end-time and --step are not supported"
+            );
+
+            // Simply copy the file
+            fs::copy(input_path, output_path)?;
+        }
+        (F::VelodynePcap, F::VelodynePcap)
+        | (F::OusterPcap, F::OusterPcap)
+        | (F::HesaiPcap, F::HesaiPcap)
+        | (F::LivoxLvx, F::LivoxLvx)
+        | (F::RawBin, F::RawBin) => {
+            bail!("--transform and --transform-file are not supported ");
         }
-        (F::NewslabPcd, F::LibpclPcd) => {
-            newslab_pcd_to_libpcl_pcd(input_path, output_path, tf)?;
+        (_, output_format @ (F::VelodynePcap | F::OusterPcap | F::HesaiPcap | F::LivoxLvx)) => {
+            bail!("converting to {output_format} is not supported");
+        }
+        (F::LibpclPcd, F::LibpclPcd) => {
+            libpcl_pcd_to_libpcl_pcd(input_path, output_path, tf)?;
         }
-        (F::VelodynePcap, F::LibpclPcd) => {
-            let velodyne_model = opts
-                .velodyne_model
-                .ok_or_else(|| format_err!("--velodyne-mode must be set"))?;
-            let velodyne_return_mode = opts
-                .velodyne_return_mode
-                .ok_or_else(|| format_err!("--velodyne-return-mode must be set"))?;
-
-            velodyne_pcap_to_libpcl_pcd(
+        (input_format @ (F::VelodynePcap | F::HesaiPcap), F::RawBin) => {
+            let (lidar, mode) = resolve_spinning_lidar(&opts, input_format)?;
+
+            spinning_pcap_to_raw_bin(
                 input_path,
+                &packet_filter(&opts),
                 output_path,
-                velodyne_model,
-                velodyne_return_mode,
+                opts.output_template.as_ref(),
+                &lidar,
+                mode,
+                &split,
                 opts.start,
                 opts.end,
+                &selector,
                 tf,
+                num_jobs(&opts),
             )?;
         }
-        (F::VelodynePcap, F::NewslabPcd) => {
-            bail!("converting from pcap.velodyne file to pcd.newslab is not supported");
+        (input_format @ (F::VelodynePcap | F::HesaiPcap), F::XyzirtPcd) => {
+            // The time is relative to the frame start rather than
+            // taken from the point timestamp.
+            let (lidar, mode) = resolve_spinning_lidar(&opts, input_format)?;
+
+            spinning_pcap_to_typed_pcd(
+                input_path,
+                &packet_filter(&opts),
+                output_path,
+                opts.output_template.as_ref(),
+                &lidar,
+                mode,
+                &split,
+                opts.start,
+                opts.end,
+                &selector,
+                tf,
+                num_jobs(&opts),
+                xyzirt_point_from_velodyne,
+            )?;
         }
-        (F::LibpclPcd | F::NewslabPcd, F::VelodynePcap) => {
-            bail!("converting to pcap.velodyne is not supported");
+        (F::OusterPcap, F::RawBin) => {
+            let metadata = resolve_ouster_metadata(input_path, opts.ouster_metadata.as_deref())?;
+
+            ouster_pcap_to_raw_bin(
+                input_path,
+                &packet_filter(&opts),
+                output_path,
+                opts.output_template.as_ref(),
+                &metadata,
+                opts.start,
+                opts.end,
+                &selector,
+                tf,
+                num_jobs(&opts),
+            )?;
         }
-        (F::LibpclPcd | F::NewslabPcd, F::RawBin) => {
-            if is_file(input_path)? {
-                pcd_file_raw_bin_file(input_path, output_path, tf)?;
-            } else {
-                pcd_dir_raw_bin_dir(input_path, output_path, tf)?;
-            }
+        (F::OusterPcap, F::XyzirtPcd) => {
+            let metadata = resolve_ouster_metadata(input_path, opts.ouster_metadata.as_deref())?;
+
+            ouster_pcap_to_typed_pcd(
+                input_path,
+                &packet_filter(&opts),
+                output_path,
+                opts.output_template.as_ref(),
+                &metadata,
+                opts.start,
+                opts.end,
+                &selector,
+                tf,
+                num_jobs(&opts),
+                |sample, frame_time| XyzirtPoint {
+                    time: frame_time.as_secs_f32(),
+                    ..XyzirtPoint::from_pcd_point(&sample, sample.xyz)
+                },
+            )?;
         }
-        (F::VelodynePcap, F::RawBin) => {
-            let velodyne_model = opts
-                .velodyne_model
-                .ok_or_else(|| format_err!("--velodyne-mode must be set"))?;
-            let velodyne_return_mode = opts
-                .velodyne_return_mode
-                .ok_or_else(|| format_err!("--velodyne-return-mode must be set"))?;
-
-            velodyne_pcap_to_raw_bin(
+        (F::OusterPcap, F::OusterPcd) => {
+            // The t field is relative to the frame start rather than
+            // taken from the column timestamp.
+            let metadata = resolve_ouster_metadata(input_path, opts.ouster_metadata.as_deref())?;
+
+            ouster_pcap_to_typed_pcd(
                 input_path,
+                &packet_filter(&opts),
                 output_path,
-                velodyne_model,
-                velodyne_return_mode,
+                opts.output_template.as_ref(),
+                &metadata,
                 opts.start,
                 opts.end,
+                &selector,
                 tf,
+                num_jobs(&opts),
+                |sample, frame_time| OusterPoint {
+                    t: frame_time.as_nanos() as u32,
+                    ..OusterPoint::from_pcd_point(&sample, sample.xyz)
+                },
             )?;
         }
-        (F::RawBin, F::LibpclPcd) => {
+        (F::LivoxLvx, F::RawBin) => {
+            livox_lvx_to_raw_bin(
+                input_path,
+                output_path,
+                opts.output_template.as_ref(),
+                opts.start,
+                opts.end,
+                &selector,
+                tf,
+                num_jobs(&opts),
+            )?;
+        }
+        (F::LivoxLvx, F::XyzirtPcd) => {
+            livox_lvx_to_typed_pcd(
+                input_path,
+                output_path,
+                opts.output_template.as_ref(),
+                opts.start,
+                opts.end,
+                &selector,
+                tf,
+                num_jobs(&opts),
+                |sample, frame_time| XyzirtPoint {
+                    time: frame_time.as_secs_f32(),
+                    ..XyzirtPoint::from_pcd_point(&sample, sample.xyz)
+                },
+            )?;
+        }
+        (_, F::RawBin) => {
             if is_file(input_path)? {
-                bin_file_to_libpcl_pcd_file(input_path, output_path, tf)?;
+                pcd_file_raw_bin_file(input_path, output_path, tf)?;
             } else {
-                bin_dir_to_libpcl_pcd_dir(input_path, output_path, tf)?;
+                pcd_dir_raw_bin_dir(input_path, output_path, tf)?;
             }
         }
-        (F::RawBin, F::NewslabPcd) => {
-            bail!("conversion from raw.bin to pcd.newslab is not supported");
+        (_, F::LibpclPcd) => {
+            convert_to_schema::<LibpclPoint>(&opts, input_format, tf)?;
         }
-        (F::RawBin, F::VelodynePcap) => {
-            bail!("converting to pcap.velodyne is not supported");
+        (_, F::LibpclExtPcd) => {
+            convert_to_schema::<LibpclExtPoint>(&opts, input_format, tf)?;
         }
-        (F::LibpclPcd, F::LibpclPcd) => {
-            libpcl_pcd_to_libpcl_pcd(input_path, output_path, tf)?;
+        (_, F::LibpclXyzPcd) => {
+            convert_to_schema::<LibpclXyzPoint>(&opts, input_format, tf)?;
         }
-        (F::NewslabPcd, F::NewslabPcd)
-        | (F::VelodynePcap, F::VelodynePcap)
-        | (F::RawBin, F::RawBin) => {
-            match (opts.start, opts.end) {
-                (StartFrame::Forward(1), EndFrame::Backward(1)) => {}
-                _ => {
-                    bail!("--start and --end are not supported ");
-                }
-            }
-
-            if tf.is_some() {
-                bail!("--transform and --transform-file are not supported ");
+        (_, F::LibpclXyziPcd) => {
+            convert_to_schema::<LibpclXyziPoint>(&opts, input_format, tf)?;
+        }
+        (_, F::LibpclXyzrgbaPcd) => {
+            convert_to_schema::<LibpclXyzrgbaPoint>(&opts, input_format, tf)?;
+        }
+        (_, F::LibpclXyziNormalPcd) => {
+            convert_to_schema::<LibpclXyziNormalPoint>(&opts, input_format, tf)?;
+        }
+        (_, F::LibpclNormalPcd) => {
+            convert_to_schema::<LibpclNormalPoint>(&opts, input_format, tf)?;
+        }
+        (_, F::LibpclXyzlPcd) => {
+            convert_to_schema::<LibpclXyzlPoint>(&opts, input_format, tf)?;
+        }
+        (F::RawBin, F::XyzirtPcd) => {
+            convert_to_schema::<XyzirtPoint>(&opts, input_format, tf)?;
+        }
+        (_, F::XyzirtPcd) => {
+            dyn_pcd_to_xyzirt_pcd(input_path, output_path, tf)?;
+        }
+        (_, F::NewslabPcd) => {
+            convert_to_schema::<NewslabV1Point>(&opts, input_format, tf)?;
+        }
+        (_, F::NewslabV2Pcd) => {
+            if input_format == F::NewslabPcd {
+                eprintln!(
+                    "Warning: the timestamps of pcd.newslab wrap around every 4.29 seconds, \
+                     and are kept as relative times rather than the Unix time"
+                );
             }
-
-            // Simply copy the file
-            fs::copy(input_path, output_path)?;
+            convert_to_schema::<NewslabV2Point>(&opts, input_format, tf)?;
+        }
+        (_, F::OusterPcd) => {
+            convert_to_schema::<OusterPoint>(&opts, input_format, tf)?;
+        }
+        (_, F::LivoxPcd) => {
+            convert_to_schema::<LivoxPoint>(&opts, input_format, tf)?;
         }
     }
 
@@ -175,8 +343,7 @@ where
         fs::copy(input_path, output_path)?;
         return Ok(());
     };
-    let input_path = input_path.as_ref();
-    let mut reader = create_pcd_reader(input_path)?;
+    let (meta, points) = create_dyn_point_reader(input_path)?;
     let pcd_rs::PcdMeta {
         width,
         height,
@@ -184,7 +351,7 @@ where
         data,
         ref field_defs,
         ..
-    } = *reader.meta();
+    } = meta;
 
     let mut writer = pcd_rs::WriterInit {
         width,
@@ -195,73 +362,43 @@ where
     }
     .create(output_path)?;
 
-    let find_field = |name| {
-        let field = field_defs
-            .fields
-            .iter()
-            .enumerate()
-            .find(|(_, field)| field.name == name);
-
-        match field {
-            Some((idx, _)) => Ok(idx),
-            None => bail!(r#""{name}" field is required but is not found"#),
-        }
-    };
-
-    let x_idx = find_field("x")?;
-    let y_idx = find_field("y")?;
-    let z_idx = find_field("z")?;
-
-    let set_value = |field: &mut pcd_rs::Field, value: f32| {
-        match field {
-            pcd_rs::Field::F32(vec) => vec[0] = value,
-            pcd_rs::Field::F64(vec) => vec[0] = value as f64,
-            _ => bail!("transforming non-floating point coordinates is not supported"),
-        }
-        Ok(())
-    };
+    let tf: na::Isometry3<f64> = tf.cast();
 
-    reader.try_for_each(|point| -> Result<_> {
+    for point in points {
         let mut point = point?;
-        let Some([x, y, z]) = point.to_xyz::<f32>() else {
-            bail!(
-                "the file {} misses one of x, y or z field",
-                input_path.display()
-            );
-        };
-
-        let [x, y, z] = transform_point([x, y, z], Some(tf));
-        set_value(&mut point.0[x_idx], x)?;
-        set_value(&mut point.0[y_idx], y)?;
-        set_value(&mut point.0[z_idx], z)?;
-
-        writer.push(&point)?;
-        Ok(())
-    })?;
+        let xyz = transform_point([point.x(), point.y(), point.z()], Some(tf));
+        point.set_xyz(xyz)?;
+        writer.push(point.record())?;
+    }
 
     writer.finish()?;
 
     Ok(())
 }
 
-fn libpcl_pcd_to_newslab_pcd<PI, PO>(
+/// Converts a file of any schema to a known schema. The `map_point`
+/// receives the input point along with its transformed xyz
+/// coordinates.
+fn dyn_pcd_to_typed_pcd<T, PI, PO, M>(
     input_path: PI,
     output_path: PO,
     tf: Option<na::Isometry3<f32>>,
+    map_point: M,
 ) -> Result<()>
 where
+    T: PcdSerialize,
     PI: AsRef<Path>,
     PO: AsRef<Path>,
+    M: Fn(DynPoint, [f64; 3]) -> T,
 {
-    let input_path = input_path.as_ref();
-    let mut reader = create_pcd_reader(input_path)?;
+    let (meta, points) = create_dyn_point_reader(input_path)?;
     let pcd_rs::PcdMeta {
         width,
         height,
         ref viewpoint,
         data,
         ..
-    } = *reader.meta();
+    } = meta;
 
     let mut writer = pcd_rs::WriterInit {
         width,
@@ -272,85 +409,24 @@ where
     }
     .create(output_path)?;
 
-    reader.try_for_each(|point| -> Result<_> {
-        let Some([x, y, z]) = point?.to_xyz::<f32>() else {
-            bail!(
-                "the file {} misses one of x, y or z field",
-                input_path.display()
-            );
-        };
-
-        let [x, y, z] = transform_point([x, y, z], tf);
-
-        let x = x as f64;
-        let y = y as f64;
-        let z = z as f64;
-        let distance = (x.powi(2) + y.powi(2) + z.powi(2)).sqrt();
-
-        let point = if abs_diff_eq!(distance, 0.0) {
-            NewslabV1Point {
-                x,
-                y,
-                z,
-                distance,
-                azimuthal_angle: 0.0,
-                vertical_angle: 0.0,
-                intensity: 0.0,
-                laser_id: 0,
-                timestamp_ns: 0,
-            }
-        } else {
-            let polar_angle = if abs_diff_eq!(z, 0.0) {
-                FRAC_PI_2
-            } else {
-                let planar_dist = (x.powi(2) + y.powi(2)).sqrt();
-                planar_dist.atan2(z) + if z > 0.0 { 0.0 } else { PI }
-            };
-            let azimuthal_angle = match (abs_diff_eq!(x, 0.0), abs_diff_eq!(y, 0.0)) {
-                (true, true) => 0.0,
-                (true, false) => {
-                    if y > 0.0 {
-                        FRAC_PI_2
-                    } else {
-                        -FRAC_PI_2
-                    }
-                }
-                (false, _) => {
-                    y.atan2(x)
-                        + if x > 0.0 {
-                            0.0
-                        } else if y >= 0.0 {
-                            PI
-                        } else {
-                            -PI
-                        }
-                }
-            };
-            let vertical_angle = FRAC_PI_2 - polar_angle;
-
-            NewslabV1Point {
-                x,
-                y,
-                z,
-                distance,
-                azimuthal_angle,
-                vertical_angle,
-                intensity: 0.0,
-                laser_id: 0,
-                timestamp_ns: 0,
-            }
-        };
+    let tf: Option<na::Isometry3<f64>> = tf.map(|tf| tf.cast());
 
+    for point in points {
+        let point = point?;
+        let xyz = transform_point([point.x(), point.y(), point.z()], tf);
+        let point = map_point(point, xyz);
         writer.push(&point)?;
-        Ok(())
-    })?;
+    }
 
     writer.finish()?;
 
     Ok(())
 }
 
-fn newslab_pcd_to_libpcl_pcd<PI, PO>(
+/// Converts a PCD file of any schema to a XYZIRT PCD file. The time of
+/// each point is relative to the first point that has a timestamp, so
+/// that epoch timestamps are not truncated to `f32`.
+fn dyn_pcd_to_xyzirt_pcd<PI, PO>(
     input_path: PI,
     output_path: PO,
     tf: Option<na::Isometry3<f32>>,
@@ -359,387 +435,1129 @@ where
     PI: AsRef<Path>,
     PO: AsRef<Path>,
 {
-    let mut reader = pcd_rs::Reader::open(input_path)?;
-    let pcd_rs::PcdMeta {
-        width,
-        height,
-        ref viewpoint,
-        data,
-        ..
-    } = *reader.meta();
+    let start_time = Cell::new(None);
+
+    dyn_pcd_to_typed_pcd(input_path, output_path, tf, |point, xyz| {
+        let time = point.timestamp().map(|time| {
+            let start = start_time.get().unwrap_or(time);
+            start_time.set(Some(start));
+            (time - start) as f32
+        });
+
+        XyzirtPoint {
+            time: time.unwrap_or(0.0),
+            ..XyzirtPoint::from_pcd_point(&point, xyz)
+        }
+    })
+}
 
-    let mut writer = pcd_rs::WriterInit {
-        width,
-        height,
-        viewpoint: viewpoint.clone(),
-        data_kind: data,
-        schema: None,
+/// Converts the packets of each LiDAR in a pcap file to a subdirectory
+/// of the output directory named after the source. The model and
+/// return mode are resolved per source.
+fn convert_each_source(opts: Convert) -> Result<()> {
+    if let Some(format) = opts.from {
+        ensure!(
+            matches!(format, FileFormat::VelodynePcap | FileFormat::HesaiPcap),
+            "--split-sources does not support the {format} format"
+        );
     }
-    .create(output_path)?;
+    let output_format = match opts.to {
+        Some(format) => format,
+        None => guess_file_format(&opts.output).ok_or_else(|| {
+            format_err!("cannot guess format of output '{}'", opts.output.display())
+        })?,
+    };
+
+    let filter = packet_filter(&opts);
+    let sources = scan_frame_sources(&opts.input, &filter)?;
+    let source_filters: Vec<_> = sources
+        .iter()
+        .map(|source| PacketFilter {
+            source_ip: Some(source.ip),
+            port: Some(source.port),
+            ..filter.clone()
+        })
+        .collect();
+    warn_unmatched_source_values(&opts.source_models, &source_filters, "--source-model");
+    warn_unmatched_source_values(
+        &opts.source_return_modes,
+        &source_filters,
+        "--source-return-mode",
+    );
+    fs::create_dir(&opts.output)?;
+
+    for (source, filter) in sources.iter().zip(&source_filters) {
+        let input_format = match source.lidar {
+            Some(SourceLidar::Hesai { .. }) => FileFormat::HesaiPcap,
+            _ => FileFormat::VelodynePcap,
+        };
+        eprintln!("Converting the packets from {}:{}", source.ip, source.port);
+
+        // The per-source values are resolved here, as the sources
+        // without one are converted with the global values.
+        convert(Convert {
+            from: Some(input_format),
+            to: Some(output_format),
+            output: opts.output.join(source.dir_name()),
+            source_ip: Some(source.ip),
+            port: Some(source.port),
+            split_sources: false,
+            velodyne_model: find_source_value(&opts.source_models, filter).or(opts.velodyne_model),
+            velodyne_return_mode: find_source_value(&opts.source_return_modes, filter)
+                .or(opts.velodyne_return_mode),
+            source_models: vec![],
+            source_return_modes: vec![],
+            ..opts.clone()
+        })?;
+    }
+
+    Ok(())
+}
+
+/// Converts a file of any supported format to a PCD file of schema
+/// `T`. PCD files of any schema are read through [DynPoint].
+fn convert_to_schema<T>(
+    opts: &Convert,
+    input_format: FileFormat,
+    tf: Option<na::Isometry3<f32>>,
+) -> Result<()>
+where
+    T: FromPcdPoint + PcdSerialize,
+{
+    let input_path = &opts.input;
+    let output_path = &opts.output;
+
+    use FileFormat as F;
+
+    match input_format {
+        F::VelodynePcap | F::HesaiPcap => {
+            let (lidar, mode) = resolve_spinning_lidar(opts, input_format)?;
+
+            spinning_pcap_to_typed_pcd(
+                input_path,
+                &packet_filter(opts),
+                output_path,
+                opts.output_template.as_ref(),
+                &lidar,
+                mode,
+                &frame_split(opts),
+                opts.start,
+                opts.end,
+                &frame_selector(opts),
+                tf,
+                num_jobs(opts),
+                |sample| T::from_pcd_point(&sample, sample.xyz.map(|val| val as f64)),
+            )?;
+        }
+        F::OusterPcap => {
+            let metadata = resolve_ouster_metadata(input_path, opts.ouster_metadata.as_deref())?;
+
+            ouster_pcap_to_typed_pcd(
+                input_path,
+                &packet_filter(opts),
+                output_path,
+                opts.output_template.as_ref(),
+                &metadata,
+                opts.start,
+                opts.end,
+                &frame_selector(opts),
+                tf,
+                num_jobs(opts),
+                |sample, _| T::from_pcd_point(&sample, sample.xyz),
+            )?;
+        }
+        F::LivoxLvx => {
+            livox_lvx_to_typed_pcd(
+                input_path,
+                output_path,
+                opts.output_template.as_ref(),
+                opts.start,
+                opts.end,
+                &frame_selector(opts),
+                tf,
+                num_jobs(opts),
+                |sample, _| T::from_pcd_point(&sample, sample.xyz),
+            )?;
+        }
+        F::RawBin => {
+            let map_point = |point: BinPoint| {
+                let BinPoint { x, y, z, .. } = point;
+                T::from_pcd_point(&point, [x as f64, y as f64, z as f64])
+            };
+
+            if is_file(input_path)? {
+                bin_file_to_pcd_file(input_path, output_path, tf, map_point)?;
+            } else {
+                bin_dir_to_pcd_dir(input_path, output_path, tf, map_point)?;
+            }
+        }
+        _ => {
+            dyn_pcd_to_typed_pcd(input_path, output_path, tf, |point, xyz| {
+                T::from_pcd_point(&point, xyz)
+            })?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Gives the filter of the packets to be read from a pcap file.
+fn packet_filter(opts: &Convert) -> PacketFilter {
+    PacketFilter {
+        interface: opts.interface.clone(),
+        source_ip: opts.source_ip,
+        port: opts.port,
+    }
+}
+
+/// Gives how the firings of a Velodyne or Hesai pcap file are split
+/// into frames.
+fn frame_split(opts: &Convert) -> FrameSplit {
+    FrameSplit::from_opts(
+        opts.cut_angle,
+        opts.frame_period,
+        opts.frame_packets,
+        opts.drop_partial_frames,
+    )
+}
+
+/// Gives how the frames are selected by time and by stride.
+fn frame_selector(opts: &Convert) -> FrameSelector {
+    FrameSelector::from_opts(opts.start_time, opts.end_time, opts.step)
+}
+
+/// Gives the number of frames converted in parallel.
+fn num_jobs(opts: &Convert) -> usize {
+    opts.jobs
+        .or_else(|| thread::available_parallelism().ok())
+        .map_or(1, NonZeroUsize::get)
+}
+
+/// Determines the LiDAR of a Velodyne or Hesai pcap file from the
+/// command line options and the packets.
+fn resolve_spinning_lidar(
+    opts: &Convert,
+    input_format: FileFormat,
+) -> Result<(SpinningLidar, VelodyneReturnMode)> {
+    let filter = packet_filter(opts);
+
+    match input_format {
+        FileFormat::HesaiPcap => resolve_hesai_lidar(
+            &opts.input,
+            &filter,
+            opts.hesai_model,
+            opts.hesai_angles.as_deref(),
+        ),
+        _ => {
+            // The values given to the selected source take precedence.
+            let model = selected_source_value(&opts.source_models, &filter, "--source-model")?
+                .or(opts.velodyne_model);
+            let mode =
+                selected_source_value(&opts.source_return_modes, &filter, "--source-return-mode")?
+                    .or(opts.velodyne_return_mode);
+
+            resolve_velodyne_lidar(
+                &opts.input,
+                &filter,
+                model,
+                mode,
+                opts.velodyne_calibration.as_deref(),
+            )
+        }
+    }
+}
+
+/// Finds the per-source value given to the source selected by the
+/// filter. It fails if values are given but none selects the source,
+/// since they would be ignored.
+fn selected_source_value<T>(
+    values: &[(SourceSelector, T)],
+    filter: &PacketFilter,
+    option: &str,
+) -> Result<Option<T>>
+where
+    T: Clone,
+{
+    if values.is_empty() {
+        return Ok(None);
+    }
+    let Some(value) = find_source_value(values, filter) else {
+        bail!(
+            "none of the sources given to {option} is selected. \
+             Please select one with --source-ip and --port, or convert each with --split-sources"
+        );
+    };
+    Ok(Some(value))
+}
+
+/// The attributes of a single return measured by a laser of a
+/// spinning LiDAR.
+#[derive(Debug, Clone, Copy)]
+struct VelodyneSample {
+    /// The transformed Cartesian coordinates.
+    xyz: [f32; 3],
+    /// The distance and angles are kept in the sensor frame as they
+    /// were measured, while the transformation only applies to xyz.
+    distance: f64,
+    azimuthal_angle: f64,
+    vertical_angle: f64,
+    intensity: f64,
+    laser_id: u32,
+    /// The time since the top of the hour.
+    toh: Duration,
+    /// The time since the Unix epoch.
+    timestamp: Duration,
+    /// The time since the first firing of the frame.
+    frame_time: Duration,
+}
+
+impl PcdPoint for VelodyneSample {
+    fn x(&self) -> f64 {
+        self.xyz[0] as f64
+    }
+
+    fn y(&self) -> f64 {
+        self.xyz[1] as f64
+    }
+
+    fn z(&self) -> f64 {
+        self.xyz[2] as f64
+    }
+
+    fn distance(&self) -> f64 {
+        self.distance
+    }
+
+    fn azimuthal_angle(&self) -> f64 {
+        self.azimuthal_angle
+    }
+
+    fn vertical_angle(&self) -> f64 {
+        self.vertical_angle
+    }
+
+    fn polar_angle(&self) -> f64 {
+        FRAC_PI_2 - self.vertical_angle
+    }
+
+    fn has_measured_spherical(&self) -> bool {
+        true
+    }
+
+    fn intensity(&self) -> Option<f64> {
+        Some(self.intensity)
+    }
+
+    fn laser_id(&self) -> Option<u32> {
+        Some(self.laser_id)
+    }
+
+    fn timestamp_ns(&self) -> Option<u32> {
+        Some(self.toh.as_nanos() as u32)
+    }
+
+    fn timestamp(&self) -> Option<f64> {
+        Some(self.timestamp.as_secs_f64())
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn spinning_pcap_to_typed_pcd<I, O, T, M>(
+    input_file: I,
+    filter: &PacketFilter,
+    output_dir: O,
+    template: Option<&OutputTemplate>,
+    lidar: &SpinningLidar,
+    mode: VelodyneReturnMode,
+    split: &FrameSplit,
+    start: StartFrame,
+    end: EndFrame,
+    selector: &FrameSelector,
+    tf: Option<na::Isometry3<f32>>,
+    jobs: usize,
+    map_sample: M,
+) -> Result<()>
+where
+    I: AsRef<Path>,
+    O: AsRef<Path>,
+    T: PcdSerialize,
+    M: Fn(VelodyneSample) -> T + Sync,
+{
+    // closures
+    let map_measurement =
+        |clock: &TohClock, point: &VelodynePoint, measurement: VelodyneMeasurement| {
+            let VelodynePoint {
+                laser_id,
+                toh,
+                azimuth,
+                elevation,
+                ..
+            } = *point;
+            let VelodyneMeasurement {
+                distance,
+                intensity,
+                xyz: [x, y, z],
+            } = measurement;
+
+            let xyz = transform_point([x as f32, y as f32, z as f32], tf);
+
+            map_sample(VelodyneSample {
+                xyz,
+                distance,
+                azimuthal_angle: velodyne_azimuth_to_azimuthal_angle(azimuth),
+                vertical_angle: elevation.as_radians(),
+                intensity: intensity as f64,
+                laser_id,
+                toh,
+                timestamp: clock.to_absolute(toh),
+                frame_time: clock.to_absolute(toh).saturating_sub(clock.current()),
+            })
+        };
+
+    let frames = SpinningFrames {
+        input_file: input_file.as_ref(),
+        filter,
+        lidar,
+        mode,
+        split,
+        start,
+        end,
+        selector,
+    };
+    let output = SpinningOutput::new(output_dir.as_ref(), template, &frames, "pcd")?;
+
+    frames.convert(&output, jobs, |files, frame, clock| {
+        let VelodyneFrame {
+            width,
+            height,
+            ref points,
+            ..
+        } = frame;
+
+        if let Some(file) = &files.strongest {
+            let points = points
+                .iter()
+                .filter_map(|point| Some(map_measurement(clock, point, point.strongest?)));
+            create_pcd_file_single(points, file, width, height)?;
+        }
+        if let Some(file) = &files.last {
+            let points = points
+                .iter()
+                .filter_map(|point| Some(map_measurement(clock, point, point.last?)));
+            create_pcd_file_single(points, file, width, height)?;
+        }
+
+        Ok(())
+    })
+}
+
+#[allow(clippy::too_many_arguments)]
+fn spinning_pcap_to_raw_bin<I, O>(
+    input_file: I,
+    filter: &PacketFilter,
+    output_dir: O,
+    template: Option<&OutputTemplate>,
+    lidar: &SpinningLidar,
+    mode: VelodyneReturnMode,
+    split: &FrameSplit,
+    start: StartFrame,
+    end: EndFrame,
+    selector: &FrameSelector,
+    tf: Option<na::Isometry3<f32>>,
+    jobs: usize,
+) -> Result<()>
+where
+    I: AsRef<Path>,
+    O: AsRef<Path>,
+{
+    // closures
+    let map_measurement = |measurement: VelodyneMeasurement| {
+        let [x, y, z] = measurement.xyz;
+        let [x, y, z] = transform_point([x as f32, y as f32, z as f32], tf);
+        [x, y, z, measurement.intensity as f32]
+    };
+
+    let frames = SpinningFrames {
+        input_file: input_file.as_ref(),
+        filter,
+        lidar,
+        mode,
+        split,
+        start,
+        end,
+        selector,
+    };
+    let output = SpinningOutput::new(output_dir.as_ref(), template, &frames, "bin")?;
+
+    frames.convert(&output, jobs, |files, frame, _clock| {
+        if let Some(file) = &files.strongest {
+            let points = frame
+                .points
+                .iter()
+                .filter_map(|point| Some(map_measurement(point.strongest?)));
+            create_raw_bin_file_single(points, file)?;
+        }
+        if let Some(file) = &files.last {
+            let points = frame
+                .points
+                .iter()
+                .filter_map(|point| Some(map_measurement(point.last?)));
+            create_raw_bin_file_single(points, file)?;
+        }
+
+        Ok(())
+    })
+}
+
+/// The frames of a Velodyne or Hesai pcap file selected by --start and
+/// --end.
+struct SpinningFrames<'a> {
+    input_file: &'a Path,
+    filter: &'a PacketFilter,
+    lidar: &'a SpinningLidar,
+    mode: VelodyneReturnMode,
+    split: &'a FrameSplit,
+    start: StartFrame,
+    end: EndFrame,
+    selector: &'a FrameSelector,
+}
+
+impl SpinningFrames<'_> {
+    /// Creates the output directory, and passes each selected frame to
+    /// `convert_frame` along with its files and the clock advanced to
+    /// the frame start. The frame timestamps are recorded in
+    /// `frames.csv`.
+    ///
+    /// The frames are located with the frame index if it is up to
+    /// date. Otherwise, frames up to the last one are converted in a
+    /// single pass that indexes the capture along the way, while other
+    /// ranges take indexing the capture beforehand.
+    fn convert<F>(&self, output: &SpinningOutput, jobs: usize, convert_frame: F) -> Result<()>
+    where
+        F: Fn(&FrameFiles, VelodyneFrame, &TohClock) -> Result<()> + Sync,
+    {
+        let Self {
+            input_file,
+            filter,
+            lidar,
+            mode,
+            split,
+            start,
+            end,
+            selector,
+        } = *self;
+
+        let to_last = match (start, end) {
+            (StartFrame::Forward(start), EndFrame::Backward(1)) => Some(start - 1),
+            _ => None,
+        };
+        let indexed = match (
+            load_frame_index(input_file, filter, lidar, mode, split)?,
+            to_last,
+        ) {
+            (Some(indexed), _) => indexed,
+            (None, Some(start)) => {
+                return self.convert_unindexed(output, jobs, start, convert_frame);
+            }
+            (None, None) => frame_index(input_file, filter, lidar, mode, split)?,
+        };
+        let (start, count) = resolve_frame_range(indexed.frames.len(), start, end)?;
+        let selected = selector.select_entries(&indexed.frames, start..start + count);
+
+        let mut time_log = output.create_dir(indexed.time_source)?;
+
+        // Reading starts at the first selected frame, located by the
+        // index.
+        let frames = indexed_frame_iter(
+            input_file, filter, lidar, mode, split, &indexed, selected,
+        )?
+        .map(|frame| -> Result<_> {
+            let (index, frame, clock) = frame?;
+            let files = output.create_files(&mut time_log, index, &frame, &clock)?;
+            Ok((files, frame, clock))
+        });
+        convert_frames_in_parallel(jobs, frames, |(files, frame, clock)| {
+            convert_frame(&files, frame, &clock)
+        })?;
+
+        time_log.finish()
+    }
+
+    /// Converts the selected frames from `start` to the last one while
+    /// indexing the capture from the start. The output directory is
+    /// created once the first selected frame is read, so that nothing
+    /// is written if `start` is beyond the last frame.
+    fn convert_unindexed<F>(
+        &self,
+        output: &SpinningOutput,
+        jobs: usize,
+        start: usize,
+        convert_frame: F,
+    ) -> Result<()>
+    where
+        F: Fn(&FrameFiles, VelodyneFrame, &TohClock) -> Result<()> + Sync,
+    {
+        let Self {
+            input_file,
+            filter,
+            lidar,
+            mode,
+            split,
+            selector,
+            ..
+        } = *self;
+
+        let mut builder = FrameIndexBuilder::new(input_file, filter, lidar, mode, split)?;
+        let time_source = builder.time_source();
+        let mut time_log = None;
+        let mut sampler = selector.sampler(start..usize::MAX, None);
+        let frames = lidar.frame_iter_from_file(input_file, filter, mode.0, split)?;
+
+        // Every frame is read to complete the index, even after the
+        // last selected one.
+        let frames = frames
+            .enumerate()
+            .map(|(index, frame)| -> Result<_> {
+                let frame = frame?;
+                builder.push(&frame);
+                let clock = builder.clock().clone();
+                if sampler.select(index, clock.current()) != Selection::Keep {
+                    return Ok(None);
+                }
+                let time_log = match &mut time_log {
+                    Some(time_log) => time_log,
+                    time_log => time_log.insert(output.create_dir(time_source)?),
+                };
+                let files = output.create_files(time_log, index, &frame, &clock)?;
+                Ok(Some((files, frame, clock)))
+            })
+            .filter_map(Result::transpose);
+        convert_frames_in_parallel(jobs, frames, |(files, frame, clock)| {
+            convert_frame(&files, frame, &clock)
+        })?;
+
+        let indexed = builder.finish();
+        store_frame_index(input_file, &indexed);
+        ensure!(
+            start <= indexed.frames.len(),
+            "--start position is out of bound"
+        );
+
+        match time_log {
+            Some(time_log) => time_log.finish(),
+            None => output.create_dir(time_source)?.finish(),
+        }
+    }
+}
+
+/// Names the files of the frames of a Velodyne or Hesai pcap file
+/// after the output template, with a file per converted return.
+struct SpinningOutput<'a> {
+    dir: &'a Path,
+    template: OutputTemplate,
+    mode: VelodyneReturnMode,
+    extension: &'a str,
+    /// The source IP address of the LiDAR if the template names it.
+    sensor: Option<String>,
+}
+
+impl<'a> SpinningOutput<'a> {
+    fn new(
+        dir: &'a Path,
+        template: Option<&OutputTemplate>,
+        frames: &SpinningFrames,
+        extension: &'a str,
+    ) -> Result<Self> {
+        let template = template
+            .cloned()
+            .unwrap_or_else(|| OutputTemplate::default_for(true));
+        let num_modes = match frames.mode.0 {
+            ReturnMode::Dual => 2,
+            _ => 1,
+        };
+        template.check(Some(num_modes))?;
+
+        let sensor = if template.uses(Placeholder::Sensor) {
+            Some(spinning_sensor_id(frames.input_file, frames.filter)?)
+        } else {
+            None
+        };
+
+        Ok(Self {
+            dir,
+            template,
+            mode: frames.mode,
+            extension,
+            sensor,
+        })
+    }
+
+    /// Creates the output directory and the time log in it.
+    fn create_dir(&self, time_source: TimeSource) -> Result<FrameTimeLog> {
+        fs::create_dir(self.dir)?;
+        FrameTimeLog::create(self.dir, time_source)
+    }
+
+    /// Names the files of a frame, creates their directories, and
+    /// records their timestamps in the time log.
+    fn create_files(
+        &self,
+        time_log: &mut FrameTimeLog,
+        index: usize,
+        frame: &VelodyneFrame,
+        clock: &TohClock,
+    ) -> Result<FrameFiles> {
+        use ReturnMode as R;
+
+        let start_time = clock.current();
+        let end_time = frame
+            .last_toh()
+            .map_or(start_time, |toh| clock.to_absolute(toh));
+
+        let mut create_file = |mode: &str| -> Result<PathBuf> {
+            let fields = FrameFields {
+                index,
+                start_time,
+                end_time,
+                mode: Some(mode),
+                sensor: self.sensor.as_deref(),
+            };
+            let file_name = self.template.render(&fields, self.extension);
+            let path = self.dir.join(&file_name);
+            create_parent_dir(&path)?;
+
+            if let Some(toh) = frame.first_toh() {
+                time_log.record(index, &file_name.to_string_lossy(), toh, start_time)?;
+            }
+            Ok(path)
+        };
+
+        let strongest = matches!(self.mode.0, R::Strongest | R::Dual)
+            .then(|| create_file("strongest"))
+            .transpose()?;
+        let last = matches!(self.mode.0, R::Last | R::Dual)
+            .then(|| create_file("last"))
+            .transpose()?;
+
+        Ok(FrameFiles { strongest, last })
+    }
+}
+
+/// The files of a frame, one per converted return.
+struct FrameFiles {
+    strongest: Option<PathBuf>,
+    last: Option<PathBuf>,
+}
 
-    reader.try_for_each(|point| -> Result<_> {
-        let NewslabV1Point { x, y, z, .. } = point?;
+/// Gives the source IP address of the LiDAR in a Velodyne or Hesai
+/// pcap file, which names the sensor in the output files.
+fn spinning_sensor_id(input_file: &Path, filter: &PacketFilter) -> Result<String> {
+    if let Some(ip) = filter.source_ip {
+        return Ok(ip.to_string());
+    }
 
-        let x = x as f32;
-        let y = y as f32;
-        let z = z as f32;
+    let sources = scan_frame_sources(input_file, filter)?;
+    let [source] = sources.as_slice() else {
+        bail!(
+            "{{sensor}} needs a single LiDAR, but the data packets come from {} sources. \
+             Please select one with --source-ip or use --split-sources",
+            sources.len()
+        );
+    };
+    Ok(source.ip.to_string())
+}
 
-        // Transform points
-        let [x, y, z] = transform_point([x, y, z], tf);
+/// Gives the output template of an Ouster or Livox file, whose frames
+/// have no return modes.
+fn frame_template(template: Option<&OutputTemplate>) -> Result<OutputTemplate> {
+    let template = template
+        .cloned()
+        .unwrap_or_else(|| OutputTemplate::default_for(false));
+    template.check(None)?;
+    Ok(template)
+}
 
-        let point = LibpclPoint { x, y, z, rgb: 0 };
+/// Gives the serial number of an Ouster LiDAR, which names the sensor
+/// in the output files.
+fn ouster_sensor_id(metadata: &OusterMetadata) -> Result<String> {
+    metadata.serial_number.clone().ok_or_else(|| {
+        format_err!("{{sensor}} needs the serial number missing in the Ouster metadata")
+    })
+}
 
-        writer.push(&point)?;
-        Ok(())
-    })?;
+/// Gives the serial number of the LiDAR in a Livox file, which names
+/// the sensor in the output files.
+fn livox_sensor_id(input_file: &Path) -> Result<String> {
+    let header = livox_header_from_file(input_file)?;
+    let [device] = header.devices.as_slice() else {
+        bail!(
+            "{{sensor}} needs a single LiDAR, but the file holds {} devices",
+            header.devices.len()
+        );
+    };
+    Ok(device.lidar_sn.clone())
+}
 
-    writer.finish()?;
+/// Gives the time of the latest sample of a frame, or the frame start
+/// if the frame is empty.
+fn frame_end_time<I>(timestamps: I, start_time: Duration) -> Duration
+where
+    I: IntoIterator<Item = Duration>,
+{
+    timestamps
+        .into_iter()
+        .max()
+        .unwrap_or(start_time)
+        .max(start_time)
+}
 
+/// Creates the directory of an output file, which the output template
+/// may place in subdirectories.
+fn create_parent_dir(path: &Path) -> Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
     Ok(())
 }
 
-fn velodyne_pcap_to_libpcl_pcd<I, O>(
+/// Passes the frames to `convert_frame` running on `jobs` threads.
+///
+/// The frames are read on the calling thread, and reading runs ahead
+/// of the conversion by at most `jobs` frames, which bounds the frames
+/// held in memory. The conversion stops at the first error.
+fn convert_frames_in_parallel<I, T, F>(jobs: usize, frames: I, convert_frame: F) -> Result<()>
+where
+    I: Iterator<Item = Result<T>>,
+    T: Send,
+    F: Fn(T) -> Result<()> + Sync,
+{
+    let pool = ThreadPoolBuilder::new().num_threads(jobs).build()?;
+    let (tx, rx) = mpsc::sync_channel(jobs);
+
+    thread::scope(|scope| {
+        let workers = scope
+            .spawn(|| pool.install(|| rx.into_iter().par_bridge().try_for_each(&convert_frame)));
+
+        let mut read = Ok(());
+        for frame in frames {
+            let frame = match frame {
+                Ok(frame) => frame,
+                Err(err) => {
+                    read = Err(err);
+                    break;
+                }
+            };
+
+            // Sending fails if the workers stopped on an error, which
+            // is reported below.
+            if tx.send(frame).is_err() {
+                break;
+            }
+        }
+
+        // Dropping the sender lets the workers finish the sent frames
+        // and stop.
+        drop(tx);
+
+        let converted = match workers.join() {
+            Ok(result) => result,
+            Err(panic) => panic::resume_unwind(panic),
+        };
+        read.and(converted)
+    })
+}
+
+#[allow(clippy::too_many_arguments)]
+fn ouster_pcap_to_typed_pcd<I, O, T, M>(
     input_file: I,
+    filter: &PacketFilter,
     output_dir: O,
-    model: ProductID,
-    mode: VelodyneReturnMode,
+    template: Option<&OutputTemplate>,
+    metadata: &OusterMetadata,
     start: StartFrame,
     end: EndFrame,
+    selector: &FrameSelector,
     tf: Option<na::Isometry3<f32>>,
+    jobs: usize,
+    map_sample: M,
 ) -> Result<()>
 where
     I: AsRef<Path>,
     O: AsRef<Path>,
+    T: PcdSerialize,
+    M: Fn(OusterSample, Duration) -> T + Sync,
 {
-    use FormatKind as F;
-    use ReturnMode as R;
-
-    let num_frames = count_frames_in_velodyne_pcap(input_file.as_ref(), model, mode)?;
-
-    let start = match start {
-        StartFrame::Forward(count) => count - 1,
-        StartFrame::Backward(count) => {
-            let Some(end) = num_frames.checked_sub(count) else {
-                bail!("--start position is out of bound");
-            };
-            end
-        }
-    };
-    let end = match end {
-        EndFrame::Forward(count) => {
-            ensure!(count <= num_frames, "--end position is out of bound");
-            count
-        }
-        EndFrame::Backward(count) => {
-            let Some(end) = (num_frames + 1).checked_sub(count) else {
-                bail!("--end position is out of bound");
-            };
-            end
-        }
-        EndFrame::Count(count) => {
-            let end = start + count;
-            ensure!(count <= num_frames, "--end position is out of bound");
-            end
-        }
-    };
-    let Some(count) = end.checked_sub(start) else {
-        bail!("--start position must go before --end position");
-    };
-
-    // closures
-    let map_measurement = |measurement: Measurement| {
-        let [x, y, z] = measurement.xyz;
-        [
-            x.as_meters() as f32,
-            y.as_meters() as f32,
-            z.as_meters() as f32,
-        ]
-    };
-
-    let map_point_single = |point: PointS| transform_point(map_measurement(point.measurement), tf);
-    let map_point_dual = |point: PointD| {
-        let MeasurementDual {
-            strongest: strongest_measure,
-            last: last_measure,
-        } = point.measurements;
-        let strongest_point = transform_point(map_measurement(strongest_measure), tf);
-        let last_point = transform_point(map_measurement(last_measure), tf);
-        (strongest_point, last_point)
+    let input_file = input_file.as_ref();
+    let template = frame_template(template)?;
+    let sensor = if template.uses(Placeholder::Sensor) {
+        Some(ouster_sensor_id(metadata)?)
+    } else {
+        None
     };
+    let range = resolve_unindexed_frame_range(start, end)?;
+    let tf: Option<na::Isometry3<f64>> = tf.map(|tf| tf.cast());
 
-    // create the velodyne-lidar config
-    let config = build_velodyne_config(model, mode.0)?;
-
-    // Create output directories
     let output_dir = output_dir.as_ref();
-    let strongest_output_dir = output_dir.join("strongest");
-    let last_output_dir = output_dir.join("last");
     fs::create_dir(output_dir)?;
 
-    match mode.0 {
-        R::Strongest => {
-            fs::create_dir(&strongest_output_dir)?;
-        }
-        R::Last => {
-            fs::create_dir(&last_output_dir)?;
-        }
-        R::Dual => {
-            fs::create_dir(&strongest_output_dir)?;
-            fs::create_dir(&last_output_dir)?;
-        }
-    }
-
-    let mut frames = frame_xyz_iter_from_file(config, input_file)?
-        .enumerate()
-        .skip(start)
-        .take(count);
-
-    match mode.0 {
-        R::Strongest => {
-            frames.try_for_each(|(index, frame)| {
-                let file_name = format!("{:06}.pcd", index);
-                let pcd_file = strongest_output_dir.join(file_name);
-
-                match frame? {
-                    F::Single16(frame) => {
-                        let width = frame.firings.len();
-                        let points = frame.into_point_iter().map(map_point_single);
-                        create_libpcl_pcd_file_single(points, pcd_file, width, 16)?;
-                    }
-                    F::Single32(frame) => {
-                        let width = frame.firings.len();
-                        let points = frame.into_point_iter().map(map_point_single);
-                        create_libpcl_pcd_file_single(points, pcd_file, width, 32)?;
-                    }
-                    _ => unreachable!(),
-                }
-
-                eyre::Ok(())
-            })?;
-        }
-        R::Last => {
-            frames.try_for_each(|(index, frame)| {
-                let file_name = format!("{:06}.pcd", index);
-                let pcd_file = last_output_dir.join(file_name);
-
-                match frame? {
-                    F::Single16(frame) => {
-                        let width = frame.firings.len();
-                        let points = frame.into_point_iter().map(map_point_single);
-                        create_libpcl_pcd_file_single(points, pcd_file, width, 16)?;
-                    }
-                    F::Single32(frame) => {
-                        let width = frame.firings.len();
-                        let points = frame.into_point_iter().map(map_point_single);
-                        create_libpcl_pcd_file_single(points, pcd_file, width, 32)?;
-                    }
-                    _ => unreachable!(),
-                }
-
-                eyre::Ok(())
-            })?;
-        }
-        R::Dual => {
-            frames.try_for_each(|(index, frame)| {
-                let file_name = format!("{:06}.pcd", index);
-                let pcd_file_strongest = strongest_output_dir.join(&file_name);
-                let pcd_file_last = last_output_dir.join(&file_name);
-
-                match frame? {
-                    F::Dual16(frame) => {
-                        let width = frame.firings.len();
-                        let points = frame.into_point_iter().map(map_point_dual);
-                        create_libpcl_pcd_file_dual(
-                            points,
-                            pcd_file_strongest,
-                            pcd_file_last,
-                            width,
-                            16,
-                        )?;
-                    }
-                    F::Dual32(frame) => {
-                        let width = frame.firings.len();
-                        let points = frame.into_point_iter().map(map_point_dual);
-                        create_libpcl_pcd_file_dual(
-                            points,
-                            pcd_file_strongest,
-                            pcd_file_last,
-                            width,
-                            32,
-                        )?;
-                    }
-                    _ => unreachable!(),
-                }
-
-                eyre::Ok(())
-            })?;
-        }
-    }
+    let mut num_frames = None;
+    let frames = select_frames(
+        counted_frames(
+            ouster_frame_iter_from_file(input_file, filter, metadata)?,
+            &mut num_frames,
+        ),
+        selector.sampler(range.clone(), None),
+        |frame| frame.start_time,
+    );
+
+    convert_frames_in_parallel(jobs, frames, |(index, frame)| {
+        let OusterFrame {
+            width,
+            height,
+            samples,
+            start_time,
+            ..
+        } = frame;
+        let end_time = frame_end_time(samples.iter().map(|sample| sample.timestamp), start_time);
+        let pcd_file = output_dir.join(template.render(
+            &FrameFields {
+                index,
+                start_time,
+                end_time,
+                mode: None,
+                sensor: sensor.as_deref(),
+            },
+            "pcd",
+        ));
+        create_parent_dir(&pcd_file)?;
+
+        // The frame time is passed along with the sample as it is
+        // relative to the earliest column of the frame. Pixels without
+        // a return are written as NaN points, which is how libpcl
+        // marks invalid points in organized clouds.
+        let points = samples.into_iter().map(|sample| {
+            let xyz = ouster_sample_xyz(&sample, tf).unwrap_or([std::primitive::f64::NAN; 3]);
+            let frame_time = sample.timestamp.saturating_sub(start_time);
+            map_sample(OusterSample { xyz, ..sample }, frame_time)
+        });
+
+        create_pcd_file_single(points, pcd_file, width, height)?;
+
+        eyre::Ok(())
+    })?;
 
-    Ok(())
+    check_unindexed_frame_range(&range, num_frames)
 }
 
-fn velodyne_pcap_to_raw_bin<I, O>(
+#[allow(clippy::too_many_arguments)]
+fn ouster_pcap_to_raw_bin<I, O>(
     input_file: I,
+    filter: &PacketFilter,
     output_dir: O,
-    model: ProductID,
-    mode: VelodyneReturnMode,
+    template: Option<&OutputTemplate>,
+    metadata: &OusterMetadata,
     start: StartFrame,
     end: EndFrame,
+    selector: &FrameSelector,
     tf: Option<na::Isometry3<f32>>,
+    jobs: usize,
 ) -> Result<()>
 where
     I: AsRef<Path>,
     O: AsRef<Path>,
 {
-    use FormatKind as F;
-    use ReturnMode as R;
+    let input_file = input_file.as_ref();
+    let template = frame_template(template)?;
+    let sensor = if template.uses(Placeholder::Sensor) {
+        Some(ouster_sensor_id(metadata)?)
+    } else {
+        None
+    };
+    let range = resolve_unindexed_frame_range(start, end)?;
+    let tf: Option<na::Isometry3<f64>> = tf.map(|tf| tf.cast());
 
-    let num_frames = count_frames_in_velodyne_pcap(input_file.as_ref(), model, mode)?;
+    let output_dir = output_dir.as_ref();
+    fs::create_dir(output_dir)?;
 
-    let start = match start {
-        StartFrame::Forward(count) => count - 1,
-        StartFrame::Backward(count) => {
-            let Some(end) = num_frames.checked_sub(count) else {
-                bail!("--start position is out of bound");
-            };
-            end
-        }
-    };
-    let end = match end {
-        EndFrame::Forward(count) => {
-            ensure!(count <= num_frames, "--end position is out of bound");
-            count
-        }
-        EndFrame::Backward(count) => {
-            let Some(end) = (num_frames + 1).checked_sub(count) else {
-                bail!("--end position is out of bound");
-            };
-            end
-        }
-        EndFrame::Count(count) => {
-            let end = start + count;
-            ensure!(count <= num_frames, "--end position is out of bound");
-            end
-        }
-    };
-    let Some(count) = end.checked_sub(start) else {
-        bail!("--start position must go before --end position");
-    };
+    let mut num_frames = None;
+    let frames = select_frames(
+        counted_frames(
+            ouster_frame_iter_from_file(input_file, filter, metadata)?,
+            &mut num_frames,
+        ),
+        selector.sampler(range.clone(), None),
+        |frame| frame.start_time,
+    );
+
+    convert_frames_in_parallel(jobs, frames, |(index, frame)| {
+        let start_time = frame.start_time;
+        let end_time = frame_end_time(
+            frame.samples.iter().map(|sample| sample.timestamp),
+            start_time,
+        );
+        let bin_file = output_dir.join(template.render(
+            &FrameFields {
+                index,
+                start_time,
+                end_time,
+                mode: None,
+                sensor: sensor.as_deref(),
+            },
+            "bin",
+        ));
+        create_parent_dir(&bin_file)?;
+
+        // Pixels without a return are dropped since the points are not
+        // organized.
+        let points = frame.samples.into_iter().filter_map(|sample| {
+            let [x, y, z] = ouster_sample_xyz(&sample, tf)?;
+            Some([x as f32, y as f32, z as f32, sample.signal as f32])
+        });
+        create_raw_bin_file_single(points, bin_file)?;
+
+        eyre::Ok(())
+    })?;
 
-    // closures
-    let map_measurement = |measurement: Measurement| {
-        let [x, y, z] = measurement.xyz;
-        [
-            x.as_meters() as f32,
-            y.as_meters() as f32,
-            z.as_meters() as f32,
-        ]
-    };
+    check_unindexed_frame_range(&range, num_frames)
+}
 
-    let map_point_single = |point: PointS| transform_point(map_measurement(point.measurement), tf);
-    let map_point_dual = |point: PointD| {
-        let MeasurementDual {
-            strongest: strongest_measure,
-            last: last_measure,
-        } = point.measurements;
-        let strongest_point = transform_point(map_measurement(strongest_measure), tf);
-        let last_point = transform_point(map_measurement(last_measure), tf);
-        (strongest_point, last_point)
+#[allow(clippy::too_many_arguments)]
+fn livox_lvx_to_typed_pcd<I, O, T, M>(
+    input_file: I,
+    output_dir: O,
+    template: Option<&OutputTemplate>,
+    start: StartFrame,
+    end: EndFrame,
+    selector: &FrameSelector,
+    tf: Option<na::Isometry3<f32>>,
+    jobs: usize,
+    map_sample: M,
+) -> Result<()>
+where
+    I: AsRef<Path>,
+    O: AsRef<Path>,
+    T: PcdSerialize,
+    M: Fn(LivoxSample, Duration) -> T + Sync,
+{
+    let input_file = input_file.as_ref();
+    let template = frame_template(template)?;
+    let sensor = if template.uses(Placeholder::Sensor) {
+        Some(livox_sensor_id(input_file)?)
+    } else {
+        None
     };
+    let range = resolve_unindexed_frame_range(start, end)?;
+    let tf: Option<na::Isometry3<f64>> = tf.map(|tf| tf.cast());
 
-    // create the velodyne-lidar config
-    let config = build_velodyne_config(model, mode.0)?;
-
-    // Create output directories
     let output_dir = output_dir.as_ref();
-    let strongest_output_dir = output_dir.join("strongest");
-    let last_output_dir = output_dir.join("last");
     fs::create_dir(output_dir)?;
 
-    match mode.0 {
-        R::Strongest => {
-            fs::create_dir(&strongest_output_dir)?;
-        }
-        R::Last => {
-            fs::create_dir(&last_output_dir)?;
-        }
-        R::Dual => {
-            fs::create_dir(&strongest_output_dir)?;
-            fs::create_dir(&last_output_dir)?;
-        }
-    }
+    let mut num_frames = None;
+    let frames = select_frames(
+        counted_frames(livox_frame_iter_from_file(input_file)?, &mut num_frames),
+        selector.sampler(range.clone(), None),
+        |frame| frame.start_time,
+    );
+
+    convert_frames_in_parallel(jobs, frames, |(index, frame)| {
+        let LivoxFrame {
+            samples,
+            start_time,
+            ..
+        } = frame;
+        let end_time = frame_end_time(samples.iter().map(|sample| sample.timestamp), start_time);
+        let pcd_file = output_dir.join(template.render(
+            &FrameFields {
+                index,
+                start_time,
+                end_time,
+                mode: None,
+                sensor: sensor.as_deref(),
+            },
+            "pcd",
+        ));
+        create_parent_dir(&pcd_file)?;
+
+        // The frame time is passed along with the sample as it is
+        // relative to the earliest sample of the frame.
+        let width = samples.len();
+        let points = samples.into_iter().map(|sample| {
+            let xyz = transform_point(sample.xyz, tf);
+            let frame_time = sample.timestamp.saturating_sub(start_time);
+            map_sample(LivoxSample { xyz, ..sample }, frame_time)
+        });
+
+        create_pcd_file_single(points, pcd_file, width, 1)?;
+
+        eyre::Ok(())
+    })?;
 
-    let mut frames = frame_xyz_iter_from_file(config, input_file)?
-        .enumerate()
-        .skip(start)
-        .take(count);
-
-    match mode.0 {
-        R::Strongest => {
-            frames.try_for_each(|(index, frame)| {
-                let file_name = format!("{:06}.bin", index);
-                let bin_file = strongest_output_dir.join(file_name);
-
-                match frame? {
-                    F::Single16(frame) => {
-                        let points = frame.into_point_iter().map(map_point_single);
-                        create_raw_bin_file_single(points, bin_file)?;
-                    }
-                    F::Single32(frame) => {
-                        let points = frame.into_point_iter().map(map_point_single);
-                        create_raw_bin_file_single(points, bin_file)?;
-                    }
-                    _ => unreachable!(),
-                }
+    check_unindexed_frame_range(&range, num_frames)
+}
 
-                eyre::Ok(())
-            })?;
-        }
-        R::Last => {
-            frames.try_for_each(|(index, frame)| {
-                let file_name = format!("{:06}.bin", index);
-                let bin_file = last_output_dir.join(file_name);
-
-                match frame? {
-                    F::Single16(frame) => {
-                        let points = frame.into_point_iter().map(map_point_single);
-                        create_raw_bin_file_single(points, bin_file)?;
-                    }
-                    F::Single32(frame) => {
-                        let points = frame.into_point_iter().map(map_point_single);
-                        create_raw_bin_file_single(points, bin_file)?;
-                    }
-                    _ => unreachable!(),
-                }
+#[allow(clippy::too_many_arguments)]
+fn livox_lvx_to_raw_bin<I, O>(
+    input_file: I,
+    output_dir: O,
+    template: Option<&OutputTemplate>,
+    start: StartFrame,
+    end: EndFrame,
+    selector: &FrameSelector,
+    tf: Option<na::Isometry3<f32>>,
+    jobs: usize,
+) -> Result<()>
+where
+    I: AsRef<Path>,
+    O: AsRef<Path>,
+{
+    let input_file = input_file.as_ref();
+    let template = frame_template(template)?;
+    let sensor = if template.uses(Placeholder::Sensor) {
+        Some(livox_sensor_id(input_file)?)
+    } else {
+        None
+    };
+    let range = resolve_unindexed_frame_range(start, end)?;
 
-                eyre::Ok(())
-            })?;
-        }
-        R::Dual => {
-            frames.try_for_each(|(index, frame)| {
-                let file_name = format!("{:06}.bin", index);
-                let bin_file_strongest = strongest_output_dir.join(&file_name);
-                let bin_file_last = last_output_dir.join(&file_name);
-
-                match frame? {
-                    F::Dual16(frame) => {
-                        let points = frame.into_point_iter().map(map_point_dual);
-                        create_raw_bin_file_dual(points, bin_file_strongest, bin_file_last)?;
-                    }
-                    F::Dual32(frame) => {
-                        let points = frame.into_point_iter().map(map_point_dual);
-                        create_raw_bin_file_dual(points, bin_file_strongest, bin_file_last)?;
-                    }
-                    _ => unreachable!(),
-                }
+    let output_dir = output_dir.as_ref();
+    fs::create_dir(output_dir)?;
 
-                eyre::Ok(())
-            })?;
-        }
-    }
+    let mut num_frames = None;
+    let frames = select_frames(
+        counted_frames(livox_frame_iter_from_file(input_file)?, &mut num_frames),
+        selector.sampler(range.clone(), None),
+        |frame| frame.start_time,
+    );
+
+    convert_frames_in_parallel(jobs, frames, |(index, frame)| {
+        let start_time = frame.start_time;
+        let end_time = frame_end_time(
+            frame.samples.iter().map(|sample| sample.timestamp),
+            start_time,
+        );
+        let bin_file = output_dir.join(template.render(
+            &FrameFields {
+                index,
+                start_time,
+                end_time,
+                mode: None,
+                sensor: sensor.as_deref(),
+            },
+            "bin",
+        ));
+        create_parent_dir(&bin_file)?;
+
+        let points = frame.samples.into_iter().map(|sample| {
+            let [x, y, z] = sample.xyz;
+            let [x, y, z] = transform_point([x as f32, y as f32, z as f32], tf);
+            [x, y, z, sample.reflectivity as f32]
+        });
+        create_raw_bin_file_single(points, bin_file)?;
+
+        eyre::Ok(())
+    })?;
 
-    Ok(())
+    check_unindexed_frame_range(&range, num_frames)
 }
 
 fn pcd_file_raw_bin_file<I, O>(
@@ -751,60 +1569,15 @@ where
     I: AsRef<Path>,
     O: AsRef<Path>,
 {
-    let input_file = input_file.as_ref();
-    let reader = create_pcd_reader(input_file)?;
+    let (_, points) = create_dyn_point_reader(input_file)?;
     let mut writer = RawBinWriter::from_path(output_file)?;
+    let tf: Option<na::Isometry3<f64>> = tf.map(|tf| tf.cast());
 
-    let intensity_field = reader
-        .meta()
-        .field_defs
-        .fields
-        .iter()
-        .enumerate()
-        .find(|(_, field)| field.name == "intensity");
-
-    let intensity_idx = match intensity_field {
-        Some((idx, field)) => {
-            if field.count == 1 {
-                Some(idx)
-            } else {
-                eprintln!("the intensity field is not a single number");
-                None
-            }
-        }
-        None => None,
-    };
-
-    for point in reader {
+    for point in points {
         let point = point?;
-
-        let intensity = match intensity_idx {
-            Some(idx) => {
-                let val = match &point.0[idx] {
-                    pcd_rs::Field::I8(vec) => vec[0] as f32,
-                    pcd_rs::Field::I16(vec) => vec[0] as f32,
-                    pcd_rs::Field::I32(vec) => vec[0] as f32,
-                    pcd_rs::Field::U8(vec) => vec[0] as f32,
-                    pcd_rs::Field::U16(vec) => vec[0] as f32,
-                    pcd_rs::Field::U32(vec) => vec[0] as f32,
-                    pcd_rs::Field::F32(vec) => vec[0],
-                    pcd_rs::Field::F64(vec) => vec[0] as f32,
-                };
-                Some(val)
-            }
-            None => None,
-        };
-        let intensity = intensity.unwrap_or(0.0);
-
-        let Some([x, y, z]) = point.to_xyz::<f32>() else {
-            bail!(
-                "the file {} misses one of x, y or z field",
-                input_file.display()
-            );
-        };
-
-        let [x, y, z] = transform_point([x, y, z], tf);
-        writer.push([x, y, z, intensity])?;
+        let intensity = point.intensity().unwrap_or(0.0);
+        let [x, y, z] = transform_point([point.x(), point.y(), point.z()], tf);
+        writer.push([x as f32, y as f32, z as f32, intensity as f32])?;
     }
     writer.finish()?;
 
@@ -900,36 +1673,42 @@ where
     Ok(())
 }
 
-fn bin_file_to_libpcl_pcd_file<I, O>(
+fn bin_file_to_pcd_file<I, O, T, M>(
     input_file: I,
     output_file: O,
     tf: Option<na::Isometry3<f32>>,
+    map_point: M,
 ) -> Result<()>
 where
     I: AsRef<Path>,
     O: AsRef<Path>,
+    T: PcdSerialize,
+    M: Fn(BinPoint) -> T,
 {
     let points: Vec<_> = load_bin_iter(input_file)?
         .map(|p| -> Result<_> {
-            let BinPoint { x, y, z, .. } = p?;
+            let BinPoint { x, y, z, intensity } = p?;
             let [x, y, z] = transform_point([x, y, z], tf);
-            Ok([x, y, z])
+            Ok(map_point(BinPoint { x, y, z, intensity }))
         })
         .try_collect()?;
 
     let num_points = points.len();
-    create_libpcl_pcd_file_single(points, output_file, num_points, 1)?;
+    create_pcd_file_single(points, output_file, num_points, 1)?;
     Ok(())
 }
 
-fn bin_dir_to_libpcl_pcd_dir<I, O>(
+fn bin_dir_to_pcd_dir<I, O, T, M>(
     input_dir: I,
     output_dir: O,
     tf: Option<na::Isometry3<f32>>,
+    map_point: M,
 ) -> Result<()>
 where
     I: AsRef<Path>,
     O: AsRef<Path>,
+    T: PcdSerialize,
+    M: Fn(BinPoint) -> T + Sync,
 {
     let output_dir = output_dir.as_ref();
     fs::create_dir(output_dir)
@@ -1002,9 +1781,9 @@ where
 
         let points: Result<Vec<_>> = points
             .map(|p| -> Result<_> {
-                let BinPoint { x, y, z, .. } = p?;
+                let BinPoint { x, y, z, intensity } = p?;
                 let [x, y, z] = transform_point([x, y, z], tf);
-                Ok([x, y, z])
+                Ok(map_point(BinPoint { x, y, z, intensity }))
             })
             .collect();
         let points = match points {
@@ -1022,7 +1801,7 @@ where
         let output_file = output_dir.join(format!("{stem}.pcd"));
 
         let num_points = points.len();
-        if let Err(err) = create_libpcl_pcd_file_single(points, &output_file, num_points, 1) {
+        if let Err(err) = create_pcd_file_single(points, &output_file, num_points, 1) {
             skip!("unable to write {}: {err}", output_file.display());
         };
     });
@@ -1030,6 +1809,134 @@ where
     Ok(())
 }
 
+fn xyzirt_point_from_velodyne(sample: VelodyneSample) -> XyzirtPoint {
+    let VelodyneSample {
+        xyz: [x, y, z],
+        intensity,
+        laser_id,
+        frame_time,
+        ..
+    } = sample;
+
+    XyzirtPoint {
+        x,
+        y,
+        z,
+        intensity: intensity as f32,
+        ring: laser_id as u16,
+        time: frame_time.as_secs_f32(),
+    }
+}
+
+/// Resolves the --start and --end options to the zero-based index of
+/// the first frame and the number of frames to be converted.
+fn resolve_frame_range(
+    num_frames: usize,
+    start: StartFrame,
+    end: EndFrame,
+) -> Result<(usize, usize)> {
+    let start = match start {
+        StartFrame::Forward(count) => count - 1,
+        StartFrame::Backward(count) => {
+            let Some(end) = num_frames.checked_sub(count) else {
+                bail!("--start position is out of bound");
+            };
+            end
+        }
+    };
+    let end = match end {
+        EndFrame::Forward(count) => {
+            ensure!(count <= num_frames, "--end position is out of bound");
+            count
+        }
+        EndFrame::Backward(count) => {
+            let Some(end) = (num_frames + 1).checked_sub(count) else {
+                bail!("--end position is out of bound");
+            };
+            end
+        }
+        EndFrame::Count(count) => {
+            let end = start + count;
+            ensure!(end <= num_frames, "--end position is out of bound");
+            end
+        }
+    };
+    let Some(count) = end.checked_sub(start) else {
+        bail!("--start position must go before --end position");
+    };
+
+    Ok((start, count))
+}
+
+/// Resolves the range of frames to convert in an Ouster or Livox
+/// capture, which is read once without an index. Positions counted
+/// from the last frame are not supported, except for the default
+/// --end at the last frame, which leaves the range open.
+fn resolve_unindexed_frame_range(start: StartFrame, end: EndFrame) -> Result<Range<usize>> {
+    let StartFrame::Forward(start) = start else {
+        bail!("--start position counted from the last frame is not supported for this format");
+    };
+    let start = start - 1;
+    let end = match end {
+        EndFrame::Forward(count) => count,
+        EndFrame::Count(count) => start + count,
+        EndFrame::Backward(1) => usize::MAX,
+        EndFrame::Backward(_) => {
+            bail!("--end position counted from the last frame is not supported for this format")
+        }
+    };
+    ensure!(
+        start <= end,
+        "--start position must go before --end position"
+    );
+    Ok(start..end)
+}
+
+/// Checks the range of frames against the number of frames, which is
+/// known only if the capture was read to the end.
+fn check_unindexed_frame_range(range: &Range<usize>, num_frames: Option<usize>) -> Result<()> {
+    let Some(num_frames) = num_frames else {
+        return Ok(());
+    };
+    ensure!(
+        range.start <= num_frames,
+        "--start position is out of bound"
+    );
+    ensure!(
+        range.end == usize::MAX || range.end <= num_frames,
+        "--end position is out of bound"
+    );
+    Ok(())
+}
+
+/// Passes the frames along, and records the number of frames in
+/// `num_frames` once the frames run out.
+fn counted_frames<'a, I, T>(
+    frames: I,
+    num_frames: &'a mut Option<usize>,
+) -> impl Iterator<Item = Result<T>> + 'a
+where
+    I: Iterator<Item = Result<T>> + 'a,
+{
+    let mut frames = frames.fuse();
+    let mut count = 0;
+
+    iter::from_fn(move || {
+        let frame = frames.next();
+        match frame {
+            Some(_) => count += 1,
+            None => *num_frames = Some(count),
+        }
+        frame
+    })
+}
+
+/// Gives the transformed coordinates of an Ouster pixel, or `None` if
+/// the pixel has no return.
+fn ouster_sample_xyz(sample: &OusterSample, tf: Option<na::Isometry3<f64>>) -> Option<[f64; 3]> {
+    (sample.range != 0.0).then(|| transform_point(sample.xyz, tf))
+}
+
 fn transform_point<T>(point: [T; 3], tf: Option<na::Isometry3<T>>) -> [T; 3]
 where
     T: na::RealField,
@@ -1044,6 +1951,18 @@ where
     }
 }
 
+/// Converts the Velodyne azimuth, which is measured clockwise from
+/// the y-axis, to the counter-clockwise angle from the x-axis within
+/// (-π, π].
+fn velodyne_azimuth_to_azimuthal_angle(azimuth: Angle) -> f64 {
+    let angle = FRAC_PI_2 - azimuth.as_radians();
+    if angle <= -PI {
+        angle + 2.0 * PI
+    } else {
+        angle
+    }
+}
+
 fn is_file<P>(path: P) -> Result<bool>
 where
     P: AsRef<Path>,
@@ -2,15 +2,25 @@ mod tui;
 
 use self::tui::{run_tui, Record, Value};
 use crate::{
+    capture::PacketFilter,
+    livox::{livox_frame_iter_from_file, livox_header_from_file, LivoxSample},
     opts::{Dump, VelodyneReturnMode},
+    ouster::{ouster_frame_iter_from_file, OusterFrame, OusterMetadata, OusterSample},
+    select::{select_frames, select_spinning_frames, FrameSelector},
+    sources,
+    spinning::SpinningLidar,
+    split::FrameSplit,
     types::FileFormat,
-    utils::{build_velodyne_config, guess_file_format},
+    utils::{
+        detect_file_format, resolve_hesai_lidar, resolve_ouster_metadata, resolve_velodyne_lidar,
+    },
+    velodyne::{VelodyneMeasurement, VelodynePoint},
 };
 use eyre::{format_err, Result};
-use itertools::{chain, izip, Itertools};
+use itertools::{chain, Itertools};
 use pcd_rs::{Field, FieldDef};
 use std::path::Path;
-use velodyne_lidar::{ProductID, ReturnMode};
+use velodyne_lidar::ReturnMode;
 
 pub fn dump(args: Dump) -> Result<()> {
     let Dump {
@@ -18,25 +28,76 @@ pub fn dump(args: Dump) -> Result<()> {
         format,
         velodyne_model,
         velodyne_return_mode,
+        velodyne_calibration,
+        ouster_metadata,
+        hesai_model,
+        hesai_angles,
+        interface,
+        source_ip,
+        port,
+        list_sources,
+        cut_angle,
+        frame_period,
+        frame_packets,
+        drop_partial_frames,
+        start_time,
+        end_time,
+        step,
     } = args;
+    let filter = PacketFilter {
+        interface,
+        source_ip,
+        port,
+    };
+    let split = FrameSplit::from_opts(cut_angle, frame_period, frame_packets, drop_partial_frames);
+    let selector = FrameSelector::from_opts(start_time, end_time, step);
+
+    if list_sources {
+        return sources::list_sources(&input, &filter, false);
+    }
 
     let format = match format {
         Some(format) => format,
-        None => guess_file_format(&input)
+        None => detect_file_format(&input)?
             .ok_or_else(|| format_err!("unable to guess file format of '{}'", input.display()))?,
     };
+    split.check_format(format)?;
 
     use FileFormat as F;
     match format {
-        F::LibpclPcd | F::NewslabPcd => dump_pcd(&input)?,
+        F::LibpclPcd
+        | F::LibpclExtPcd
+        | F::LibpclXyzPcd
+        | F::LibpclXyziPcd
+        | F::LibpclXyzrgbaPcd
+        | F::LibpclXyziNormalPcd
+        | F::LibpclNormalPcd
+        | F::LibpclXyzlPcd
+        | F::NewslabPcd
+        | F::NewslabV2Pcd
+        | F::XyzirtPcd
+        | F::OusterPcd
+        | F::LivoxPcd => dump_pcd(&input)?,
         F::VelodynePcap => {
-            let velodyne_model =
-                velodyne_model.ok_or_else(|| format_err!("--velodyne-mode must be set"))?;
-            let velodyne_return_mode = velodyne_return_mode
-                .ok_or_else(|| format_err!("--velodyne-return-mode must be set"))?;
-
-            dump_velodyne_pcap(&input, velodyne_model, velodyne_return_mode)?
+            let (lidar, mode) = resolve_velodyne_lidar(
+                &input,
+                &filter,
+                velodyne_model,
+                velodyne_return_mode,
+                velodyne_calibration.as_deref(),
+            )?;
+            dump_spinning_pcap(&input, &filter, &lidar, mode, &split, &selector)?;
         }
+        F::HesaiPcap => {
+            let (lidar, mode) =
+                resolve_hesai_lidar(&input, &filter, hesai_model, hesai_angles.as_deref())?;
+            dump_spinning_pcap(&input, &filter, &lidar, mode, &split, &selector)?;
+        }
+        F::OusterPcap => {
+            let metadata = resolve_ouster_metadata(&input, ouster_metadata.as_deref())?;
+            dump_ouster_pcap(&input, &filter, &metadata, &selector)?;
+        }
+        F::LivoxLvx => dump_livox_lvx(&input, &selector)?,
         F::RawBin => todo!(),
     }
 
@@ -97,29 +158,44 @@ where
     Ok(())
 }
 
-fn dump_velodyne_pcap<P>(path: P, model: ProductID, mode: VelodyneReturnMode) -> Result<()>
+fn dump_spinning_pcap<P>(
+    path: P,
+    filter: &PacketFilter,
+    lidar: &SpinningLidar,
+    mode: VelodyneReturnMode,
+    split: &FrameSplit,
+    selector: &FrameSelector,
+) -> Result<()>
 where
     P: AsRef<Path>,
 {
-    let config = build_velodyne_config(model, mode.0)?;
-    let frames = velodyne_lidar::iter::frame_xyz_iter_from_file(config, path)?;
+    let frames = select_spinning_frames(path, filter, lidar, mode, split, selector)?;
 
     let header: Vec<String> = {
-        let prefix = &[
-            "frame",
-            "laser_id",
-            "time",
-            "azimuth (deg)",
-            "distance (m)",
-            "intensity",
-        ];
+        let prefix = &["frame", "laser_id", "time", "azimuth (deg)"];
         let suffix: &[_] = match mode.0 {
-            ReturnMode::Strongest => &["x (m, strongest)", "y (m, strongest)", "z (m, strongest)"],
-            ReturnMode::Last => &["x (m, last)", "y (m, last)", "z (m, last)"],
+            ReturnMode::Strongest => &[
+                "distance (m, strongest)",
+                "intensity (strongest)",
+                "x (m, strongest)",
+                "y (m, strongest)",
+                "z (m, strongest)",
+            ],
+            ReturnMode::Last => &[
+                "distance (m, last)",
+                "intensity (last)",
+                "x (m, last)",
+                "y (m, last)",
+                "z (m, last)",
+            ],
             ReturnMode::Dual => &[
+                "distance (m, strongest)",
+                "intensity (strongest)",
                 "x (m, strongest)",
                 "y (m, strongest)",
                 "z (m, strongest)",
+                "distance (m, last)",
+                "intensity (last)",
                 "x (m, last)",
                 "y (m, last)",
                 "z (m, last)",
@@ -131,74 +207,130 @@ where
             .collect()
     };
 
-    let data: Vec<Record> = izip!(1.., frames)
-        .map(|(frame_id, frame)| -> Result<_> {
-            let frame = frame?;
+    let data: Vec<Record> = frames
+        .map(|frame| -> Result<_> {
+            let (index, frame) = frame?;
+            let frame_id = index + 1;
 
             let points: Vec<Record> = frame
-                .into_firing_iter()
-                .flat_map(|firing| {
-                    firing.into_point_iter().map(|point| -> Vec<Value> {
-                        use velodyne_lidar::types::{
-                            measurements::{Measurement, MeasurementDual},
-                            point::{Point as P, PointD, PointS},
-                        };
-
-                        match point {
-                            P::Single(point) => {
-                                let PointS {
-                                    laser_id,
-                                    toh,
-                                    azimuth,
-                                    measurement:
-                                        Measurement {
-                                            distance,
-                                            intensity,
-                                            xyz: [x, y, z],
-                                        },
-                                } = point;
-
-                                vec![
-                                    frame_id.into(),
-                                    laser_id.into(),
-                                    format!("{toh:?}").into(),
-                                    azimuth.as_degrees().into(),
-                                    distance.as_meters().into(),
-                                    intensity.into(),
-                                    x.as_meters().into(),
-                                    y.as_meters().into(),
-                                    z.as_meters().into(),
-                                ]
-                            }
-                            P::Dual(point) => {
-                                let PointD {
-                                    laser_id,
-                                    toh,
-                                    azimuth,
-                                    measurements: MeasurementDual { strongest, last },
-                                } = point;
-
-                                vec![
-                                    frame_id.into(),
-                                    laser_id.into(),
-                                    format!("{toh:?}").into(),
-                                    azimuth.as_degrees().into(),
-                                    strongest.distance.as_meters().into(),
-                                    strongest.intensity.into(),
-                                    strongest.xyz[0].as_meters().into(),
-                                    strongest.xyz[1].as_meters().into(),
-                                    strongest.xyz[2].as_meters().into(),
-                                    last.distance.as_meters().into(),
-                                    last.intensity.into(),
-                                    last.xyz[0].as_meters().into(),
-                                    last.xyz[1].as_meters().into(),
-                                    last.xyz[2].as_meters().into(),
-                                ]
-                            }
-                        }
-                    })
+                .points
+                .into_iter()
+                .map(|point| {
+                    let VelodynePoint {
+                        laser_id,
+                        toh,
+                        azimuth,
+                        strongest,
+                        last,
+                        ..
+                    } = point;
+
+                    let prefix: Vec<Value> = vec![
+                        frame_id.into(),
+                        laser_id.into(),
+                        format!("{toh:?}").into(),
+                        azimuth.as_degrees().into(),
+                    ];
+                    let measurements = chain!(strongest, last).flat_map(|measurement| {
+                        let VelodyneMeasurement {
+                            distance,
+                            intensity,
+                            xyz: [x, y, z],
+                        } = measurement;
+                        let values: [Value; 5] = [
+                            distance.into(),
+                            intensity.into(),
+                            x.into(),
+                            y.into(),
+                            z.into(),
+                        ];
+                        values
+                    });
+
+                    Record(chain!(prefix, measurements).collect())
+                })
+                .collect();
+
+            Ok(points)
+        })
+        .flatten_ok()
+        .try_collect()?;
+
+    run_tui(header, data)?;
+    Ok(())
+}
+
+fn dump_ouster_pcap<P>(
+    path: P,
+    filter: &PacketFilter,
+    metadata: &OusterMetadata,
+    selector: &FrameSelector,
+) -> Result<()>
+where
+    P: AsRef<Path>,
+{
+    let frames = select_frames(
+        ouster_frame_iter_from_file(path, filter, metadata)?,
+        selector.sampler(0..usize::MAX, None),
+        |frame| frame.start_time,
+    );
+
+    let header: Vec<String> = [
+        "frame",
+        "frame_id",
+        "beam",
+        "measurement_id",
+        "time",
+        "range (m)",
+        "signal",
+        "reflectivity",
+        "near_ir",
+        "x (m)",
+        "y (m)",
+        "z (m)",
+    ]
+    .iter()
+    .map(|title| title.to_string())
+    .collect();
+
+    let data: Vec<Record> = frames
+        .map(|frame| -> Result<_> {
+            let (index, frame) = frame?;
+            let frame_index = index + 1;
+            let OusterFrame {
+                frame_id, samples, ..
+            } = frame;
+
+            let points: Vec<Record> = samples
+                .into_iter()
+                .map(|sample| {
+                    let OusterSample {
+                        xyz: [x, y, z],
+                        range,
+                        signal,
+                        reflectivity,
+                        near_ir,
+                        beam,
+                        measurement_id,
+                        timestamp,
+                        ..
+                    } = sample;
+
+                    Record(vec![
+                        frame_index.into(),
+                        frame_id.into(),
+                        beam.into(),
+                        measurement_id.into(),
+                        format!("{timestamp:?}").into(),
+                        range.into(),
+                        signal.into(),
+                        reflectivity.into(),
+                        near_ir.into(),
+                        x.into(),
+                        y.into(),
+                        z.into(),
+                    ])
                 })
-                .map(Record)
                 .collect();
 
             Ok(points)
@@ -209,3 +341,68 @@ where
     run_tui(header, data)?;
     Ok(())
 }
+
+fn dump_livox_lvx<P>(path: P, selector: &FrameSelector) -> Result<()>
+where
+    P: AsRef<Path>,
+{
+    let path = path.as_ref();
+    let header = livox_header_from_file(path)?;
+    let frames = select_frames(
+        livox_frame_iter_from_file(path)?,
+        selector.sampler(0..usize::MAX, None),
+        |frame| frame.start_time,
+    );
+
+    let header_titles: Vec<String> = [
+        "frame",
+        "device",
+        "time",
+        "x (m)",
+        "y (m)",
+        "z (m)",
+        "reflectivity",
+        "tag",
+    ]
+    .iter()
+    .map(|title| title.to_string())
+    .collect();
+
+    let data: Vec<Record> = frames
+        .map(|frame| -> Result<_> {
+            let (_, frame) = frame?;
+            let frame_index = frame.index;
+
+            let points: Vec<Record> = frame
+                .samples
+                .into_iter()
+                .map(|sample| {
+                    let LivoxSample {
+                        xyz: [x, y, z],
+                        reflectivity,
+                        tag,
+                        device,
+                        timestamp,
+                    } = sample;
+
+                    Record(vec![
+                        frame_index.into(),
+                        header.devices[device].lidar_sn.clone().into(),
+                        format!("{timestamp:?}").into(),
+                        x.into(),
+                        y.into(),
+                        z.into(),
+                        reflectivity.into(),
+                        tag.into(),
+                    ])
+                })
+                .collect();
+
+            Ok(points)
+        })
+        .flatten_ok()
+        .try_collect()?;
+
+    run_tui(header_titles, data)?;
+    Ok(())
+}
@@ -0,0 +1,689 @@
+//! Decoding of Hesai Pandar pcap files into frames.
+//!
+//! The firings are decoded into [VelodynePoint]s, so that the frames
+//! share the conversion, dump and show code paths with Velodyne.
+
+use crate::{
+    capture::{CaptureReader, PacketFilter},
+    index::FrameIndexEntry,
+    sources::warn_mixed_sources,
+    split::FrameSplit,
+    velodyne::{FiringFrameIter, VelodyneFrame, VelodyneMeasurement, VelodynePoint},
+};
+use clap::ValueEnum;
+use eyre::{bail, ensure, Context, Result};
+use itertools::Itertools;
+use measurements::Angle;
+use std::{
+    collections::{HashMap, HashSet},
+    fmt, fs,
+    path::Path,
+    time::Duration,
+};
+use velodyne_lidar::ReturnMode;
+
+/// The number of data packets inspected to detect the model and
+/// return mode of a Hesai pcap file.
+const HESAI_SNIFF_PACKETS: usize = 16;
+
+/// The Hesai Pandar models.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, ValueEnum)]
+pub enum HesaiModel {
+    #[value(name = "Pandar40P")]
+    Pandar40P,
+    #[value(name = "PandarXT32")]
+    PandarXT32,
+    #[value(name = "PandarQT64")]
+    PandarQT64,
+}
+
+impl fmt::Display for HesaiModel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let value = self
+            .to_possible_value()
+            .expect("every Hesai model has a name");
+        f.write_str(value.get_name())
+    }
+}
+
+impl HesaiModel {
+    pub fn num_lasers(self) -> usize {
+        HesaiLayout::of(self).num_lasers
+    }
+
+    /// Tells the model from a UDP payload. The Pandar40P packets are
+    /// told by the size, and the others by the laser and block
+    /// numbers in the packet header.
+    pub fn from_packet(payload: &[u8]) -> Option<Self> {
+        let model = match payload {
+            _ if matches!(payload.len(), 1262 | 1266) => Self::Pandar40P,
+            [0xee, 0xff, _, _, _, _, 32, 8, ..] => Self::PandarXT32,
+            [0xee, 0xff, _, _, _, _, 64, 4, ..] => Self::PandarQT64,
+            _ => return None,
+        };
+        (payload.len() >= HesaiLayout::of(model).packet_size).then_some(model)
+    }
+
+    /// Reads the return mode in a data packet of the model.
+    pub fn return_mode_of_packet(self, payload: &[u8]) -> Option<ReturnMode> {
+        hesai_return_mode_from_byte(HesaiLayout::of(self).return_mode_byte(payload))
+    }
+}
+
+/// Interprets the return mode byte in the packet tail. The return
+/// modes involving the first return are not supported.
+pub fn hesai_return_mode_from_byte(byte: u8) -> Option<ReturnMode> {
+    let mode = match byte {
+        0x37 => ReturnMode::Strongest,
+        0x38 => ReturnMode::Last,
+        0x39 => ReturnMode::Dual,
+        _ => return None,
+    };
+    Some(mode)
+}
+
+/// Detects the model and return mode of a Hesai pcap file from the
+/// first data packets. The most frequent values are chosen. Each
+/// gives `None` if it cannot be recognized.
+pub fn detect_hesai_model_and_mode<P>(
+    path: P,
+    filter: &PacketFilter,
+) -> Result<(Option<HesaiModel>, Option<ReturnMode>)>
+where
+    P: AsRef<Path>,
+{
+    let mut capture = CaptureReader::open(path, filter)?;
+    let mut counts: HashMap<(HesaiModel, u8), usize> = HashMap::new();
+    let mut sources = HashSet::new();
+    let mut num_data_packets = 0;
+
+    while num_data_packets < HESAI_SNIFF_PACKETS {
+        let Some(packet) = capture.next_packet()? else {
+            break;
+        };
+        let Some(packet) = packet.udp() else {
+            continue;
+        };
+        let Some(model) = HesaiModel::from_packet(packet.payload) else {
+            continue;
+        };
+        let return_mode = HesaiLayout::of(model).return_mode_byte(packet.payload);
+
+        *counts.entry((model, return_mode)).or_default() += 1;
+        sources.insert((packet.source, packet.destination.port()));
+        num_data_packets += 1;
+    }
+    warn_mixed_sources(sources.len());
+
+    let Some((model, return_mode)) = counts
+        .into_iter()
+        .max_by_key(|&(_, count)| count)
+        .map(|(key, _)| key)
+    else {
+        return Ok((None, None));
+    };
+
+    Ok((Some(model), hesai_return_mode_from_byte(return_mode)))
+}
+
+/// The angle corrections of the lasers in degrees, indexed by the
+/// zero-based laser ID.
+#[derive(Debug, Clone)]
+pub struct HesaiAngles {
+    pub elevations: Vec<f64>,
+    /// The azimuth offsets added to the block azimuth.
+    pub azimuths: Vec<f64>,
+}
+
+impl HesaiAngles {
+    /// Gives the nominal angles of the model.
+    pub fn builtin(model: HesaiModel) -> Result<Self> {
+        use HesaiModel as M;
+
+        const PANDAR_40P_ELEVATIONS: [f64; 40] = [
+            15.0, 11.0, 8.0, 5.0, 3.0, 2.0, 1.67, 1.33, 1.0, 0.67, 0.33, 0.0, -0.33, -0.67, -1.0,
+            -1.33, -1.67, -2.0, -2.33, -2.67, -3.0, -3.33, -3.67, -4.0, -4.33, -4.67, -5.0, -5.33,
+            -5.67, -6.0, -7.0, -8.0, -9.0, -10.0, -11.0, -12.0, -13.0, -14.0, -19.0, -25.0,
+        ];
+        const PANDAR_40P_AZIMUTHS: [f64; 40] = [
+            -1.042, -1.042, -1.042, -1.042, -1.042, -1.042, 1.042, 3.125, 5.208, -5.208, -3.125,
+            -1.042, 1.042, 3.125, 5.208, -5.208, -3.125, -1.042, 1.042, 3.125, 5.208, -5.208,
+            -3.125, -1.042, 1.042, 3.125, 5.208, -5.208, -3.125, -1.042, -1.042, -1.042, -1.042,
+            -1.042, -1.042, -1.042, -1.042, -1.042, -1.042, -1.042,
+        ];
+
+        let angles = match model {
+            M::Pandar40P => Self {
+                elevations: PANDAR_40P_ELEVATIONS.to_vec(),
+                azimuths: PANDAR_40P_AZIMUTHS.to_vec(),
+            },
+            // The lasers are evenly spaced from 15 to -16 degrees
+            // without azimuth offsets.
+            M::PandarXT32 => Self {
+                elevations: (0..32).map(|index| 15.0 - index as f64).collect(),
+                azimuths: vec![0.0; 32],
+            },
+            // The angles of QT64 vary from unit to unit and have to be
+            // read from the correction file of the sensor.
+            M::PandarQT64 => bail!(
+                "The laser angles of the model '{}' are not built in. Please set --hesai-angles",
+                model
+            ),
+        };
+        Ok(angles)
+    }
+
+    /// Loads an angle correction CSV file shipped with the sensor.
+    /// Each row gives the one-based laser ID, the elevation and the
+    /// azimuth offset in degrees. A header row is skipped.
+    pub fn load<P>(path: P) -> Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        let path = path.as_ref();
+        let text = fs::read_to_string(path)?;
+        let lasers = parse_angle_csv(&text)
+            .wrap_err_with(|| format!("unable to read the angle file {}", path.display()))?;
+
+        let (elevations, azimuths) = lasers
+            .into_iter()
+            .map(|(_, elevation, azimuth)| (elevation, azimuth))
+            .unzip();
+        Ok(Self {
+            elevations,
+            azimuths,
+        })
+    }
+
+    pub fn num_lasers(&self) -> usize {
+        self.elevations.len()
+    }
+
+    /// Checks that the angles cover the lasers of the sensor.
+    pub fn check_num_lasers(&self, num_lasers: usize) -> Result<()> {
+        ensure!(
+            self.num_lasers() == num_lasers,
+            "the angle file has {} lasers, but the sensor has {}",
+            self.num_lasers(),
+            num_lasers
+        );
+        Ok(())
+    }
+}
+
+/// Reads the rows of an angle correction CSV file, sorted by the laser
+/// ID.
+fn parse_angle_csv(text: &str) -> Result<Vec<(usize, f64, f64)>> {
+    let mut lasers: Vec<_> = text
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .enumerate()
+        .filter(|(index, line)| {
+            // Skip the header row
+            let is_header = line.starts_with(|ch: char| ch.is_ascii_alphabetic());
+            !(*index == 0 && is_header)
+        })
+        .map(|(_, line)| -> Result<_> {
+            let fields: Vec<_> = line.split(',').map(str::trim).collect();
+            let [laser_id, elevation, azimuth] = fields[..] else {
+                bail!("expect 3 columns in the row '{line}'");
+            };
+            let parse = |text: &str| -> Result<f64> {
+                text.parse()
+                    .wrap_err_with(|| format!("invalid value '{text}' in the row '{line}'"))
+            };
+            let laser_id: usize = laser_id
+                .parse()
+                .wrap_err_with(|| format!("invalid laser ID '{laser_id}'"))?;
+
+            Ok((laser_id, parse(elevation)?, parse(azimuth)?))
+        })
+        .try_collect()?;
+
+    ensure!(!lasers.is_empty(), "no lasers are found");
+    lasers.sort_by_key(|&(laser_id, _, _)| laser_id);
+
+    for (index, &(laser_id, _, _)) in lasers.iter().enumerate() {
+        ensure!(
+            laser_id == index + 1,
+            "expect exactly one row for laser {}",
+            index + 1
+        );
+    }
+
+    Ok(lasers)
+}
+
+/// Reads the frames in a Hesai pcap file. Packets of other models or
+/// return modes are skipped.
+pub fn hesai_frame_iter_from_file<P>(
+    path: P,
+    filter: &PacketFilter,
+    model: HesaiModel,
+    mode: ReturnMode,
+    split: &FrameSplit,
+    angles: &HesaiAngles,
+    start: Option<&FrameIndexEntry>,
+) -> Result<impl Iterator<Item = Result<VelodyneFrame>>>
+where
+    P: AsRef<Path>,
+{
+    let layout = HesaiLayout::of(model);
+    angles.check_num_lasers(layout.num_lasers)?;
+    let angles = angles.clone();
+
+    let decode = move |payload: &[u8]| {
+        if HesaiModel::from_packet(payload) != Some(model) {
+            return None;
+        }
+        if hesai_return_mode_from_byte(layout.return_mode_byte(payload)) != Some(mode) {
+            return None;
+        }
+        Some(layout.decode_packet(&angles, mode, payload))
+    };
+    let mut frames = FiringFrameIter::new(path, filter, layout.num_lasers, split, decode)?;
+    if let Some(entry) = start {
+        frames.seek(entry)?;
+    }
+    Ok(frames)
+}
+
+/// The firing delays of the Pandar40P lasers in nanoseconds, which
+/// are made of 0.93 µs and 1.6 µs steps.
+const PANDAR_40P_LASER_DELAYS: [u32; 40] = [
+    42_450, 39_920, 12_240, 930, 19_160, 24_220, 27_680, 29_540, 14_770, 35_530, 41_520, 8_520,
+    18_230, 38_060, 31_140, 11_310, 5_990, 44_980, 26_080, 21_690, 16_630, 34_600, 38_990, 4_390,
+    32_070, 13_170, 25_150, 6_920, 10_380, 45_910, 28_610, 9_450, 33_000, 22_620, 43_380, 1_860,
+    15_700, 20_090, 36_460, 2_790,
+];
+
+/// The firing delays of the PandarXT32 lasers in nanoseconds. The
+/// lasers are fired in order every 1.512 µs.
+const PANDAR_XT32_LASER_DELAYS: [u32; 32] = {
+    let mut delays = [0; 32];
+    let mut laser_id = 0;
+    while laser_id < delays.len() {
+        delays[laser_id] = 1_512 * laser_id as u32 + 280;
+        laser_id += 1;
+    }
+    delays
+};
+
+/// The packet layout of a Hesai model.
+#[derive(Debug, Clone, Copy)]
+struct HesaiLayout {
+    /// The number of lasers, which is the frame height.
+    num_lasers: usize,
+    num_blocks: usize,
+    /// The minimum UDP payload size.
+    packet_size: usize,
+    /// The size of the packet header. It is zero if the packet has
+    /// no header.
+    header_size: usize,
+    /// The size of the block header, which holds the azimuth and
+    /// optionally a leading flag.
+    block_header_size: usize,
+    /// The size of a channel, which holds the distance and the
+    /// reflectivity.
+    channel_size: usize,
+    /// The offsets in the packet tail.
+    return_mode_offset: usize,
+    utc_offset: usize,
+    timestamp_offset: usize,
+    /// The duration between firings.
+    firing_period: Duration,
+    /// The time from the start of the last firing in a packet to the
+    /// packet timestamp in nanoseconds. It is negative if the last
+    /// firing starts after the timestamp.
+    last_firing_lead: i64,
+    /// The delays of the lasers from the start of a firing in
+    /// nanoseconds, indexed by the laser ID. It is empty if the firing
+    /// sequence is not built in.
+    laser_delays: &'static [u32],
+}
+
+impl HesaiLayout {
+    fn of(model: HesaiModel) -> Self {
+        match model {
+            HesaiModel::Pandar40P => Self {
+                num_lasers: 40,
+                num_blocks: 10,
+                packet_size: 1262,
+                header_size: 0,
+                block_header_size: 4,
+                channel_size: 3,
+                return_mode_offset: 14,
+                utc_offset: 16,
+                timestamp_offset: 10,
+                firing_period: Duration::from_nanos(55_560),
+                last_firing_lead: 28_580,
+                laser_delays: &PANDAR_40P_LASER_DELAYS,
+            },
+            HesaiModel::PandarXT32 => Self {
+                num_lasers: 32,
+                num_blocks: 8,
+                packet_size: 1080,
+                header_size: 12,
+                block_header_size: 2,
+                channel_size: 4,
+                return_mode_offset: 10,
+                utc_offset: 13,
+                timestamp_offset: 19,
+                firing_period: Duration::from_micros(50),
+                last_firing_lead: -3_280,
+                laser_delays: &PANDAR_XT32_LASER_DELAYS,
+            },
+            HesaiModel::PandarQT64 => Self {
+                num_lasers: 64,
+                num_blocks: 4,
+                packet_size: 1072,
+                header_size: 12,
+                block_header_size: 2,
+                channel_size: 4,
+                return_mode_offset: 10,
+                utc_offset: 13,
+                timestamp_offset: 19,
+                firing_period: Duration::from_nanos(166_670),
+                last_firing_lead: 0,
+                laser_delays: &[],
+            },
+        }
+    }
+
+    fn block_size(&self) -> usize {
+        self.block_header_size + self.num_lasers * self.channel_size
+    }
+
+    fn tail_offset(&self) -> usize {
+        self.header_size + self.num_blocks * self.block_size()
+    }
+
+    fn return_mode_byte(&self, payload: &[u8]) -> u8 {
+        payload[self.tail_offset() + self.return_mode_offset]
+    }
+
+    /// Gives the distance unit in meters. It is read from the packet
+    /// header if the packet has one.
+    fn distance_resolution(&self, payload: &[u8]) -> f64 {
+        match self.header_size {
+            0 => 0.004,
+            _ => payload[9] as f64 / 1000.0,
+        }
+    }
+
+    /// Gives the packet time since the top of the hour from the
+    /// minute and second of the UTC time and the microsecond
+    /// timestamp in the tail.
+    fn packet_toh(&self, payload: &[u8]) -> Duration {
+        let tail = &payload[self.tail_offset()..];
+        let utc = &tail[self.utc_offset..self.utc_offset + 6];
+        let micros = u32::from_le_bytes(
+            tail[self.timestamp_offset..self.timestamp_offset + 4]
+                .try_into()
+                .unwrap(),
+        );
+        let (minute, second) = (utc[4] as u64, utc[5] as u64);
+
+        Duration::from_secs(minute * 60 + second) + Duration::from_micros(micros as u64)
+    }
+
+    /// Decodes the firings in a data packet.
+    ///
+    /// In the single return modes, each block holds a firing. In the
+    /// dual return mode, a firing spans two consecutive blocks sharing
+    /// the same azimuth, which hold the last and the strongest returns
+    /// in order.
+    ///
+    /// The packet timestamp is taken after the last firing. As in the
+    /// Hesai SDK, the firings are timed backwards from it by the firing
+    /// period, and the lasers are delayed from the start of the firing
+    /// by the firing sequence of the model. The lasers of models
+    /// without a built-in sequence are timed at the start of the
+    /// firing.
+    fn decode_packet(
+        &self,
+        angles: &HesaiAngles,
+        mode: ReturnMode,
+        payload: &[u8],
+    ) -> Vec<(Angle, Vec<VelodynePoint>)> {
+        let read_u16 = |offset: usize| u16::from_le_bytes([payload[offset], payload[offset + 1]]);
+        let packet_toh = self.packet_toh(payload);
+        let distance_resolution = self.distance_resolution(payload);
+
+        let blocks_per_firing = match mode {
+            ReturnMode::Dual => 2,
+            _ => 1,
+        };
+        let num_firings = self.num_blocks / blocks_per_firing;
+        let block_offset = |block: usize| self.header_size + block * self.block_size();
+
+        let read_measurement = |block: usize, laser_id: usize, azimuth: f64| {
+            let offset =
+                block_offset(block) + self.block_header_size + laser_id * self.channel_size;
+            let distance = read_u16(offset) as f64 * distance_resolution;
+            let intensity = payload[offset + 2];
+
+            let elevation = angles.elevations[laser_id].to_radians();
+            let azimuth = azimuth.to_radians();
+            let xyz = [
+                distance * elevation.cos() * azimuth.sin(),
+                distance * elevation.cos() * azimuth.cos(),
+                distance * elevation.sin(),
+            ];
+
+            VelodyneMeasurement {
+                distance,
+                intensity,
+                xyz,
+            }
+        };
+
+        (0..num_firings)
+            .map(|firing| {
+                let first_block = firing * blocks_per_firing;
+                let firing_offset = -self.last_firing_lead
+                    - (self.firing_period * (num_firings - 1 - firing) as u32).as_nanos() as i64;
+                let azimuth_offset = block_offset(first_block) + self.block_header_size - 2;
+                let azimuth = read_u16(azimuth_offset) as f64 / 100.0;
+
+                let points: Vec<_> = (0..self.num_lasers)
+                    .map(|laser_id| {
+                        let laser_azimuth = (azimuth + angles.azimuths[laser_id]).rem_euclid(360.0);
+
+                        let first = read_measurement(first_block, laser_id, laser_azimuth);
+                        let (strongest, last) = match mode {
+                            ReturnMode::Strongest => (Some(first), None),
+                            ReturnMode::Last => (None, Some(first)),
+                            ReturnMode::Dual => {
+                                let second =
+                                    read_measurement(first_block + 1, laser_id, laser_azimuth);
+                                (Some(second), Some(first))
+                            }
+                        };
+
+                        let delay = self.laser_delays.get(laser_id).copied().unwrap_or(0);
+                        let toh = shift_toh(packet_toh, firing_offset + delay as i64);
+
+                        VelodynePoint {
+                            laser_id: laser_id as u32,
+                            toh,
+                            azimuth: Angle::from_degrees(laser_azimuth),
+                            elevation: Angle::from_degrees(angles.elevations[laser_id]),
+                            strongest,
+                            last,
+                        }
+                    })
+                    .collect();
+
+                (Angle::from_degrees(azimuth), points)
+            })
+            .collect()
+    }
+}
+
+/// Shifts a top-of-hour time by an offset in nanoseconds, wrapping
+/// around the hour.
+fn shift_toh(toh: Duration, offset: i64) -> Duration {
+    const HOUR_NANOS: i64 = 3_600_000_000_000;
+    let nanos = (toh.as_nanos() as i64 + offset).rem_euclid(HOUR_NANOS);
+    Duration::from_nanos(nanos as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use approx::assert_abs_diff_eq;
+
+    /// 00:34:56.5 past the hour.
+    const PACKET_TOH: Duration = Duration::from_millis(2_096_500);
+
+    /// Builds a PandarXT32 packet in which laser 0 of each block
+    /// measures 1000 + block units of 4 mm. The blocks start at 90
+    /// degrees and advance by 0.2 degrees.
+    fn xt32_packet(return_mode: u8) -> Vec<u8> {
+        let layout = HesaiLayout::of(HesaiModel::PandarXT32);
+        let mut payload = vec![0; layout.packet_size];
+        payload[..8].copy_from_slice(&[0xee, 0xff, 6, 1, 0, 0, 32, 8]);
+        payload[9] = 4;
+
+        for block in 0..layout.num_blocks {
+            let offset = layout.header_size + block * layout.block_size();
+            let azimuth = 9_000 + block as u16 * 20;
+            payload[offset..offset + 2].copy_from_slice(&azimuth.to_le_bytes());
+            payload[offset + 2..offset + 4].copy_from_slice(&(1_000 + block as u16).to_le_bytes());
+            payload[offset + 4] = 10 + block as u8;
+        }
+
+        let tail = layout.tail_offset();
+        payload[tail + layout.return_mode_offset] = return_mode;
+        payload[tail + layout.utc_offset..][..6].copy_from_slice(&[123, 10, 16, 12, 34, 56]);
+        payload[tail + layout.timestamp_offset..][..4].copy_from_slice(&500_000u32.to_le_bytes());
+        payload
+    }
+
+    fn flat_angles() -> HesaiAngles {
+        HesaiAngles {
+            elevations: vec![0.0; 32],
+            azimuths: vec![0.0; 32],
+        }
+    }
+
+    fn decode(mode: ReturnMode) -> Vec<(Angle, Vec<VelodynePoint>)> {
+        let payload = xt32_packet(0x37);
+        HesaiLayout::of(HesaiModel::PandarXT32).decode_packet(&flat_angles(), mode, &payload)
+    }
+
+    #[test]
+    fn detect_model_and_return_mode() {
+        let payload = xt32_packet(0x39);
+        let model = HesaiModel::from_packet(&payload);
+        assert_eq!(model, Some(HesaiModel::PandarXT32));
+        assert_eq!(
+            model.unwrap().return_mode_of_packet(&payload),
+            Some(ReturnMode::Dual)
+        );
+
+        // Truncated packets and unknown headers are not recognized.
+        assert_eq!(HesaiModel::from_packet(&payload[..1000]), None);
+        assert_eq!(HesaiModel::from_packet(&[0; 1080]), None);
+        assert_eq!(
+            HesaiModel::from_packet(&[0; 1262]),
+            Some(HesaiModel::Pandar40P)
+        );
+        assert_eq!(hesai_return_mode_from_byte(0x3b), None);
+    }
+
+    #[test]
+    fn decode_xt32_single_return() {
+        let firings = decode(ReturnMode::Strongest);
+        assert_eq!(firings.len(), 8);
+
+        let (azimuth, points) = &firings[0];
+        assert_abs_diff_eq!(azimuth.as_degrees(), 90.0, epsilon = 1e-9);
+        assert_eq!(points.len(), 32);
+        assert!(points[0].last.is_none());
+
+        let strongest = points[0].strongest.unwrap();
+        assert_abs_diff_eq!(strongest.distance, 4.0, epsilon = 1e-9);
+        assert_eq!(strongest.intensity, 10);
+        assert_abs_diff_eq!(strongest.xyz[0], 4.0, epsilon = 1e-9);
+        assert_abs_diff_eq!(strongest.xyz[1], 0.0, epsilon = 1e-9);
+        assert_abs_diff_eq!(strongest.xyz[2], 0.0, epsilon = 1e-9);
+
+        let (azimuth, points) = &firings[7];
+        assert_abs_diff_eq!(azimuth.as_degrees(), 91.4, epsilon = 1e-9);
+        let strongest = points[0].strongest.unwrap();
+        assert_abs_diff_eq!(strongest.distance, 4.028, epsilon = 1e-9);
+        assert_eq!(strongest.intensity, 17);
+    }
+
+    #[test]
+    fn time_xt32_firings_backwards_from_packet_timestamp() {
+        let firings = decode(ReturnMode::Strongest);
+        let toh = |firing: usize, laser_id: usize| firings[firing].1[laser_id].toh;
+
+        // The last firing starts 3.28 µs after the packet timestamp
+        // and laser 0 fires 0.28 µs later.
+        assert_eq!(toh(7, 0), PACKET_TOH + Duration::from_nanos(3_560));
+        assert_eq!(
+            toh(7, 31),
+            PACKET_TOH + Duration::from_nanos(3_280 + 1_512 * 31 + 280)
+        );
+        // The firings are 50 µs apart.
+        assert_eq!(toh(6, 0), toh(7, 0) - Duration::from_micros(50));
+        assert_eq!(
+            toh(0, 0),
+            PACKET_TOH - Duration::from_micros(350) + Duration::from_nanos(3_560)
+        );
+    }
+
+    #[test]
+    fn decode_xt32_dual_return() {
+        let firings = decode(ReturnMode::Dual);
+        assert_eq!(firings.len(), 4);
+
+        // A firing spans two blocks holding the last and the strongest
+        // returns in order.
+        let point = &firings[1].1[0];
+        assert_abs_diff_eq!(point.last.unwrap().distance, 4.008, epsilon = 1e-9);
+        assert_abs_diff_eq!(point.strongest.unwrap().distance, 4.012, epsilon = 1e-9);
+        assert_abs_diff_eq!(firings[1].0.as_degrees(), 90.4, epsilon = 1e-9);
+
+        assert_eq!(
+            firings[3].1[0].toh,
+            PACKET_TOH + Duration::from_nanos(3_560)
+        );
+        assert_eq!(
+            firings[0].1[0].toh,
+            PACKET_TOH - Duration::from_micros(150) + Duration::from_nanos(3_560)
+        );
+    }
+
+    #[test]
+    fn shift_toh_wraps_around_the_hour() {
+        let toh = shift_toh(Duration::from_micros(100), -200_000);
+        assert_eq!(toh, Duration::from_secs(3600) - Duration::from_micros(100));
+
+        let toh = shift_toh(Duration::from_secs(3600) - Duration::from_micros(1), 3_000);
+        assert_eq!(toh, Duration::from_micros(2));
+    }
+
+    #[test]
+    fn parse_angle_csv_sorted_by_laser_id() {
+        let text = "Laser id,Elevation,Azimuth\n2,-1.5,0.25\n1, 2.0 , -0.5\n\n3,-4,0\n";
+        let lasers = parse_angle_csv(text).unwrap();
+        assert_eq!(lasers, [(1, 2.0, -0.5), (2, -1.5, 0.25), (3, -4.0, 0.0)]);
+    }
+
+    #[test]
+    fn parse_angle_csv_rejects_invalid_rows() {
+        // Laser 2 is missing.
+        assert!(parse_angle_csv("1,0,0\n3,0,0\n").is_err());
+        // Laser 1 is repeated.
+        assert!(parse_angle_csv("1,0,0\n1,0,0\n").is_err());
+        assert!(parse_angle_csv("1,0\n").is_err());
+        assert!(parse_angle_csv("1,zero,0\n").is_err());
+        assert!(parse_angle_csv("Laser id,Elevation,Azimuth\n").is_err());
+    }
+}
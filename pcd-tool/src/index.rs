@@ -0,0 +1,394 @@
+//! The sidecar index of the frames in a Velodyne or Hesai pcap file.
+//!
+//! Locating the frames takes a pass decoding the whole capture. The
+//! index records the byte offsets and timestamps of every frame in
+//! `<input>.index.json`, so that later runs count and seek frames
+//! without decoding the capture again. An index is kept per packet
+//! filter, LiDAR model, return mode and frame split, and is rebuilt
+//! once the capture file changes.
+
+use crate::{
+    capture::PacketFilter,
+    opts::{Index, VelodyneReturnMode},
+    position::{spinning_toh_clock, TimeSource},
+    spinning::SpinningLidar,
+    split::FrameSplit,
+    types::FileFormat,
+    utils::{detect_file_format, resolve_hesai_lidar, resolve_velodyne_lidar, TohClock},
+    velodyne::{FrameSpan, VelodyneFrame},
+};
+use eyre::{bail, format_err, Result};
+use itertools::Either;
+use serde::{Deserialize, Serialize};
+use std::{
+    ffi::OsString,
+    fs::{self, File},
+    io::{BufReader, BufWriter},
+    iter,
+    net::Ipv4Addr,
+    path::{Path, PathBuf},
+    time::{Duration, UNIX_EPOCH},
+};
+
+/// The version of the index file layout. Index files of other versions
+/// are rebuilt.
+const INDEX_VERSION: u32 = 1;
+
+/// What the frames of an index are read with.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FrameIndexKey {
+    pub interface: Option<String>,
+    pub source_ip: Option<Ipv4Addr>,
+    pub port: Option<u16>,
+    /// The LiDAR model, such as "velodyne VLP16".
+    pub lidar: String,
+    pub return_mode: String,
+    pub split: FrameSplit,
+}
+
+impl FrameIndexKey {
+    fn new(
+        filter: &PacketFilter,
+        lidar: &SpinningLidar,
+        mode: VelodyneReturnMode,
+        split: &FrameSplit,
+    ) -> Self {
+        Self {
+            interface: filter
+                .interface
+                .as_ref()
+                .map(|interface| interface.to_string()),
+            source_ip: filter.source_ip,
+            port: filter.port,
+            lidar: lidar.to_string(),
+            return_mode: format!("{:?}", mode.0).to_lowercase(),
+            split: *split,
+        }
+    }
+}
+
+/// A frame recorded in the index.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FrameIndexEntry {
+    #[serde(flatten)]
+    pub span: FrameSpan,
+    /// The top-of-hour timestamp of the first firing.
+    pub toh: Duration,
+    /// The time since the Unix epoch of the first firing.
+    pub start_time: Duration,
+    /// The time since the Unix epoch of the last firing.
+    pub end_time: Duration,
+}
+
+/// The frames in a capture read with a [FrameIndexKey].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FrameIndex {
+    pub key: FrameIndexKey,
+    /// The size of the capture file when it was indexed.
+    pub file_len: u64,
+    /// The modification time of the capture file when it was indexed.
+    pub file_modified: Option<Duration>,
+    /// The source of the hour of the frame timestamps.
+    pub time_source: TimeSource,
+    pub frames: Vec<FrameIndexEntry>,
+}
+
+impl FrameIndex {
+    fn is_fresh(&self, key: &FrameIndexKey, stamp: (u64, Option<Duration>)) -> bool {
+        self.key == *key && (self.file_len, self.file_modified) == stamp
+    }
+}
+
+/// The content of an index file, which holds the indexes of a capture
+/// read in different ways.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct FrameIndexFile {
+    version: u32,
+    indexes: Vec<FrameIndex>,
+}
+
+/// Gives the path of the index file of a capture.
+pub fn index_path<P>(path: P) -> PathBuf
+where
+    P: AsRef<Path>,
+{
+    let path = path.as_ref();
+    let mut name: OsString = path.file_name().unwrap_or_default().to_owned();
+    name.push(".index.json");
+    path.with_file_name(name)
+}
+
+/// Gives the frame index of a capture. It is loaded from the index file
+/// if up to date, or is built and saved otherwise.
+pub fn frame_index<P>(
+    path: P,
+    filter: &PacketFilter,
+    lidar: &SpinningLidar,
+    mode: VelodyneReturnMode,
+    split: &FrameSplit,
+) -> Result<FrameIndex>
+where
+    P: AsRef<Path>,
+{
+    let path = path.as_ref();
+    if let Some(index) = load_frame_index(path, filter, lidar, mode, split)? {
+        return Ok(index);
+    }
+
+    let index = build_frame_index(path, filter, lidar, mode, split)?;
+    store_frame_index(path, &index);
+    Ok(index)
+}
+
+/// Loads the frame index of a capture from the index file. It gives
+/// `None` if the file holds no up-to-date index read the same way.
+pub fn load_frame_index<P>(
+    path: P,
+    filter: &PacketFilter,
+    lidar: &SpinningLidar,
+    mode: VelodyneReturnMode,
+    split: &FrameSplit,
+) -> Result<Option<FrameIndex>>
+where
+    P: AsRef<Path>,
+{
+    let path = path.as_ref();
+    let key = FrameIndexKey::new(filter, lidar, mode, split);
+    let stamp = file_stamp(path)?;
+
+    let found = load_index_file(path)
+        .indexes
+        .into_iter()
+        .find(|index| index.is_fresh(&key, stamp));
+    Ok(found)
+}
+
+/// Locates the frames by decoding the whole capture.
+pub fn build_frame_index<P>(
+    path: P,
+    filter: &PacketFilter,
+    lidar: &SpinningLidar,
+    mode: VelodyneReturnMode,
+    split: &FrameSplit,
+) -> Result<FrameIndex>
+where
+    P: AsRef<Path>,
+{
+    let path = path.as_ref();
+    let mut builder = FrameIndexBuilder::new(path, filter, lidar, mode, split)?;
+
+    for frame in lidar.frame_iter_from_file(path, filter, mode.0, split)? {
+        builder.push(&frame?);
+    }
+
+    Ok(builder.finish())
+}
+
+/// Reads the frames at the indices, given in increasing order, from an
+/// indexed capture. Reading seeks to the first frame, and goes on
+/// through the last one. Each frame is given along with its index and
+/// the clock advanced to the frame start.
+pub fn indexed_frame_iter<P>(
+    path: P,
+    filter: &PacketFilter,
+    lidar: &SpinningLidar,
+    mode: VelodyneReturnMode,
+    split: &FrameSplit,
+    indexed: &FrameIndex,
+    selected: Vec<usize>,
+) -> Result<impl Iterator<Item = Result<(usize, VelodyneFrame, TohClock)>>>
+where
+    P: AsRef<Path>,
+{
+    let (Some(&first), Some(&last)) = (selected.first(), selected.last()) else {
+        return Ok(Either::Left(iter::empty()));
+    };
+
+    let entry = &indexed.frames[first];
+    let mut clock = TohClock::from_reference(entry.start_time, entry.toh);
+    let frames = lidar.frame_iter_from_entry(path, filter, mode.0, split, Some(entry))?;
+    let mut selected = selected.into_iter().peekable();
+
+    let frames = (first..=last)
+        .zip(frames)
+        .filter_map(move |(index, frame)| {
+            let frame = match frame {
+                Ok(frame) => frame,
+                Err(err) => return Some(Err(err)),
+            };
+            if let Some(toh) = frame.first_toh() {
+                clock.advance(toh);
+            }
+
+            selected.next_if_eq(&index)?;
+            Some(Ok((index, frame, clock.clone())))
+        });
+    Ok(Either::Right(frames))
+}
+
+/// Saves the index to the index file of the capture. The index only
+/// saves time later, so a failure, such as in a read-only directory,
+/// is reported as a warning.
+pub fn store_frame_index<P>(path: P, index: &FrameIndex)
+where
+    P: AsRef<Path>,
+{
+    let path = path.as_ref();
+    if let Err(err) = save_frame_index(path, index) {
+        eprintln!(
+            "Warning: unable to save the frame index to '{}': {err}",
+            index_path(path).display()
+        );
+    }
+}
+
+/// Indexes the frames of a capture as they are read from the start, so
+/// that a pass over the capture does not need another one to index it.
+pub struct FrameIndexBuilder {
+    index: FrameIndex,
+    clock: TohClock,
+}
+
+impl FrameIndexBuilder {
+    pub fn new<P>(
+        path: P,
+        filter: &PacketFilter,
+        lidar: &SpinningLidar,
+        mode: VelodyneReturnMode,
+        split: &FrameSplit,
+    ) -> Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        let path = path.as_ref();
+        let (file_len, file_modified) = file_stamp(path)?;
+        let (clock, time_source) = spinning_toh_clock(path, filter, lidar)?;
+
+        Ok(Self {
+            index: FrameIndex {
+                key: FrameIndexKey::new(filter, lidar, mode, split),
+                file_len,
+                file_modified,
+                time_source,
+                frames: vec![],
+            },
+            clock,
+        })
+    }
+
+    pub fn time_source(&self) -> TimeSource {
+        self.index.time_source
+    }
+
+    /// The clock advanced to the start of the last pushed frame.
+    pub fn clock(&self) -> &TohClock {
+        &self.clock
+    }
+
+    /// Records the next frame of the capture.
+    pub fn push(&mut self, frame: &VelodyneFrame) {
+        let toh = frame.first_toh().unwrap_or_default();
+        self.clock.advance(toh);
+        let end_toh = frame.last_toh().unwrap_or(toh);
+
+        self.index.frames.push(FrameIndexEntry {
+            span: frame.span,
+            toh,
+            start_time: self.clock.current(),
+            end_time: self.clock.to_absolute(end_toh),
+        });
+    }
+
+    pub fn finish(self) -> FrameIndex {
+        self.index
+    }
+}
+
+/// Saves the index to the index file of the capture, replacing the
+/// index with the same key.
+fn save_frame_index(path: &Path, index: &FrameIndex) -> Result<()> {
+    let mut file = load_index_file(path);
+    file.version = INDEX_VERSION;
+    file.indexes.retain(|other| other.key != index.key);
+    file.indexes.push(index.clone());
+
+    let writer = BufWriter::new(File::create(index_path(path))?);
+    serde_json::to_writer(writer, &file)?;
+    Ok(())
+}
+
+/// Loads the index file of a capture. A missing, unreadable or
+/// outdated file gives no indexes.
+fn load_index_file(path: &Path) -> FrameIndexFile {
+    let load = || -> Option<FrameIndexFile> {
+        let reader = BufReader::new(File::open(index_path(path)).ok()?);
+        let file: FrameIndexFile = serde_json::from_reader(reader).ok()?;
+        (file.version == INDEX_VERSION).then_some(file)
+    };
+    load().unwrap_or_default()
+}
+
+/// Gives the size and modification time of a file, by which an index
+/// is told to be outdated.
+fn file_stamp(path: &Path) -> Result<(u64, Option<Duration>)> {
+    let metadata = fs::metadata(path)?;
+    let modified = metadata
+        .modified()
+        .ok()
+        .and_then(|time| time.duration_since(UNIX_EPOCH).ok());
+    Ok((metadata.len(), modified))
+}
+
+pub fn index(args: Index) -> Result<()> {
+    let Index {
+        format,
+        input,
+        velodyne_model,
+        velodyne_return_mode,
+        velodyne_calibration,
+        hesai_model,
+        hesai_angles,
+        interface,
+        source_ip,
+        port,
+        cut_angle,
+        frame_period,
+        frame_packets,
+        drop_partial_frames,
+    } = args;
+    let filter = PacketFilter {
+        interface,
+        source_ip,
+        port,
+    };
+    let split = FrameSplit::from_opts(cut_angle, frame_period, frame_packets, drop_partial_frames);
+
+    let format = match format {
+        Some(format) => format,
+        None => detect_file_format(&input)?
+            .ok_or_else(|| format_err!("unable to guess file format of '{}'", input.display()))?,
+    };
+    let (lidar, mode) = match format {
+        FileFormat::VelodynePcap => resolve_velodyne_lidar(
+            &input,
+            &filter,
+            velodyne_model,
+            velodyne_return_mode,
+            velodyne_calibration.as_deref(),
+        )?,
+        FileFormat::HesaiPcap => {
+            resolve_hesai_lidar(&input, &filter, hesai_model, hesai_angles.as_deref())?
+        }
+        format => bail!("indexing the {format} format is not supported"),
+    };
+
+    let index = build_frame_index(&input, &filter, &lidar, mode, &split)?;
+    save_frame_index(&input, &index)?;
+    println!(
+        "Indexed {} frames into '{}'",
+        index.frames.len(),
+        index_path(&input).display()
+    );
+
+    Ok(())
+}
@@ -1,20 +1,151 @@
-use eyre::{ensure, Result};
-use std::path::Path;
+use crate::{
+    capture::{CaptureReader, Interface, PacketFilter},
+    index::{build_frame_index, load_frame_index},
+    opts::{Info, VelodyneReturnMode},
+    packet::{
+        linktype_name, velodyne_product_id_from_byte, velodyne_return_mode_from_byte, UdpPacket,
+        VelodynePacket,
+    },
+    sources,
+    spinning::SpinningLidar,
+    split::FrameSplit,
+    types::FileFormat,
+    utils::{self, detect_file_format, detect_pcd_schema},
+};
+use eyre::{bail, format_err, Result};
+use pcd_rs::{Field, PcdMeta};
+use serde_json::json;
+use std::{collections::BTreeMap, net::SocketAddrV4, path::Path, time::Duration};
 
-pub fn info(file: impl AsRef<Path>) -> Result<()> {
-    let file = file.as_ref();
+/// The minimum interval between consecutive data packets from the same
+/// source to be reported as a gap.
+const GAP_THRESHOLD: Duration = Duration::from_millis(100);
 
-    ensure!(
-        file.extension().map(|ext| ext == "pcd").unwrap_or(false),
-        "file name must ends with '.pcd', but get '{}'",
-        file.display()
-    );
+pub fn info(args: Info) -> Result<()> {
+    let Info {
+        file,
+        json,
+        interface,
+        source_ip,
+        port,
+        list_sources,
+    } = args;
+    let filter = PacketFilter {
+        interface,
+        source_ip,
+        port,
+    };
+
+    if list_sources {
+        return sources::list_sources(&file, &filter, json);
+    }
+
+    let format = detect_file_format(&file)?
+        .ok_or_else(|| format_err!("unable to guess file format of '{}'", file.display()))?;
 
+    use FileFormat as F;
+    match format {
+        F::VelodynePcap => info_velodyne_pcap(&file, &filter, json),
+        F::RawBin | F::OusterPcap | F::HesaiPcap | F::LivoxLvx => {
+            bail!("info does not support the {format} format")
+        }
+        _ => info_pcd(&file, json),
+    }
+}
+
+fn info_pcd(file: &Path, json: bool) -> Result<()> {
     let reader = pcd_rs::DynReader::open(file)?;
-    let fields = &reader.meta().field_defs;
+    let meta = reader.meta().clone();
+
+    // Every element of a field is a column. Fields having multiple
+    // elements are named like name#1, name#2, ...
+    let columns: Vec<String> = meta
+        .field_defs
+        .iter()
+        .flat_map(|field| {
+            let pcd_rs::FieldDef {
+                ref name, count, ..
+            } = *field;
+
+            if count == 1 {
+                vec![name.clone()]
+            } else {
+                (1..=count).map(|idx| format!("{name}#{idx}")).collect()
+            }
+        })
+        .collect();
+    let find_column = |name: &str| columns.iter().position(|column| column == name);
+    let xyz_columns = (|| Some([find_column("x")?, find_column("y")?, find_column("z")?]))();
+
+    // Compute the statistics in one pass
+    let mut stats = PcdStats::new(columns.len());
+    let mut values = vec![];
+
+    for record in reader {
+        let record = record?;
+
+        values.clear();
+        record
+            .0
+            .iter()
+            .for_each(|field| extend_field_values(field, &mut values));
+
+        let xyz = xyz_columns.map(|[x, y, z]| [values[x], values[y], values[z]]);
+        stats.push(&values, xyz);
+    }
+
+    if json {
+        print_json(&meta, &columns, &stats)?;
+    } else {
+        print_text(&meta, &columns, &stats);
+    }
+
+    Ok(())
+}
+
+fn print_text(meta: &PcdMeta, columns: &[String], stats: &PcdStats) {
+    let PcdMeta {
+        width,
+        height,
+        ref viewpoint,
+        data,
+        ref field_defs,
+        ..
+    } = *meta;
+    let data = format!("{:?}", data).to_lowercase();
+
+    match detect_pcd_schema(field_defs) {
+        Some(format) => println!("schema: {}", format),
+        None => println!("schema: unknown"),
+    }
+    println!("points: {}", stats.num_points);
+    println!("width: {}", width);
+    println!("height: {}", height);
+    println!("organized: {}", height > 1);
+    println!("data: {}", data);
+    println!(
+        "viewpoint: {} {} {} {} {} {} {}",
+        viewpoint.tx,
+        viewpoint.ty,
+        viewpoint.tz,
+        viewpoint.qw,
+        viewpoint.qx,
+        viewpoint.qy,
+        viewpoint.qz
+    );
+    println!("non-finite points: {}", stats.num_non_finite_points);
+    println!("zero-range points: {}", stats.num_zero_range_points);
+    match &stats.bbox {
+        Some(([min_x, min_y, min_z], [max_x, max_y, max_z])) => println!(
+            "bbox: ({}, {}, {}) - ({}, {}, {})",
+            min_x, min_y, min_z, max_x, max_y, max_z
+        ),
+        None => println!("bbox: none"),
+    }
+    println!();
 
     println!("name\ttype\tcount");
-    fields.iter().for_each(|field| {
+    field_defs.iter().for_each(|field| {
         let pcd_rs::FieldDef {
             ref name,
             kind,
@@ -23,6 +154,549 @@ pub fn info(file: impl AsRef<Path>) -> Result<()> {
 
         println!("{}\t{:?}\t{}", name, kind, count);
     });
+    println!();
+
+    let show = |value: Option<f64>| match value {
+        Some(value) => value.to_string(),
+        None => "-".to_string(),
+    };
+
+    println!("name\tmin\tmax\tmean\tstddev\tnan\tinf");
+    columns
+        .iter()
+        .zip(&stats.columns)
+        .for_each(|(name, column)| {
+            println!(
+                "{}\t{}\t{}\t{}\t{}\t{}\t{}",
+                name,
+                show(column.min()),
+                show(column.max()),
+                show(column.mean()),
+                show(column.stddev()),
+                column.num_nan,
+                column.num_inf
+            );
+        });
+}
+
+fn print_json(meta: &PcdMeta, columns: &[String], stats: &PcdStats) -> Result<()> {
+    let PcdMeta {
+        width,
+        height,
+        ref viewpoint,
+        data,
+        ref field_defs,
+        ..
+    } = *meta;
+
+    let fields: Vec<_> = field_defs
+        .iter()
+        .map(|field| {
+            json!({
+                "name": field.name,
+                "type": format!("{:?}", field.kind),
+                "count": field.count,
+            })
+        })
+        .collect();
+    let column_stats: Vec<_> = columns
+        .iter()
+        .zip(&stats.columns)
+        .map(|(name, column)| {
+            json!({
+                "name": name,
+                "min": column.min(),
+                "max": column.max(),
+                "mean": column.mean(),
+                "stddev": column.stddev(),
+                "nan": column.num_nan,
+                "inf": column.num_inf,
+            })
+        })
+        .collect();
+    let bbox = stats
+        .bbox
+        .map(|(min, max)| json!({ "min": min, "max": max }));
+
+    let output = json!({
+        "schema": detect_pcd_schema(field_defs).map(|format| format.to_string()),
+        "points": stats.num_points,
+        "width": width,
+        "height": height,
+        "organized": height > 1,
+        "data": format!("{:?}", data).to_lowercase(),
+        "viewpoint": [
+            viewpoint.tx, viewpoint.ty, viewpoint.tz,
+            viewpoint.qw, viewpoint.qx, viewpoint.qy, viewpoint.qz,
+        ],
+        "non_finite_points": stats.num_non_finite_points,
+        "zero_range_points": stats.num_zero_range_points,
+        "bbox": bbox,
+        "fields": fields,
+        "stats": column_stats,
+    });
+
+    println!("{}", serde_json::to_string_pretty(&output)?);
+    Ok(())
+}
+
+fn extend_field_values(field: &Field, values: &mut Vec<f64>) {
+    match field {
+        Field::I8(vec) => values.extend(vec.iter().map(|&val| val as f64)),
+        Field::I16(vec) => values.extend(vec.iter().map(|&val| val as f64)),
+        Field::I32(vec) => values.extend(vec.iter().map(|&val| val as f64)),
+        Field::U8(vec) => values.extend(vec.iter().map(|&val| val as f64)),
+        Field::U16(vec) => values.extend(vec.iter().map(|&val| val as f64)),
+        Field::U32(vec) => values.extend(vec.iter().map(|&val| val as f64)),
+        Field::F32(vec) => values.extend(vec.iter().map(|&val| val as f64)),
+        Field::F64(vec) => values.extend(vec.iter().copied()),
+    }
+}
+
+/// The statistics of a point cloud accumulated point by point.
+struct PcdStats {
+    num_points: usize,
+    /// The number of points having NaN or infinite coordinates.
+    num_non_finite_points: usize,
+    /// The number of points located at the origin, which usually
+    /// stands for missing returns.
+    num_zero_range_points: usize,
+    /// The bounding box of finite points, excluding zero-range points.
+    bbox: Option<([f64; 3], [f64; 3])>,
+    columns: Vec<ColumnStats>,
+}
+
+impl PcdStats {
+    fn new(num_columns: usize) -> Self {
+        Self {
+            num_points: 0,
+            num_non_finite_points: 0,
+            num_zero_range_points: 0,
+            bbox: None,
+            columns: vec![ColumnStats::default(); num_columns],
+        }
+    }
+
+    fn push(&mut self, values: &[f64], xyz: Option<[f64; 3]>) {
+        self.num_points += 1;
+        self.columns
+            .iter_mut()
+            .zip(values)
+            .for_each(|(column, &value)| column.push(value));
+
+        let Some(xyz) = xyz else {
+            return;
+        };
+
+        if !xyz.iter().all(|val| val.is_finite()) {
+            self.num_non_finite_points += 1;
+        } else if xyz.iter().all(|&val| val == 0.0) {
+            self.num_zero_range_points += 1;
+        } else {
+            let (min, max) = self.bbox.get_or_insert((xyz, xyz));
+            min.iter_mut()
+                .zip(max.iter_mut())
+                .zip(xyz)
+                .for_each(|((min, max), val)| {
+                    *min = min.min(val);
+                    *max = max.max(val);
+                });
+        }
+    }
+}
+
+/// The statistics of a column. The mean and variance are computed by
+/// Welford's algorithm over finite values.
+#[derive(Debug, Clone, Default)]
+struct ColumnStats {
+    num_finite: usize,
+    num_nan: usize,
+    num_inf: usize,
+    min: f64,
+    max: f64,
+    mean: f64,
+    m2: f64,
+}
+
+impl ColumnStats {
+    fn push(&mut self, value: f64) {
+        if value.is_nan() {
+            self.num_nan += 1;
+            return;
+        }
+        if value.is_infinite() {
+            self.num_inf += 1;
+            return;
+        }
+
+        if self.num_finite == 0 {
+            self.min = value;
+            self.max = value;
+        } else {
+            self.min = self.min.min(value);
+            self.max = self.max.max(value);
+        }
+
+        self.num_finite += 1;
+        let delta = value - self.mean;
+        self.mean += delta / self.num_finite as f64;
+        self.m2 += delta * (value - self.mean);
+    }
+
+    fn min(&self) -> Option<f64> {
+        (self.num_finite > 0).then_some(self.min)
+    }
+
+    fn max(&self) -> Option<f64> {
+        (self.num_finite > 0).then_some(self.max)
+    }
+
+    fn mean(&self) -> Option<f64> {
+        (self.num_finite > 0).then_some(self.mean)
+    }
+
+    /// Gives the population standard deviation.
+    fn stddev(&self) -> Option<f64> {
+        (self.num_finite > 0).then(|| (self.m2 / self.num_finite as f64).sqrt())
+    }
+}
+
+fn info_velodyne_pcap(file: &Path, filter: &PacketFilter, json: bool) -> Result<()> {
+    let mut capture = CaptureReader::open(file, filter)?;
+    let mut stats = PcapStats::default();
+    let mut comments: Vec<PacketComment> = vec![];
+    let mut interface_packets: BTreeMap<u32, usize> = BTreeMap::new();
+
+    while let Some(packet) = capture.next_packet()? {
+        stats.push(packet.udp(), packet.time);
+        *interface_packets.entry(packet.interface).or_default() += 1;
+
+        comments.extend(packet.comments.iter().map(|comment| PacketComment {
+            packet: stats.num_packets,
+            time: packet.time,
+            comment: comment.clone(),
+        }));
+    }
+
+    let interfaces = capture.interfaces();
+    let section_comments = capture.section_comments();
+
+    // Count frames with the model and return mode in the first data
+    // packet.
+    let model = stats
+        .factory_bytes
+        .and_then(|(_, product_id)| velodyne_product_id_from_byte(product_id));
+    let mode = stats
+        .factory_bytes
+        .and_then(|(return_mode, _)| velodyne_return_mode_from_byte(return_mode));
+    let num_frames = match (model, mode) {
+        (Some(model), Some(mode)) => {
+            let lidar = SpinningLidar::Velodyne {
+                model,
+                calibration: None,
+            };
+            match count_frames(file, filter, &lidar, VelodyneReturnMode(mode)) {
+                Ok(num_frames) => Some(num_frames),
+                Err(err) => {
+                    eprintln!("Warning: unable to count the frames: {err}");
+                    None
+                }
+            }
+        }
+        _ => None,
+    };
+
+    let duration = match (stats.first_time, stats.last_time) {
+        (Some(first), Some(last)) => last.saturating_sub(first),
+        _ => Duration::ZERO,
+    };
+    let packet_rate =
+        (!duration.is_zero()).then(|| stats.num_packets as f64 / duration.as_secs_f64());
+    let format_time = |time: Option<Duration>| utils::format_time(time?);
+    let first_time = format_time(stats.first_time);
+    let last_time = format_time(stats.last_time);
+    let total_gap: Duration = stats.gaps.iter().map(|gap| gap.length).sum();
+
+    let linktype_text = |linktype: u16| match linktype_name(linktype) {
+        Some(name) => format!("{linktype} ({name})"),
+        None => linktype.to_string(),
+    };
+
+    if json {
+        let interfaces: Vec<_> = interfaces
+            .iter()
+            .map(|interface| {
+                let Interface {
+                    id,
+                    linktype,
+                    ref name,
+                    ref description,
+                    ..
+                } = *interface;
+
+                json!({
+                    "id": id,
+                    "name": name,
+                    "description": description,
+                    "linktype": linktype,
+                    "linktype_name": linktype_name(linktype),
+                    "packets": interface_packets.get(&id).copied().unwrap_or(0),
+                })
+            })
+            .collect();
+        let packet_comments: Vec<_> = comments
+            .iter()
+            .map(|comment| {
+                json!({
+                    "packet": comment.packet,
+                    "timestamp": format_time(Some(comment.time)),
+                    "comment": comment.comment,
+                })
+            })
+            .collect();
+        let sources: Vec<_> = stats
+            .sources
+            .iter()
+            .map(|(&(source, port), source_stats)| {
+                json!({
+                    "source": source.to_string(),
+                    "port": port,
+                    "packets": source_stats.num_packets,
+                    "data_packets": source_stats.num_data_packets,
+                    "position_packets": source_stats.num_position_packets,
+                })
+            })
+            .collect();
+        let gaps: Vec<_> = stats
+            .gaps
+            .iter()
+            .map(|gap| {
+                json!({
+                    "source": gap.source.to_string(),
+                    "port": gap.port,
+                    "start": format_time(Some(gap.start)),
+                    "length": gap.length.as_secs_f64(),
+                })
+            })
+            .collect();
+
+        let output = json!({
+            "format": FileFormat::VelodynePcap.to_string(),
+            "packets": stats.num_packets,
+            "udp_packets": stats.num_udp_packets,
+            "data_packets": stats.num_data_packets,
+            "position_packets": stats.num_position_packets,
+            "product_id": model.map(|model| model.to_string()),
+            "return_mode": mode.map(|mode| format!("{:?}", mode).to_lowercase()),
+            "frames": num_frames,
+            "duration": duration.as_secs_f64(),
+            "first_timestamp": first_time,
+            "last_timestamp": last_time,
+            "packet_rate": packet_rate,
+            "sources": sources,
+            "gaps": gaps,
+            "total_gap": total_gap.as_secs_f64(),
+            "interfaces": interfaces,
+            "section_comments": section_comments,
+            "packet_comments": packet_comments,
+        });
+        println!("{}", serde_json::to_string_pretty(&output)?);
+        return Ok(());
+    }
+
+    let show = |value: Option<String>| value.unwrap_or_else(|| "-".to_string());
+
+    println!("format: {}", FileFormat::VelodynePcap);
+    println!("packets: {}", stats.num_packets);
+    println!("udp packets: {}", stats.num_udp_packets);
+    println!("data packets: {}", stats.num_data_packets);
+    println!("position packets: {}", stats.num_position_packets);
+    println!("product id: {}", show(model.map(|model| model.to_string())));
+    println!(
+        "return mode: {}",
+        show(mode.map(|mode| format!("{:?}", mode).to_lowercase()))
+    );
+    println!(
+        "frames: {}",
+        show(num_frames.map(|count| count.to_string()))
+    );
+    println!("duration: {:.3}s", duration.as_secs_f64());
+    println!("first timestamp: {}", show(first_time));
+    println!("last timestamp: {}", show(last_time));
+    println!(
+        "packet rate: {}",
+        show(packet_rate.map(|rate| format!("{:.1}/s", rate)))
+    );
+    println!(
+        "gaps: {} ({:.3}s in total)",
+        stats.gaps.len(),
+        total_gap.as_secs_f64()
+    );
+    println!();
+
+    println!("interface\tname\tlinktype\tpackets\tdescription");
+    interfaces.iter().for_each(|interface| {
+        println!(
+            "{}\t{}\t{}\t{}\t{}",
+            interface.id,
+            show(interface.name.clone()),
+            linktype_text(interface.linktype),
+            interface_packets.get(&interface.id).copied().unwrap_or(0),
+            show(interface.description.clone())
+        );
+    });
+    println!();
+
+    println!("source\tport\tpackets\tdata\tposition");
+    stats
+        .sources
+        .iter()
+        .for_each(|(&(source, port), source_stats)| {
+            println!(
+                "{}\t{}\t{}\t{}\t{}",
+                source,
+                port,
+                source_stats.num_packets,
+                source_stats.num_data_packets,
+                source_stats.num_position_packets
+            );
+        });
+
+    if !stats.gaps.is_empty() {
+        println!();
+        println!("gap start\tlength\tsource\tport");
+        stats.gaps.iter().for_each(|gap| {
+            println!(
+                "{}\t{:.3}s\t{}\t{}",
+                show(format_time(Some(gap.start))),
+                gap.length.as_secs_f64(),
+                gap.source,
+                gap.port
+            );
+        });
+    }
+
+    if !section_comments.is_empty() || !comments.is_empty() {
+        println!();
+        println!("packet\ttimestamp\tcomment");
+        section_comments.iter().for_each(|comment| {
+            println!("-\t-\t{comment}");
+        });
+        comments.iter().for_each(|comment| {
+            println!(
+                "{}\t{}\t{}",
+                comment.packet,
+                show(format_time(Some(comment.time))),
+                comment.comment
+            );
+        });
+    }
 
     Ok(())
 }
+
+/// Counts the frames of a Velodyne pcap file by the frame index. The
+/// index is loaded if up to date, or is built otherwise without saving
+/// it, so that inspecting a capture writes nothing next to it.
+fn count_frames(
+    file: &Path,
+    filter: &PacketFilter,
+    lidar: &SpinningLidar,
+    mode: VelodyneReturnMode,
+) -> Result<usize> {
+    let split = FrameSplit::default();
+    let index = match load_frame_index(file, filter, lidar, mode, &split)? {
+        Some(index) => index,
+        None => build_frame_index(file, filter, lidar, mode, &split)?,
+    };
+    Ok(index.frames.len())
+}
+
+/// A comment attached to a packet in a pcapng file.
+#[derive(Debug)]
+struct PacketComment {
+    /// The 1-based number of the packet among the packets read.
+    packet: usize,
+    time: Duration,
+    comment: String,
+}
+
+/// The statistics of a pcap file accumulated packet by packet.
+#[derive(Debug, Default)]
+struct PcapStats {
+    num_packets: usize,
+    num_udp_packets: usize,
+    num_data_packets: usize,
+    num_position_packets: usize,
+    first_time: Option<Duration>,
+    last_time: Option<Duration>,
+    /// The return mode and product ID bytes of the first data packet.
+    factory_bytes: Option<(u8, u8)>,
+    /// The statistics indexed by the source address and the
+    /// destination port.
+    sources: BTreeMap<(SocketAddrV4, u16), SourceStats>,
+    gaps: Vec<Gap>,
+}
+
+impl PcapStats {
+    fn push(&mut self, packet: Option<UdpPacket<'_>>, time: Duration) {
+        self.num_packets += 1;
+        self.first_time.get_or_insert(time);
+        self.last_time = Some(time);
+
+        let Some(packet) = packet else {
+            return;
+        };
+        self.num_udp_packets += 1;
+
+        let key = (packet.source, packet.destination.port());
+        let source = self.sources.entry(key).or_default();
+        source.num_packets += 1;
+
+        match VelodynePacket::classify(packet.payload) {
+            Some(VelodynePacket::Data {
+                return_mode,
+                product_id,
+            }) => {
+                self.num_data_packets += 1;
+                self.factory_bytes.get_or_insert((return_mode, product_id));
+                source.num_data_packets += 1;
+
+                if let Some(prev) = source.last_data_time {
+                    let interval = packet.time.saturating_sub(prev);
+                    if interval >= GAP_THRESHOLD {
+                        self.gaps.push(Gap {
+                            source: packet.source,
+                            port: packet.destination.port(),
+                            start: prev,
+                            length: interval,
+                        });
+                    }
+                }
+                source.last_data_time = Some(packet.time);
+            }
+            Some(VelodynePacket::Position) => {
+                self.num_position_packets += 1;
+                source.num_position_packets += 1;
+            }
+            None => {}
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct SourceStats {
+    num_packets: usize,
+    num_data_packets: usize,
+    num_position_packets: usize,
+    last_data_time: Option<Duration>,
+}
+
+/// An interval without data packets from a source.
+#[derive(Debug)]
+struct Gap {
+    source: SocketAddrV4,
+    port: u16,
+    start: Duration,
+    length: Duration,
+}
@@ -1,15 +1,19 @@
-use crate::{opts::VelodyneReturnMode, types::BinPoint, utils::build_velodyne_config};
+use crate::{
+    capture::{CaptureReader, PacketFilter},
+    types::BinPoint,
+};
 use byteorder::{LittleEndian, ReadBytesExt};
-use eyre::{Context, Result};
-use pcd_format::LibpclPoint;
-use pcd_rs::DataKind;
+use eyre::{format_err, Context, Result};
+use pcd_format::{DynPoint, DynSchema};
+use pcd_rs::{DataKind, PcdSerialize};
 use std::{
     fs::File,
     io::{self, prelude::*, BufReader, BufWriter},
     iter,
     path::Path,
+    sync::Arc,
+    time::Duration,
 };
-use velodyne_lidar::{iter::frame_xyz_iter_from_file, ProductID};
 
 pub struct RawBinWriter {
     writer: Option<BufWriter<File>>,
@@ -144,7 +148,7 @@ where
     Ok(iter::from_fn(move || next().transpose()))
 }
 
-pub fn create_libpcl_pcd_file_single<P, I>(
+pub fn create_pcd_file_single<P, I, T>(
     points: I,
     pcd_file: P,
     width: usize,
@@ -152,7 +156,8 @@ pub fn create_libpcl_pcd_file_single<P, I>(
 ) -> Result<()>
 where
     P: AsRef<Path>,
-    I: IntoIterator<Item = [f32; 3]>,
+    I: IntoIterator<Item = T>,
+    T: PcdSerialize,
 {
     let mut writer = pcd_rs::WriterInit {
         width: width as u64,
@@ -163,126 +168,69 @@ where
     }
     .create(pcd_file)?;
 
-    points
-        .into_iter()
-        .map(|[x, y, z]| LibpclPoint { x, y, z, rgb: 0 })
-        .try_for_each(|point| -> Result<_> {
-            writer.push(&point)?;
-            Ok(())
-        })?;
+    points.into_iter().try_for_each(|point| -> Result<_> {
+        writer.push(&point)?;
+        Ok(())
+    })?;
     writer.finish()?;
 
     Ok(())
 }
 
-pub fn create_libpcl_pcd_file_dual<P1, P2, I>(
-    points: I,
-    pcd_file1: P1,
-    pcd_file2: P2,
-    width: usize,
-    height: usize,
-) -> Result<()>
-where
-    P1: AsRef<Path>,
-    P2: AsRef<Path>,
-    I: IntoIterator<Item = ([f32; 3], [f32; 3])>,
-{
-    let data_kind = DataKind::Binary;
-
-    let mut writer1 = pcd_rs::WriterInit {
-        width: width as u64,
-        height: height as u64,
-        viewpoint: Default::default(),
-        data_kind,
-        schema: None,
-    }
-    .create(pcd_file1)?;
-    let mut writer2 = pcd_rs::WriterInit {
-        width: width as u64,
-        height: height as u64,
-        viewpoint: Default::default(),
-        data_kind,
-        schema: None,
-    }
-    .create(pcd_file2)?;
-
-    let map_point = |[x, y, z]: [f32; 3]| LibpclPoint { x, y, z, rgb: 0 };
-    points
-        .into_iter()
-        .map(|(p1, p2)| (map_point(p1), map_point(p2)))
-        .try_for_each(|(p1, p2)| -> Result<_> {
-            writer1.push(&p1)?;
-            writer2.push(&p2)?;
-            Ok(())
-        })?;
-    writer1.finish()?;
-    writer2.finish()?;
-
-    Ok(())
-}
-
 pub fn create_raw_bin_file_single<P, I>(points: I, bin_file: P) -> Result<()>
 where
     P: AsRef<Path>,
-    I: IntoIterator<Item = [f32; 3]>,
+    I: IntoIterator<Item = [f32; 4]>,
 {
     let mut writer = RawBinWriter::from_path(bin_file)?;
 
-    for [x, y, z] in points {
-        writer.push([x, y, z, 0.0])?;
+    for point in points {
+        writer.push(point)?;
     }
 
     writer.finish()?;
     Ok(())
 }
 
-pub fn create_raw_bin_file_dual<P1, P2, I>(points: I, bin_file1: P1, bin_file2: P2) -> Result<()>
+pub fn create_pcd_reader<P>(
+    input_path: P,
+) -> Result<pcd_rs::Reader<pcd_rs::DynRecord, BufReader<File>>>
 where
-    P1: AsRef<Path>,
-    P2: AsRef<Path>,
-    I: IntoIterator<Item = ([f32; 3], [f32; 3])>,
+    P: AsRef<Path>,
 {
-    let mut writer1 = RawBinWriter::from_path(bin_file1)?;
-    let mut writer2 = RawBinWriter::from_path(bin_file2)?;
-
-    points.into_iter().try_for_each(|(p1, p2)| -> Result<_> {
-        {
-            let [x, y, z] = p1;
-            writer1.push([x, y, z, 0.0])?;
-        }
-
-        {
-            let [x, y, z] = p2;
-            writer2.push([x, y, z, 0.0])?;
-        }
-        Ok(())
-    })?;
-
-    writer1.finish()?;
-    writer2.finish()?;
-
-    Ok(())
+    let reader = pcd_rs::Reader::open(input_path)?;
+    Ok(reader)
 }
 
-pub fn count_frames_in_velodyne_pcap<P>(
-    path: P,
-    model: ProductID,
-    mode: VelodyneReturnMode,
-) -> Result<usize>
+/// Opens a PCD file of any schema and gives the header along with the
+/// points bound to the schema in the header.
+pub fn create_dyn_point_reader<P>(
+    input_path: P,
+) -> Result<(pcd_rs::PcdMeta, impl Iterator<Item = Result<DynPoint>>)>
 where
     P: AsRef<Path>,
 {
-    let config = build_velodyne_config(model, mode.0)?;
-    let count = frame_xyz_iter_from_file(config, path)?.count();
-    Ok(count)
+    let input_path = input_path.as_ref();
+    let reader = create_pcd_reader(input_path)?;
+    let meta = reader.meta().clone();
+    let schema = DynSchema::new(&meta.field_defs)
+        .wrap_err_with(|| format!("unable to read the file {}", input_path.display()))?;
+    let schema = Arc::new(schema);
+
+    let points =
+        reader.map(move |record| -> Result<_> { Ok(DynPoint::new(schema.clone(), record?)) });
+    Ok((meta, points))
 }
 
-pub fn create_pcd_reader<P>(
-    input_path: P,
-) -> Result<pcd_rs::Reader<pcd_rs::DynRecord, BufReader<File>>>
+/// Gives the capture time of the first packet accepted by the filter
+/// in the pcap file since the Unix epoch.
+pub fn first_packet_time_in_pcap<P>(path: P, filter: &PacketFilter) -> Result<Duration>
 where
     P: AsRef<Path>,
 {
-    let reader = pcd_rs::Reader::open(input_path)?;
-    Ok(reader)
+    let path = path.as_ref();
+    let packet = CaptureReader::open(path, filter)?
+        .next_packet()?
+        .ok_or_else(|| format_err!("no packets found in '{}'", path.display()))?;
+    Ok(packet.time)
 }
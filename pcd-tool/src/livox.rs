@@ -0,0 +1,860 @@
+//! Reading of Livox .lvx and .lvx2 files.
+//!
+//! A file consists of the headers, the device information blocks and
+//! a sequence of frames. Each frame holds the packages sent by the
+//! devices over the frame duration, which are the payloads of the UDP
+//! packets along with a shortened header.
+
+use chrono::NaiveDate;
+use eyre::{bail, ensure, format_err, Result};
+use nalgebra as na;
+use pcd_format::PcdPoint;
+use std::{
+    f64::consts::FRAC_PI_2,
+    fs::File,
+    io::{self, prelude::*, BufReader, SeekFrom},
+    path::Path,
+    time::Duration,
+};
+
+/// The signature at the start of a Livox file.
+const LIVOX_SIGNATURE: &[u8] = b"livox_tech";
+/// The magic code following the file version.
+const LIVOX_MAGIC_CODE: u32 = 0xac0e_a767;
+/// The size of the public header, which holds the signature, the
+/// version and the magic code.
+const PUBLIC_HEADER_SIZE: usize = 24;
+/// The size of the private header, which holds the frame duration
+/// and the device count.
+const PRIVATE_HEADER_SIZE: usize = 5;
+/// The size of the frame header, which holds the offsets of the
+/// current and next frames and the frame index.
+const FRAME_HEADER_SIZE: usize = 24;
+
+/// Checks the signature at the start of a file.
+pub fn is_livox_file(head: &[u8]) -> bool {
+    head.starts_with(LIVOX_SIGNATURE)
+}
+
+/// The major version of the file format.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LvxVersion {
+    /// The .lvx format written by Livox Viewer for the Mid and Avia
+    /// series.
+    V1,
+    /// The .lvx2 format written by Livox Viewer 2 for HAP and Mid-360.
+    V2,
+}
+
+impl LvxVersion {
+    fn device_info_size(self) -> usize {
+        match self {
+            Self::V1 => 59,
+            Self::V2 => 63,
+        }
+    }
+
+    fn package_header_size(self) -> usize {
+        match self {
+            Self::V1 => 19,
+            Self::V2 => 27,
+        }
+    }
+}
+
+/// A device recorded in the file.
+#[derive(Debug, Clone)]
+pub struct LivoxDevice {
+    /// The device index in .lvx files or the LiDAR ID in .lvx2 files,
+    /// by which the packages refer to the device.
+    pub id: u32,
+    pub lidar_sn: String,
+    /// The transformation from the device frame to the common frame.
+    /// It is `None` if the extrinsic parameters are disabled.
+    pub extrinsic: Option<na::Isometry3<f64>>,
+}
+
+/// The headers of a Livox file.
+#[derive(Debug, Clone)]
+pub struct LivoxHeader {
+    pub version: LvxVersion,
+    pub devices: Vec<LivoxDevice>,
+}
+
+impl LivoxHeader {
+    /// Reads the headers and device information blocks, leaving the
+    /// reader at the first frame.
+    fn read<R>(reader: &mut R) -> Result<Self>
+    where
+        R: Read,
+    {
+        let mut public = [0u8; PUBLIC_HEADER_SIZE];
+        reader.read_exact(&mut public)?;
+        ensure!(is_livox_file(&public), "not a Livox file");
+
+        let magic_code = u32::from_le_bytes(public[20..24].try_into().unwrap());
+        ensure!(
+            magic_code == LIVOX_MAGIC_CODE,
+            "invalid magic code {magic_code:#x}"
+        );
+        let version = match public[16] {
+            1 => LvxVersion::V1,
+            2 => LvxVersion::V2,
+            major => bail!("the Livox file version {major} is not supported"),
+        };
+
+        let mut private = [0u8; PRIVATE_HEADER_SIZE];
+        reader.read_exact(&mut private)?;
+        let num_devices = private[4] as usize;
+
+        let devices = (0..num_devices)
+            .map(|_| -> Result<_> {
+                let mut buf = vec![0u8; version.device_info_size()];
+                reader.read_exact(&mut buf)?;
+                Ok(parse_device_info(version, &buf))
+            })
+            .collect::<Result<_>>()?;
+
+        Ok(Self { version, devices })
+    }
+
+    fn first_frame_offset(&self) -> u64 {
+        let devices_size = self.devices.len() * self.version.device_info_size();
+        (PUBLIC_HEADER_SIZE + PRIVATE_HEADER_SIZE + devices_size) as u64
+    }
+}
+
+fn parse_device_info(version: LvxVersion, buf: &[u8]) -> LivoxDevice {
+    let read_f32 = |offset: usize| f32::from_le_bytes(buf[offset..offset + 4].try_into().unwrap());
+
+    let (id, extrinsic_offset) = match version {
+        LvxVersion::V1 => (buf[32] as u32, 34),
+        LvxVersion::V2 => (u32::from_le_bytes(buf[32..36].try_into().unwrap()), 38),
+    };
+    let lidar_sn = String::from_utf8_lossy(&buf[0..16])
+        .trim_end_matches('\0')
+        .to_string();
+
+    // The angles are in degrees and the offsets are in meters.
+    let extrinsic = (buf[extrinsic_offset] != 0).then(|| {
+        let [roll, pitch, yaw, x, y, z] =
+            [0, 1, 2, 3, 4, 5].map(|index| read_f32(extrinsic_offset + 1 + index * 4) as f64);
+        let rotation = na::UnitQuaternion::from_euler_angles(
+            roll.to_radians(),
+            pitch.to_radians(),
+            yaw.to_radians(),
+        );
+        na::Isometry3::from_parts(na::Translation3::new(x, y, z), rotation)
+    });
+
+    LivoxDevice {
+        id,
+        lidar_sn,
+        extrinsic,
+    }
+}
+
+/// A return measured by a Livox LiDAR.
+#[derive(Debug, Clone, Copy)]
+pub struct LivoxSample {
+    /// The Cartesian coordinates in meters in the common frame.
+    pub xyz: [f64; 3],
+    pub reflectivity: u8,
+    /// The confidence flags of the return. It is zero for the data
+    /// types without tags.
+    pub tag: u8,
+    /// The index of the device in the file headers.
+    pub device: usize,
+    /// The time of the return. It is the Unix time only if the sensor
+    /// clock is synchronized by PTP or GPS.
+    pub timestamp: Duration,
+}
+
+impl PcdPoint for LivoxSample {
+    fn x(&self) -> f64 {
+        self.xyz[0]
+    }
+
+    fn y(&self) -> f64 {
+        self.xyz[1]
+    }
+
+    fn z(&self) -> f64 {
+        self.xyz[2]
+    }
+
+    fn distance(&self) -> f64 {
+        let [x, y, z] = self.xyz;
+        (x.powi(2) + y.powi(2) + z.powi(2)).sqrt()
+    }
+
+    fn azimuthal_angle(&self) -> f64 {
+        self.xyz[1].atan2(self.xyz[0])
+    }
+
+    fn vertical_angle(&self) -> f64 {
+        FRAC_PI_2 - self.polar_angle()
+    }
+
+    fn polar_angle(&self) -> f64 {
+        let [x, y, z] = self.xyz;
+        (x.powi(2) + y.powi(2)).sqrt().atan2(z)
+    }
+
+    fn intensity(&self) -> Option<f64> {
+        Some(self.reflectivity as f64)
+    }
+
+    fn laser_id(&self) -> Option<u32> {
+        None
+    }
+
+    fn timestamp_ns(&self) -> Option<u32> {
+        Some(self.timestamp.as_nanos() as u32)
+    }
+
+    fn timestamp(&self) -> Option<f64> {
+        Some(self.timestamp.as_secs_f64())
+    }
+
+    fn reflectivity(&self) -> Option<f64> {
+        Some(self.reflectivity as f64)
+    }
+
+    fn tag(&self) -> Option<u8> {
+        Some(self.tag)
+    }
+}
+
+/// A frame of a Livox file. The samples of all devices are gathered
+/// in an unorganized point cloud.
+#[derive(Debug, Clone)]
+pub struct LivoxFrame {
+    /// The frame index recorded in the file.
+    pub index: u64,
+    pub samples: Vec<LivoxSample>,
+    /// The time of the earliest sample.
+    pub start_time: Duration,
+}
+
+/// Reads the headers of a Livox file.
+pub fn livox_header_from_file<P>(path: P) -> Result<LivoxHeader>
+where
+    P: AsRef<Path>,
+{
+    let mut reader = BufReader::new(File::open(path)?);
+    LivoxHeader::read(&mut reader)
+}
+
+/// Reads the frames in a Livox .lvx or .lvx2 file.
+///
+/// Returns without a range, which the sensor reports as the origin,
+/// are dropped.
+pub fn livox_frame_iter_from_file<P>(path: P) -> Result<impl Iterator<Item = Result<LivoxFrame>>>
+where
+    P: AsRef<Path>,
+{
+    let mut reader = BufReader::new(File::open(path)?);
+    let header = LivoxHeader::read(&mut reader)?;
+    let file_len = reader.get_ref().metadata()?.len();
+
+    let mut offset = header.first_frame_offset();
+    let mut finished = false;
+
+    let iter = std::iter::from_fn(move || -> Option<Result<LivoxFrame>> {
+        if finished || offset + FRAME_HEADER_SIZE as u64 > file_len {
+            return None;
+        }
+
+        let mut next = || -> Result<LivoxFrame> {
+            let mut frame_header = [0u8; FRAME_HEADER_SIZE];
+            reader.seek(SeekFrom::Start(offset))?;
+            reader.read_exact(&mut frame_header)?;
+
+            let read_u64 = |offset: usize| {
+                u64::from_le_bytes(frame_header[offset..offset + 8].try_into().unwrap())
+            };
+            let next_offset = read_u64(8);
+            let index = read_u64(16);
+
+            // The next offset of the last frame can be left unset when
+            // the recording is interrupted.
+            let body_offset = offset + FRAME_HEADER_SIZE as u64;
+            let end = if next_offset > offset && next_offset <= file_len {
+                ensure!(
+                    next_offset >= body_offset,
+                    "the frame {index} at offset {offset} has an invalid next offset {next_offset}"
+                );
+                next_offset
+            } else {
+                file_len
+            };
+            let mut body = vec![0u8; (end - body_offset) as usize];
+            reader.read_exact(&mut body)?;
+            offset = end;
+
+            let samples = decode_frame_body(&header, &body)
+                .map_err(|err| format_err!("unable to decode the frame {index}: {err}"))?;
+            let start_time = samples
+                .iter()
+                .map(|sample| sample.timestamp)
+                .min()
+                .unwrap_or_default();
+
+            Ok(LivoxFrame {
+                index,
+                samples,
+                start_time,
+            })
+        };
+
+        let result = next();
+        if result.is_err() {
+            finished = true;
+        }
+        Some(result)
+    });
+
+    Ok(iter)
+}
+
+/// The encoding of a point in a package.
+#[derive(Debug, Clone, Copy)]
+enum PointEncoding {
+    /// The x, y and z as integers of `coord_size` bytes, scaled by
+    /// `unit` meters.
+    Cartesian { coord_size: usize, unit: f64 },
+    /// The depth in millimeters and the zenith and azimuth angles in
+    /// 0.01 degrees.
+    Spherical,
+}
+
+/// The layout of the points of a data type.
+#[derive(Debug, Clone, Copy)]
+struct DataType {
+    encoding: PointEncoding,
+    has_tag: bool,
+    num_returns: usize,
+    point_size: usize,
+    /// The number of points in a package. It is `None` if the package
+    /// header gives the payload length.
+    num_points: Option<usize>,
+    /// The time between consecutive points.
+    point_interval: Duration,
+}
+
+impl DataType {
+    /// Looks up the data type. It gives `None` for the IMU data, and
+    /// fails for unknown data types.
+    fn of(version: LvxVersion, data_type: u8) -> Result<Option<Self>> {
+        use PointEncoding as E;
+
+        const MM: E = E::Cartesian {
+            coord_size: 4,
+            unit: 0.001,
+        };
+        const CM: E = E::Cartesian {
+            coord_size: 2,
+            unit: 0.01,
+        };
+        const INTERVAL_100K: Duration = Duration::from_micros(10);
+        const INTERVAL_240K: Duration = Duration::from_nanos(4_167);
+
+        let new = |encoding: PointEncoding,
+                   has_tag: bool,
+                   num_returns: usize,
+                   point_size: usize,
+                   num_points: Option<usize>,
+                   point_interval: Duration| Self {
+            encoding,
+            has_tag,
+            num_returns,
+            point_size,
+            num_points,
+            point_interval,
+        };
+
+        let data_type = match (version, data_type) {
+            (LvxVersion::V1, 0) => new(MM, false, 1, 13, Some(100), INTERVAL_100K),
+            (LvxVersion::V1, 1) => new(E::Spherical, false, 1, 9, Some(100), INTERVAL_100K),
+            (LvxVersion::V1, 2) => new(MM, true, 1, 14, Some(96), INTERVAL_240K),
+            (LvxVersion::V1, 3) => new(E::Spherical, true, 1, 10, Some(96), INTERVAL_240K),
+            (LvxVersion::V1, 4) => new(MM, true, 2, 28, Some(48), INTERVAL_240K),
+            (LvxVersion::V1, 5) => new(E::Spherical, true, 2, 16, Some(48), INTERVAL_240K),
+            (LvxVersion::V1, 6) => return Ok(None),
+            (LvxVersion::V1, 7) => new(MM, true, 3, 42, Some(30), INTERVAL_240K),
+            (LvxVersion::V1, 8) => new(E::Spherical, true, 3, 22, Some(30), INTERVAL_240K),
+            // The packages of .lvx2 files do not record the point
+            // interval. The points share the package timestamp.
+            (LvxVersion::V2, 0) => return Ok(None),
+            (LvxVersion::V2, 1) => new(MM, true, 1, 14, None, Duration::ZERO),
+            (LvxVersion::V2, 2) => new(CM, true, 1, 8, None, Duration::ZERO),
+            (LvxVersion::V2, 3) => new(E::Spherical, true, 1, 10, None, Duration::ZERO),
+            (_, data_type) => bail!("unknown data type {data_type}"),
+        };
+        Ok(Some(data_type))
+    }
+}
+
+/// Decodes the packages in the body of a frame.
+fn decode_frame_body(header: &LivoxHeader, body: &[u8]) -> Result<Vec<LivoxSample>> {
+    let version = header.version;
+    let header_size = version.package_header_size();
+    let mut samples = vec![];
+    let mut rest = body;
+
+    while !rest.is_empty() {
+        let package_header = rest.get(..header_size).ok_or_else(truncated)?;
+        let read_u32 = |offset: usize| {
+            u32::from_le_bytes(package_header[offset..offset + 4].try_into().unwrap())
+        };
+        let read_timestamp = |type_offset: usize, offset: usize| {
+            let bytes = package_header[offset..offset + 8].try_into().unwrap();
+            decode_timestamp(version, package_header[type_offset], bytes)
+        };
+
+        let (device_id, data_type, timestamp, length) = match version {
+            LvxVersion::V1 => (
+                package_header[0] as u32,
+                package_header[10],
+                read_timestamp(9, 11)?,
+                None,
+            ),
+            LvxVersion::V2 => (
+                read_u32(1),
+                package_header[17],
+                read_timestamp(6, 7)?,
+                Some(read_u32(18) as usize),
+            ),
+        };
+
+        // The IMU packages of .lvx files have a single 24-byte record.
+        let data_type_info = DataType::of(version, data_type)?;
+        let length = match (length, data_type_info) {
+            (Some(length), _) => length,
+            (None, Some(info)) => info.point_size * info.num_points.unwrap_or(0),
+            (None, None) => 24,
+        };
+        let payload = rest
+            .get(header_size..header_size + length)
+            .ok_or_else(truncated)?;
+        rest = &rest[header_size + length..];
+
+        let Some(info) = data_type_info else {
+            continue;
+        };
+        let device = header
+            .devices
+            .iter()
+            .position(|device| device.id == device_id)
+            .ok_or_else(|| format_err!("unknown device {device_id}"))?;
+        let extrinsic = header.devices[device].extrinsic;
+
+        let points = payload.chunks_exact(info.point_size).enumerate();
+        for (index, point) in points {
+            let timestamp = timestamp + info.point_interval * index as u32;
+
+            for (xyz, reflectivity, tag) in decode_point(&info, point) {
+                if xyz == [0.0; 3] {
+                    continue;
+                }
+                let xyz = match extrinsic {
+                    Some(extrinsic) => {
+                        let [x, y, z] = xyz;
+                        let point = extrinsic * na::Point3::new(x, y, z);
+                        [point.x, point.y, point.z]
+                    }
+                    None => xyz,
+                };
+
+                samples.push(LivoxSample {
+                    xyz,
+                    reflectivity,
+                    tag,
+                    device,
+                    timestamp,
+                });
+            }
+        }
+    }
+
+    Ok(samples)
+}
+
+/// Decodes the returns of a point into the Cartesian coordinates in
+/// meters, the reflectivity and the tag.
+fn decode_point(info: &DataType, point: &[u8]) -> Vec<([f64; 3], u8, u8)> {
+    let read_i16 = |offset: usize| i16::from_le_bytes([point[offset], point[offset + 1]]);
+    let read_u16 = |offset: usize| u16::from_le_bytes([point[offset], point[offset + 1]]);
+    let read_i32 =
+        |offset: usize| i32::from_le_bytes(point[offset..offset + 4].try_into().unwrap());
+    let read_u32 =
+        |offset: usize| u32::from_le_bytes(point[offset..offset + 4].try_into().unwrap());
+    let tag_at = |offset: usize| if info.has_tag { point[offset] } else { 0 };
+
+    match info.encoding {
+        PointEncoding::Cartesian { coord_size, unit } => {
+            let return_size = info.point_size / info.num_returns;
+
+            (0..info.num_returns)
+                .map(|index| {
+                    let offset = index * return_size;
+                    let coord = |axis: usize| {
+                        let offset = offset + axis * coord_size;
+                        let value = match coord_size {
+                            2 => read_i16(offset) as f64,
+                            _ => read_i32(offset) as f64,
+                        };
+                        value * unit
+                    };
+                    let attrs = offset + 3 * coord_size;
+
+                    (
+                        [coord(0), coord(1), coord(2)],
+                        point[attrs],
+                        tag_at(attrs + 1),
+                    )
+                })
+                .collect()
+        }
+        PointEncoding::Spherical => {
+            // A single return starts with the depth, while multiple
+            // returns share the angles at the start of the point.
+            let (theta, phi, returns): (_, _, Vec<_>) = if info.num_returns == 1 {
+                (read_u16(4), read_u16(6), vec![(0, 8)])
+            } else {
+                let returns = (0..info.num_returns)
+                    .map(|index| {
+                        let offset = 4 + index * 6;
+                        (offset, offset + 4)
+                    })
+                    .collect();
+                (read_u16(0), read_u16(2), returns)
+            };
+            let theta = (theta as f64 / 100.0).to_radians();
+            let phi = (phi as f64 / 100.0).to_radians();
+
+            returns
+                .into_iter()
+                .map(|(depth_offset, attrs)| {
+                    let depth = read_u32(depth_offset) as f64 / 1000.0;
+                    let xyz = [
+                        depth * theta.sin() * phi.cos(),
+                        depth * theta.sin() * phi.sin(),
+                        depth * theta.cos(),
+                    ];
+                    (xyz, point[attrs], tag_at(attrs + 1))
+                })
+                .collect()
+        }
+    }
+}
+
+/// Decodes the timestamp of a package by the timestamp type.
+///
+/// The timestamps synchronized to GPS in .lvx files hold the UTC date,
+/// the hour and the microseconds since the hour. The other supported
+/// types hold the nanoseconds since the sync source started, which is
+/// the Unix epoch for PTP and GPS sources.
+fn decode_timestamp(version: LvxVersion, timestamp_type: u8, bytes: [u8; 8]) -> Result<Duration> {
+    let nanos = || Duration::from_nanos(u64::from_le_bytes(bytes));
+
+    let timestamp = match (version, timestamp_type) {
+        // No sync source, PTP or PPS
+        (LvxVersion::V1, 0 | 1 | 4) => nanos(),
+        (LvxVersion::V1, 3) => {
+            let [year, month, day, hour, micros @ ..] = bytes;
+            let micros = u32::from_le_bytes(micros);
+            let Some(hour_start) =
+                NaiveDate::from_ymd_opt(2000 + year as i32, month as u32, day as u32)
+                    .and_then(|date| date.and_hms_opt(hour as u32, 0, 0))
+            else {
+                bail!("invalid UTC timestamp {year:02}-{month:02}-{day:02} {hour:02}h");
+            };
+            let secs = u64::try_from(hour_start.and_utc().timestamp())?;
+            Duration::from_secs(secs) + Duration::from_micros(micros as u64)
+        }
+        // No sync source, gPTP or GPS
+        (LvxVersion::V2, 0..=2) => nanos(),
+        (_, timestamp_type) => bail!("unsupported timestamp type {timestamp_type}"),
+    };
+    Ok(timestamp)
+}
+
+fn truncated() -> eyre::Report {
+    io::Error::from(io::ErrorKind::UnexpectedEof).into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use approx::assert_abs_diff_eq;
+
+    /// Builds the headers of a file holding the devices, given by the
+    /// ID, the serial number and the extrinsic roll, pitch, yaw, x, y
+    /// and z if enabled.
+    fn file_header(version: LvxVersion, devices: &[(u32, &str, Option<[f32; 6]>)]) -> Vec<u8> {
+        let major = match version {
+            LvxVersion::V1 => 1,
+            LvxVersion::V2 => 2,
+        };
+        let mut bytes = LIVOX_SIGNATURE.to_vec();
+        bytes.resize(16, 0);
+        bytes.extend([major, 1, 0, 0]);
+        bytes.extend(LIVOX_MAGIC_CODE.to_le_bytes());
+        bytes.extend(50u32.to_le_bytes());
+        bytes.push(devices.len() as u8);
+
+        for &(id, lidar_sn, extrinsic) in devices {
+            let mut device = vec![0; version.device_info_size()];
+            device[..lidar_sn.len()].copy_from_slice(lidar_sn.as_bytes());
+            let extrinsic_offset = match version {
+                LvxVersion::V1 => {
+                    device[32] = id as u8;
+                    34
+                }
+                LvxVersion::V2 => {
+                    device[32..36].copy_from_slice(&id.to_le_bytes());
+                    38
+                }
+            };
+            if let Some(values) = extrinsic {
+                device[extrinsic_offset] = 1;
+                for (index, value) in values.into_iter().enumerate() {
+                    let offset = extrinsic_offset + 1 + index * 4;
+                    device[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+                }
+            }
+            bytes.extend(device);
+        }
+        bytes
+    }
+
+    fn header(version: LvxVersion, devices: &[(u32, &str, Option<[f32; 6]>)]) -> LivoxHeader {
+        let bytes = file_header(version, devices);
+        LivoxHeader::read(&mut bytes.as_slice()).unwrap()
+    }
+
+    /// Builds a .lvx package of the device with the timestamp in
+    /// nanoseconds.
+    fn v1_package(device: u8, data_type: u8, timestamp: u64, payload: &[u8]) -> Vec<u8> {
+        let mut bytes = vec![0; LvxVersion::V1.package_header_size()];
+        bytes[0] = device;
+        bytes[10] = data_type;
+        bytes[11..19].copy_from_slice(&timestamp.to_le_bytes());
+        bytes.extend(payload);
+        bytes
+    }
+
+    /// Builds a .lvx2 package of the LiDAR with the timestamp in
+    /// nanoseconds.
+    fn v2_package(lidar_id: u32, data_type: u8, timestamp: u64, payload: &[u8]) -> Vec<u8> {
+        let mut bytes = vec![0; LvxVersion::V2.package_header_size()];
+        bytes[1..5].copy_from_slice(&lidar_id.to_le_bytes());
+        bytes[6] = 2;
+        bytes[7..15].copy_from_slice(&timestamp.to_le_bytes());
+        bytes[17] = data_type;
+        bytes[18..22].copy_from_slice(&(payload.len() as u32).to_le_bytes());
+        bytes.extend(payload);
+        bytes
+    }
+
+    /// Encodes a point in millimeters followed by the attribute bytes.
+    fn mm_point(xyz: [i32; 3], attrs: &[u8]) -> Vec<u8> {
+        let mut bytes: Vec<u8> = xyz.into_iter().flat_map(i32::to_le_bytes).collect();
+        bytes.extend(attrs);
+        bytes
+    }
+
+    #[test]
+    fn read_header_and_devices() {
+        let mut bytes = file_header(
+            LvxVersion::V2,
+            &[
+                (7, "47MDL9T0020193", None),
+                (
+                    0x0201_a8c0,
+                    "3JEDK380010X",
+                    Some([0.0, 0.0, 90.0, 1.0, 2.0, 3.0]),
+                ),
+            ],
+        );
+        bytes.extend(b"frame");
+
+        let mut reader = bytes.as_slice();
+        let header = LivoxHeader::read(&mut reader).unwrap();
+        assert_eq!(reader, b"frame");
+        assert_eq!(header.version, LvxVersion::V2);
+        assert_eq!(header.first_frame_offset(), 24 + 5 + 2 * 63);
+
+        let [first, second] = &header.devices[..] else {
+            panic!("expect 2 devices");
+        };
+        assert_eq!(first.id, 7);
+        assert_eq!(first.lidar_sn, "47MDL9T0020193");
+        assert!(first.extrinsic.is_none());
+
+        // The yaw turns the x axis to the y axis before the
+        // translation.
+        assert_eq!(second.id, 0x0201_a8c0);
+        assert_eq!(second.lidar_sn, "3JEDK380010X");
+        let point = second.extrinsic.unwrap() * na::Point3::new(1.0, 0.0, 0.0);
+        assert_abs_diff_eq!(point, na::Point3::new(1.0, 3.0, 3.0), epsilon = 1e-6);
+    }
+
+    #[test]
+    fn reject_invalid_header() {
+        let read = |bytes: Vec<u8>| LivoxHeader::read(&mut bytes.as_slice());
+        let valid = file_header(LvxVersion::V1, &[(0, "SN", None)]);
+        assert!(read(valid.clone()).is_ok());
+
+        let mut bytes = valid.clone();
+        bytes[0] = b'L';
+        assert!(read(bytes).is_err());
+
+        let mut bytes = valid.clone();
+        bytes[20] ^= 0xff;
+        assert!(read(bytes).is_err());
+
+        let mut bytes = valid.clone();
+        bytes[16] = 3;
+        assert!(read(bytes).is_err());
+
+        // The device information is cut short.
+        assert!(read(valid[..valid.len() - 1].to_vec()).is_err());
+    }
+
+    #[test]
+    fn decode_lvx_cartesian_packages() {
+        let header = header(LvxVersion::V1, &[(0, "SN0", None), (1, "SN1", None)]);
+
+        // The first and third of the 100 points have a return.
+        let mut payload = mm_point([1000, -2000, 500], &[10]);
+        payload.extend(mm_point([0; 3], &[30]));
+        payload.extend(mm_point([0, 0, 1000], &[20]));
+        payload.resize(100 * 13, 0);
+
+        let mut body = v1_package(1, 0, 1_000_000_000, &payload);
+        // IMU packages are skipped.
+        body.extend(v1_package(0, 6, 0, &[0xff; 24]));
+        body.extend(v1_package(0, 0, 2_000_000_000, &[0; 100 * 13]));
+
+        let samples = decode_frame_body(&header, &body).unwrap();
+        assert_eq!(samples.len(), 2);
+
+        let sample = &samples[0];
+        assert_abs_diff_eq!(&sample.xyz[..], &[1.0, -2.0, 0.5][..], epsilon = 1e-9);
+        assert_eq!(sample.reflectivity, 10);
+        assert_eq!(sample.tag, 0);
+        assert_eq!(sample.device, 1);
+        assert_eq!(sample.timestamp, Duration::from_secs(1));
+
+        // The points are 10 µs apart.
+        let sample = &samples[1];
+        assert_abs_diff_eq!(&sample.xyz[..], &[0.0, 0.0, 1.0][..], epsilon = 1e-9);
+        assert_eq!(sample.reflectivity, 20);
+        assert_eq!(
+            sample.timestamp,
+            Duration::from_secs(1) + Duration::from_micros(20)
+        );
+    }
+
+    #[test]
+    fn decode_lvx2_packages_with_extrinsic() {
+        let header = header(
+            LvxVersion::V2,
+            &[(0x1234_5678, "SN", Some([0.0, 0.0, 0.0, 1.0, 0.0, 0.0]))],
+        );
+
+        let mut payload = mm_point([1000, 0, 0], &[5, 3]);
+        payload.extend(mm_point([2000, 0, 0], &[6, 0]));
+        let body = v2_package(0x1234_5678, 1, 1_700_000_000_123_456_789, &payload);
+
+        let samples = decode_frame_body(&header, &body).unwrap();
+        assert_eq!(samples.len(), 2);
+
+        // The points share the package timestamp.
+        let timestamp = Duration::from_nanos(1_700_000_000_123_456_789);
+        assert_abs_diff_eq!(&samples[0].xyz[..], &[2.0, 0.0, 0.0][..], epsilon = 1e-9);
+        assert_eq!(samples[0].reflectivity, 5);
+        assert_eq!(samples[0].tag, 3);
+        assert_eq!(samples[0].timestamp, timestamp);
+        assert_abs_diff_eq!(&samples[1].xyz[..], &[3.0, 0.0, 0.0][..], epsilon = 1e-9);
+        assert_eq!(samples[1].timestamp, timestamp);
+    }
+
+    #[test]
+    fn decode_spherical_points() {
+        // A single return with the depth of 2 m at the zenith and
+        // azimuth angles of 90 degrees.
+        let info = DataType::of(LvxVersion::V1, 1).unwrap().unwrap();
+        let mut point = 2000u32.to_le_bytes().to_vec();
+        point.extend(9000u16.to_le_bytes());
+        point.extend(9000u16.to_le_bytes());
+        point.push(7);
+
+        let [(xyz, reflectivity, tag)] = decode_point(&info, &point)[..] else {
+            panic!("expect a single return");
+        };
+        assert_abs_diff_eq!(&xyz[..], &[0.0, 2.0, 0.0][..], epsilon = 1e-9);
+        assert_eq!((reflectivity, tag), (7, 0));
+
+        // Dual returns share the angles at the start of the point.
+        let info = DataType::of(LvxVersion::V1, 5).unwrap().unwrap();
+        let mut point = 0u16.to_le_bytes().to_vec();
+        point.extend(0u16.to_le_bytes());
+        for (depth, reflectivity, tag) in [(1000u32, 1, 2), (3000, 3, 4)] {
+            point.extend(depth.to_le_bytes());
+            point.extend([reflectivity, tag]);
+        }
+
+        let returns = decode_point(&info, &point);
+        assert_eq!(returns.len(), 2);
+        assert_abs_diff_eq!(&returns[0].0[..], &[0.0, 0.0, 1.0][..], epsilon = 1e-9);
+        assert_eq!((returns[0].1, returns[0].2), (1, 2));
+        assert_abs_diff_eq!(&returns[1].0[..], &[0.0, 0.0, 3.0][..], epsilon = 1e-9);
+        assert_eq!((returns[1].1, returns[1].2), (3, 4));
+    }
+
+    #[test]
+    fn reject_invalid_packages() {
+        let header = header(LvxVersion::V1, &[(0, "SN", None)]);
+        let payload = [0; 100 * 13];
+
+        let body = v1_package(1, 0, 0, &payload);
+        assert!(decode_frame_body(&header, &body).is_err());
+
+        let body = v1_package(0, 0, 0, &payload[1..]);
+        assert!(decode_frame_body(&header, &body).is_err());
+
+        let body = v1_package(0, 9, 0, &payload);
+        assert!(decode_frame_body(&header, &body).is_err());
+
+        let body = &v1_package(0, 0, 0, &payload)[..10];
+        assert!(decode_frame_body(&header, body).is_err());
+    }
+
+    #[test]
+    fn decode_timestamp_by_type() {
+        let nanos = 1_700_000_000_000_000_001u64.to_le_bytes();
+        for (version, timestamp_type) in [(LvxVersion::V1, 1), (LvxVersion::V2, 2)] {
+            assert_eq!(
+                decode_timestamp(version, timestamp_type, nanos).unwrap(),
+                Duration::from_nanos(1_700_000_000_000_000_001)
+            );
+        }
+
+        // 2023-10-16T12:00:01.5Z synchronized to GPS.
+        let [a, b, c, d] = 1_500_000u32.to_le_bytes();
+        let utc = [23, 10, 16, 12, a, b, c, d];
+        assert_eq!(
+            decode_timestamp(LvxVersion::V1, 3, utc).unwrap(),
+            Duration::from_millis(1_697_457_601_500)
+        );
+
+        let invalid_utc = [23, 13, 16, 12, a, b, c, d];
+        assert!(decode_timestamp(LvxVersion::V1, 3, invalid_utc).is_err());
+        assert!(decode_timestamp(LvxVersion::V1, 2, nanos).is_err());
+        assert!(decode_timestamp(LvxVersion::V2, 3, nanos).is_err());
+    }
+}
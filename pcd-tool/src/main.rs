@@ -1,22 +1,42 @@
+mod calibration;
+mod capture;
 mod convert;
 mod dump;
+mod hesai;
+mod index;
 mod info;
 mod io;
+mod livox;
 mod opts;
+mod ouster;
+mod packet;
+mod position;
+mod select;
 mod show;
+mod sources;
+mod spinning;
+mod split;
+mod template;
 mod types;
 mod utils;
+mod velodyne;
 
 use clap::Parser;
 use eyre::Result;
-use opts::{Info, Opts};
+use opts::Opts;
 
 fn main() -> Result<()> {
     let opts = Opts::parse();
 
     match opts {
-        Opts::Info(Info { file }) => {
-            crate::info::info(file)?;
+        Opts::Info(args) => {
+            crate::info::info(args)?;
+        }
+        Opts::Index(args) => {
+            crate::index::index(args)?;
+        }
+        Opts::Positions(args) => {
+            crate::position::positions(args)?;
         }
         Opts::Convert(args) => {
             crate::convert::convert(args)?;
@@ -1,13 +1,22 @@
-use crate::types::FileFormat;
+use crate::{
+    capture::InterfaceSelector,
+    hesai::HesaiModel,
+    sources::{parse_source_value, SourceSelector},
+    template::OutputTemplate,
+    types::FileFormat,
+};
+use chrono::{DateTime, NaiveTime, Timelike};
 use clap::Parser;
 use eyre::bail;
-use std::{path::PathBuf, str::FromStr};
+use std::{net::Ipv4Addr, num::NonZeroUsize, path::PathBuf, str::FromStr, time::Duration};
 use velodyne_lidar::{ProductID, ReturnMode};
 
 /// The Swiss army knife for point cloud data.
 #[derive(Debug, Clone, Parser)]
 pub enum Opts {
     Info(Info),
+    Index(Index),
+    Positions(Positions),
     Dump(Dump),
     Show(Show),
     Convert(Convert),
@@ -24,12 +33,105 @@ pub struct Dump {
     pub input: PathBuf,
 
     /// The Velodyne LiDAR model name.
+    ///
+    /// It is detected from the data packets if not set.
     #[clap(long)]
     pub velodyne_model: Option<ProductID>,
 
     /// The return mode configured on the Velodyne LiDAR.
+    ///
+    /// It is detected from the data packets if not set.
     #[clap(long)]
     pub velodyne_return_mode: Option<VelodyneReturnMode>,
+
+    /// The calibration file of the Velodyne LiDAR, either a VeloView
+    /// db.xml file or a ROS velodyne_pointcloud YAML file.
+    ///
+    /// The nominal laser angles of the model are used if not set.
+    #[clap(long)]
+    pub velodyne_calibration: Option<PathBuf>,
+
+    /// The metadata JSON file of the Ouster LiDAR.
+    ///
+    /// The .json file next to the input file is used if not set.
+    #[clap(long)]
+    pub ouster_metadata: Option<PathBuf>,
+
+    /// The Hesai LiDAR model name.
+    ///
+    /// It is detected from the data packets if not set.
+    #[clap(long)]
+    pub hesai_model: Option<HesaiModel>,
+
+    /// The angle correction CSV file of the Hesai LiDAR.
+    ///
+    /// The nominal laser angles of the model are used if not set.
+    #[clap(long)]
+    pub hesai_angles: Option<PathBuf>,
+
+    /// The interface of a pcapng capture to read packets from, given
+    /// by the index or the name.
+    ///
+    /// Packets on all interfaces are read if not set.
+    #[clap(long)]
+    pub interface: Option<InterfaceSelector>,
+
+    /// Read only the UDP packets sent from the IP address.
+    #[clap(long)]
+    pub source_ip: Option<Ipv4Addr>,
+
+    /// Read only the UDP packets sent to the destination port.
+    #[clap(long)]
+    pub port: Option<u16>,
+
+    /// List the UDP sources in the pcap file and exit.
+    #[clap(long)]
+    pub list_sources: bool,
+
+    /// Cut the frames of a Velodyne or Hesai LiDAR where the azimuth
+    /// passes the angle in degrees, measured clockwise from the front
+    /// of the sensor.
+    ///
+    /// The frames are cut where the azimuth wraps around if no cut is
+    /// set.
+    #[clap(long, conflicts_with_all = ["frame_period", "frame_packets"])]
+    pub cut_angle: Option<f64>,
+
+    /// Cut the frames of a Velodyne or Hesai LiDAR at fixed time
+    /// windows, such as 100ms.
+    #[clap(long, value_parser = parse_duration, conflicts_with = "frame_packets")]
+    pub frame_period: Option<Duration>,
+
+    /// Cut the frames of a Velodyne or Hesai LiDAR every the number of
+    /// data packets.
+    #[clap(long, value_parser = parse_frame_packets)]
+    pub frame_packets: Option<usize>,
+
+    /// Drop the partial first and last frames, which appear when the
+    /// capture starts or stops in the middle of a frame.
+    #[clap(long)]
+    pub drop_partial_frames: bool,
+
+    /// Select the frames starting from the time.
+    ///
+    /// The time is an RFC 3339 date and time such as
+    /// 2023-10-16T14:03:12Z, the seconds since the Unix epoch, a UTC
+    /// time of day such as 14:03:12, or a duration since the start of
+    /// the first frame such as 90s.
+    #[clap(long, value_parser = parse_start_time)]
+    pub start_time: Option<TimeSelector>,
+
+    /// Select the frames starting before the time.
+    ///
+    /// It takes the same forms as --start-time, and also a duration
+    /// after --start-time prefixed with a '+', such as +20s.
+    #[clap(long, value_parser = parse_end_time)]
+    pub end_time: Option<TimeSelector>,
+
+    /// Keep one of every the number of selected frames, starting from
+    /// the first one.
+    #[clap(long, default_value = "1")]
+    pub step: NonZeroUsize,
 }
 
 /// Show the point cloud data in a graphics user interface.
@@ -43,19 +145,250 @@ pub struct Show {
     pub input: PathBuf,
 
     /// The Velodyne LiDAR model name.
+    ///
+    /// It is detected from the data packets if not set.
     #[clap(long)]
     pub velodyne_model: Option<ProductID>,
 
     /// The return mode configured on the Velodyne LiDAR.
+    ///
+    /// It is detected from the data packets if not set.
     #[clap(long)]
     pub velodyne_return_mode: Option<VelodyneReturnMode>,
+
+    /// The calibration file of the Velodyne LiDAR, either a VeloView
+    /// db.xml file or a ROS velodyne_pointcloud YAML file.
+    ///
+    /// The nominal laser angles of the model are used if not set.
+    #[clap(long)]
+    pub velodyne_calibration: Option<PathBuf>,
+
+    /// The metadata JSON file of the Ouster LiDAR.
+    ///
+    /// The .json file next to the input file is used if not set.
+    #[clap(long)]
+    pub ouster_metadata: Option<PathBuf>,
+
+    /// The Hesai LiDAR model name.
+    ///
+    /// It is detected from the data packets if not set.
+    #[clap(long)]
+    pub hesai_model: Option<HesaiModel>,
+
+    /// The angle correction CSV file of the Hesai LiDAR.
+    ///
+    /// The nominal laser angles of the model are used if not set.
+    #[clap(long)]
+    pub hesai_angles: Option<PathBuf>,
+
+    /// The interface of a pcapng capture to read packets from, given
+    /// by the index or the name.
+    ///
+    /// Packets on all interfaces are read if not set.
+    #[clap(long)]
+    pub interface: Option<InterfaceSelector>,
+
+    /// Read only the UDP packets sent from the IP address.
+    #[clap(long)]
+    pub source_ip: Option<Ipv4Addr>,
+
+    /// Read only the UDP packets sent to the destination port.
+    #[clap(long)]
+    pub port: Option<u16>,
+
+    /// List the UDP sources in the pcap file and exit.
+    #[clap(long)]
+    pub list_sources: bool,
+
+    /// Cut the frames of a Velodyne or Hesai LiDAR where the azimuth
+    /// passes the angle in degrees, measured clockwise from the front
+    /// of the sensor.
+    ///
+    /// The frames are cut where the azimuth wraps around if no cut is
+    /// set.
+    #[clap(long, conflicts_with_all = ["frame_period", "frame_packets"])]
+    pub cut_angle: Option<f64>,
+
+    /// Cut the frames of a Velodyne or Hesai LiDAR at fixed time
+    /// windows, such as 100ms.
+    #[clap(long, value_parser = parse_duration, conflicts_with = "frame_packets")]
+    pub frame_period: Option<Duration>,
+
+    /// Cut the frames of a Velodyne or Hesai LiDAR every the number of
+    /// data packets.
+    #[clap(long, value_parser = parse_frame_packets)]
+    pub frame_packets: Option<usize>,
+
+    /// Drop the partial first and last frames, which appear when the
+    /// capture starts or stops in the middle of a frame.
+    #[clap(long)]
+    pub drop_partial_frames: bool,
+
+    /// Select the frames starting from the time.
+    ///
+    /// The time is an RFC 3339 date and time such as
+    /// 2023-10-16T14:03:12Z, the seconds since the Unix epoch, a UTC
+    /// time of day such as 14:03:12, or a duration since the start of
+    /// the first frame such as 90s.
+    #[clap(long, value_parser = parse_start_time)]
+    pub start_time: Option<TimeSelector>,
+
+    /// Select the frames starting before the time.
+    ///
+    /// It takes the same forms as --start-time, and also a duration
+    /// after --start-time prefixed with a '+', such as +20s.
+    #[clap(long, value_parser = parse_end_time)]
+    pub end_time: Option<TimeSelector>,
+
+    /// Keep one of every the number of selected frames, starting from
+    /// the first one.
+    #[clap(long, default_value = "1")]
+    pub step: NonZeroUsize,
 }
 
-/// Show the information of a point cloud file.
+/// Show the information of a point cloud or pcap file.
 #[derive(Debug, Clone, Parser)]
 pub struct Info {
     /// The file to be inspected.
     pub file: PathBuf,
+
+    /// The interface of a pcapng capture to inspect, given by the
+    /// index or the name.
+    ///
+    /// Packets on all interfaces are inspected if not set.
+    #[clap(long)]
+    pub interface: Option<InterfaceSelector>,
+
+    /// Inspect only the UDP packets sent from the IP address.
+    #[clap(long)]
+    pub source_ip: Option<Ipv4Addr>,
+
+    /// Inspect only the UDP packets sent to the destination port.
+    #[clap(long)]
+    pub port: Option<u16>,
+
+    /// List the UDP sources in the pcap file and exit.
+    #[clap(long)]
+    pub list_sources: bool,
+
+    /// Print the information in JSON.
+    #[clap(long)]
+    pub json: bool,
+}
+
+/// Build the frame index of a Velodyne or Hesai pcap file, which lets
+/// later conversions count and seek frames without decoding the
+/// capture.
+#[derive(Debug, Clone, Parser)]
+pub struct Index {
+    /// The input file format.
+    #[clap(short, long)]
+    pub format: Option<FileFormat>,
+
+    /// The input file path.
+    pub input: PathBuf,
+
+    /// The Velodyne LiDAR model name.
+    ///
+    /// It is detected from the data packets if not set.
+    #[clap(long)]
+    pub velodyne_model: Option<ProductID>,
+
+    /// The return mode configured on the Velodyne LiDAR.
+    ///
+    /// It is detected from the data packets if not set.
+    #[clap(long)]
+    pub velodyne_return_mode: Option<VelodyneReturnMode>,
+
+    /// The calibration file of the Velodyne LiDAR, either a VeloView
+    /// db.xml file or a ROS velodyne_pointcloud YAML file.
+    ///
+    /// The nominal laser angles of the model are used if not set.
+    #[clap(long)]
+    pub velodyne_calibration: Option<PathBuf>,
+
+    /// The Hesai LiDAR model name.
+    ///
+    /// It is detected from the data packets if not set.
+    #[clap(long)]
+    pub hesai_model: Option<HesaiModel>,
+
+    /// The angle correction CSV file of the Hesai LiDAR.
+    ///
+    /// The nominal laser angles of the model are used if not set.
+    #[clap(long)]
+    pub hesai_angles: Option<PathBuf>,
+
+    /// The interface of a pcapng capture to read packets from, given
+    /// by the index or the name.
+    ///
+    /// Packets on all interfaces are read if not set.
+    #[clap(long)]
+    pub interface: Option<InterfaceSelector>,
+
+    /// Read only the UDP packets sent from the IP address.
+    #[clap(long)]
+    pub source_ip: Option<Ipv4Addr>,
+
+    /// Read only the UDP packets sent to the destination port.
+    #[clap(long)]
+    pub port: Option<u16>,
+
+    /// Cut the frames of a Velodyne or Hesai LiDAR where the azimuth
+    /// passes the angle in degrees, measured clockwise from the front
+    /// of the sensor.
+    ///
+    /// The frames are cut where the azimuth wraps around if no cut is
+    /// set.
+    #[clap(long, conflicts_with_all = ["frame_period", "frame_packets"])]
+    pub cut_angle: Option<f64>,
+
+    /// Cut the frames of a Velodyne or Hesai LiDAR at fixed time
+    /// windows, such as 100ms.
+    #[clap(long, value_parser = parse_duration, conflicts_with = "frame_packets")]
+    pub frame_period: Option<Duration>,
+
+    /// Cut the frames of a Velodyne or Hesai LiDAR every the number of
+    /// data packets.
+    #[clap(long, value_parser = parse_frame_packets)]
+    pub frame_packets: Option<usize>,
+
+    /// Drop the partial first and last frames, which appear when the
+    /// capture starts or stops in the middle of a frame.
+    #[clap(long)]
+    pub drop_partial_frames: bool,
+}
+
+/// Extract the GPS time and position in the position packets of a
+/// Velodyne pcap file.
+#[derive(Debug, Clone, Parser)]
+pub struct Positions {
+    /// The input pcap file.
+    pub input: PathBuf,
+
+    /// The output file, written in JSON if the extension is .json or
+    /// in CSV otherwise.
+    ///
+    /// The records are printed to stdout if not set.
+    #[clap(short, long)]
+    pub output: Option<PathBuf>,
+
+    /// Write the records in JSON.
+    #[clap(long)]
+    pub json: bool,
+
+    /// The interface of a pcapng capture to read, given by the index
+    /// or the name.
+    #[clap(long)]
+    pub interface: Option<InterfaceSelector>,
+
+    /// Read only the UDP packets sent from the IP address.
+    #[clap(long)]
+    pub source_ip: Option<Ipv4Addr>,
+
+    /// Read only the UDP packets sent to the destination port.
+    #[clap(long)]
+    pub port: Option<u16>,
 }
 
 /// Convert point cloud file format.
@@ -77,14 +410,123 @@ pub struct Convert {
     #[clap(short, long)]
     pub output: PathBuf,
 
+    /// Name the frame files converted from a pcap or lvx file after the
+    /// template, which is a path relative to the output directory
+    /// without the file extension.
+    ///
+    /// The placeholders are {index} for the zero-based frame index,
+    /// {number} for the one-based frame number, {start_time} and
+    /// {end_time} for the frame times in seconds since the Unix epoch,
+    /// {mode} for the return mode of Velodyne and Hesai LiDARs, and
+    /// {sensor} for the source IP address of Velodyne and Hesai LiDARs
+    /// or the serial number of Ouster and Livox LiDARs. {index} and
+    /// {number} take a width padded with zeros, as in {index:06}.
+    ///
+    /// It defaults to {mode}/{index:06} for Velodyne and Hesai LiDARs,
+    /// and to {index:06} otherwise.
+    #[clap(long)]
+    pub output_template: Option<OutputTemplate>,
+
     /// The Velodyne LiDAR model name.
+    ///
+    /// It is detected from the data packets if not set.
     #[clap(long)]
     pub velodyne_model: Option<ProductID>,
 
     /// The return mode configured on the Velodyne LiDAR.
+    ///
+    /// It is detected from the data packets if not set.
     #[clap(long)]
     pub velodyne_return_mode: Option<VelodyneReturnMode>,
 
+    /// The calibration file of the Velodyne LiDAR, either a VeloView
+    /// db.xml file or a ROS velodyne_pointcloud YAML file.
+    ///
+    /// The nominal laser angles of the model are used if not set.
+    #[clap(long)]
+    pub velodyne_calibration: Option<PathBuf>,
+
+    /// The metadata JSON file of the Ouster LiDAR.
+    ///
+    /// The .json file next to the input file is used if not set.
+    #[clap(long)]
+    pub ouster_metadata: Option<PathBuf>,
+
+    /// The Hesai LiDAR model name.
+    ///
+    /// It is detected from the data packets if not set.
+    #[clap(long)]
+    pub hesai_model: Option<HesaiModel>,
+
+    /// The angle correction CSV file of the Hesai LiDAR.
+    ///
+    /// The nominal laser angles of the model are used if not set.
+    #[clap(long)]
+    pub hesai_angles: Option<PathBuf>,
+
+    /// The interface of a pcapng capture to read packets from, given
+    /// by the index or the name.
+    ///
+    /// Packets on all interfaces are read if not set.
+    #[clap(long)]
+    pub interface: Option<InterfaceSelector>,
+
+    /// Read only the UDP packets sent from the IP address.
+    #[clap(long)]
+    pub source_ip: Option<Ipv4Addr>,
+
+    /// Read only the UDP packets sent to the destination port.
+    #[clap(long)]
+    pub port: Option<u16>,
+
+    /// List the UDP sources in the pcap file and exit.
+    #[clap(long)]
+    pub list_sources: bool,
+
+    /// Convert the packets of each LiDAR in the pcap file to its own
+    /// subdirectory of the output, named after the source IP address
+    /// and port.
+    #[clap(long)]
+    pub split_sources: bool,
+
+    /// The Velodyne model of the LiDAR at a source, given as
+    /// IP[:PORT]=MODEL. It can be repeated for multiple sources.
+    ///
+    /// It takes precedence over --velodyne-model for the source.
+    #[clap(long = "source-model", value_parser = parse_source_value::<ProductID>)]
+    pub source_models: Vec<(SourceSelector, ProductID)>,
+
+    /// The return mode of the Velodyne LiDAR at a source, given as
+    /// IP[:PORT]=MODE. It can be repeated for multiple sources.
+    ///
+    /// It takes precedence over --velodyne-return-mode for the source.
+    #[clap(long = "source-return-mode", value_parser = parse_source_value::<VelodyneReturnMode>)]
+    pub source_return_modes: Vec<(SourceSelector, VelodyneReturnMode)>,
+
+    /// Cut the frames of a Velodyne or Hesai LiDAR where the azimuth
+    /// passes the angle in degrees, measured clockwise from the front
+    /// of the sensor.
+    ///
+    /// The frames are cut where the azimuth wraps around if no cut is
+    /// set.
+    #[clap(long, conflicts_with_all = ["frame_period", "frame_packets"])]
+    pub cut_angle: Option<f64>,
+
+    /// Cut the frames of a Velodyne or Hesai LiDAR at fixed time
+    /// windows, such as 100ms.
+    #[clap(long, value_parser = parse_duration, conflicts_with = "frame_packets")]
+    pub frame_period: Option<Duration>,
+
+    /// Cut the frames of a Velodyne or Hesai LiDAR every the number of
+    /// data packets.
+    #[clap(long, value_parser = parse_frame_packets)]
+    pub frame_packets: Option<usize>,
+
+    /// Drop the partial first and last frames, which appear when the
+    /// capture starts or stops in the middle of a frame.
+    #[clap(long)]
+    pub drop_partial_frames: bool,
+
     /// The first frame number to start to convert.
     ///
     /// If positive number is provided, it's frame number starting
@@ -103,6 +545,27 @@ pub struct Convert {
     #[clap(long, default_value = "-1", value_parser = parse_end_or_count)]
     pub end: EndFrame,
 
+    /// Select the frames starting from the time.
+    ///
+    /// The time is an RFC 3339 date and time such as
+    /// 2023-10-16T14:03:12Z, the seconds since the Unix epoch, a UTC
+    /// time of day such as 14:03:12, or a duration since the start of
+    /// the first frame such as 90s.
+    #[clap(long, value_parser = parse_start_time)]
+    pub start_time: Option<TimeSelector>,
+
+    /// Select the frames starting before the time.
+    ///
+    /// It takes the same forms as --start-time, and also a duration
+    /// after --start-time prefixed with a '+', such as +20s.
+    #[clap(long, value_parser = parse_end_time)]
+    pub end_time: Option<TimeSelector>,
+
+    /// Keep one of every the number of selected frames, starting from
+    /// the first one.
+    #[clap(long, default_value = "1")]
+    pub step: NonZeroUsize,
+
     /// Apply point transformation described in the file.
     ///
     /// The transformation file format is defined in tftk.
@@ -116,6 +579,15 @@ pub struct Convert {
     /// https://github.com/NEWSLabNTU/tftk#file-format
     #[clap(long)]
     pub transform: Option<String>,
+
+    /// The number of frames of a pcap or lvx file converted in
+    /// parallel.
+    ///
+    /// The frames are read one after another, and at most about twice
+    /// this number of frames are held in memory. It defaults to the
+    /// number of CPUs.
+    #[clap(short, long)]
+    pub jobs: Option<NonZeroUsize>,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
@@ -149,6 +621,20 @@ pub enum EndFrame {
     Count(usize),
 }
 
+/// A point in time given to --start-time or --end-time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TimeSelector {
+    /// The UTC time since the Unix epoch.
+    At(Duration),
+    /// The UTC time of day, taken on the day nearest to the reference
+    /// time.
+    TimeOfDay(Duration),
+    /// The time since the start of the first frame of the file.
+    Offset(Duration),
+    /// The time since --start-time. It is given to --end-time only.
+    After(Duration),
+}
+
 fn parse_end_or_count(arg: &str) -> Result<EndFrame, String> {
     macro_rules! bail {
         () => {
@@ -218,3 +704,130 @@ fn parse_start_frame(arg: &str) -> Result<StartFrame, String> {
 
     Ok(arg)
 }
+
+fn parse_frame_packets(arg: &str) -> Result<usize, String> {
+    match arg.parse::<usize>() {
+        Ok(count) if count > 0 => Ok(count),
+        _ => Err(format!(
+            "expect a positive number of packets, but get '{arg}'"
+        )),
+    }
+}
+
+/// Parses a duration written as a number followed by a unit among ns,
+/// us, ms, s, m and h, such as 100ms or 1.5s.
+pub fn parse_duration(arg: &str) -> Result<Duration, String> {
+    let invalid = || format!("invalid duration '{arg}'");
+
+    let split = arg
+        .find(|ch: char| !(ch.is_ascii_digit() || ch == '.'))
+        .ok_or_else(invalid)?;
+    let (value, unit) = arg.split_at(split);
+    let value: f64 = value.parse().map_err(|_| invalid())?;
+    let unit_secs = match unit {
+        "ns" => 1e-9,
+        "us" => 1e-6,
+        "ms" => 1e-3,
+        "s" => 1.0,
+        "m" => 60.0,
+        "h" => 3600.0,
+        _ => return Err(invalid()),
+    };
+
+    Duration::try_from_secs_f64(value * unit_secs).map_err(|_| invalid())
+}
+
+/// Parses the --start-time option. See [parse_time_selector].
+fn parse_start_time(arg: &str) -> Result<TimeSelector, String> {
+    if arg.starts_with('+') {
+        return Err(format!(
+            "--start-time does not accept a time after itself, but get '{arg}'"
+        ));
+    }
+    parse_time_selector(arg)
+}
+
+/// Parses the --end-time option. See [parse_time_selector].
+fn parse_end_time(arg: &str) -> Result<TimeSelector, String> {
+    parse_time_selector(arg)
+}
+
+/// Parses a point in time written in one of the forms:
+///
+/// - an RFC 3339 date and time, such as 2023-10-16T14:03:12.5Z,
+/// - the seconds since the Unix epoch, such as 1697465000.5,
+/// - a UTC time of day, such as 14:03:12 or 14:03:12.5,
+/// - a duration since the first frame, such as 90s,
+/// - a duration prefixed with a '+', which is relative to the start
+///   time, such as +20s.
+fn parse_time_selector(arg: &str) -> Result<TimeSelector, String> {
+    if let Some(duration) = arg.strip_prefix('+') {
+        return Ok(TimeSelector::After(parse_duration(duration)?));
+    }
+
+    if let Ok(time) = DateTime::parse_from_rfc3339(arg) {
+        let secs = u64::try_from(time.timestamp())
+            .map_err(|_| format!("time '{arg}' is before the Unix epoch"))?;
+        let since_epoch = Duration::new(secs, time.timestamp_subsec_nanos());
+        return Ok(TimeSelector::At(since_epoch));
+    }
+
+    if let Ok(secs) = arg.parse::<f64>() {
+        let since_epoch =
+            Duration::try_from_secs_f64(secs).map_err(|_| format!("invalid time '{arg}'"))?;
+        return Ok(TimeSelector::At(since_epoch));
+    }
+
+    if let Ok(time) = NaiveTime::parse_from_str(arg, "%H:%M:%S%.f") {
+        let since_midnight =
+            Duration::new(time.num_seconds_from_midnight().into(), time.nanosecond());
+        return Ok(TimeSelector::TimeOfDay(since_midnight));
+    }
+
+    parse_duration(arg)
+        .map(TimeSelector::Offset)
+        .map_err(|_| format!("invalid time '{arg}'"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_absolute_times() {
+        assert_eq!(
+            parse_time_selector("2023-10-16T23:59:50.5Z"),
+            Ok(TimeSelector::At(Duration::new(1_697_500_790, 500_000_000)))
+        );
+        assert_eq!(
+            parse_time_selector("2023-10-17T07:59:50+08:00"),
+            Ok(TimeSelector::At(Duration::from_secs(1_697_500_790)))
+        );
+        assert_eq!(
+            parse_time_selector("1697500790.5"),
+            Ok(TimeSelector::At(Duration::new(1_697_500_790, 500_000_000)))
+        );
+        assert!(parse_time_selector("2023-10-16T23:59:50").is_err());
+    }
+
+    #[test]
+    fn parse_relative_times() {
+        assert_eq!(
+            parse_time_selector("23:59:50"),
+            Ok(TimeSelector::TimeOfDay(Duration::from_secs(86_390)))
+        );
+        assert_eq!(
+            parse_time_selector("90s"),
+            Ok(TimeSelector::Offset(Duration::from_secs(90)))
+        );
+        assert_eq!(
+            parse_time_selector("+1.5s"),
+            Ok(TimeSelector::After(Duration::from_millis(1500)))
+        );
+        assert!(parse_start_time("+20s").is_err());
+        assert_eq!(
+            parse_end_time("+20s"),
+            Ok(TimeSelector::After(Duration::from_secs(20)))
+        );
+    }
+}
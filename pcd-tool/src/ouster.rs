@@ -0,0 +1,727 @@
+//! Decoding of Ouster pcap files with the sensor metadata.
+
+use crate::capture::{CaptureReader, PacketFilter};
+use eyre::{bail, ensure, format_err, Context, Result};
+use pcd_format::PcdPoint;
+use serde_json::Value;
+use std::{
+    f64::consts::{FRAC_PI_2, TAU},
+    fs,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+/// The size of the packet header of non-legacy UDP profiles.
+const PACKET_HEADER_SIZE: usize = 32;
+/// The size of the packet footer of non-legacy UDP profiles.
+const PACKET_FOOTER_SIZE: usize = 32;
+/// The size of a pixel, which is the same in the supported profiles.
+const PIXEL_SIZE: usize = 12;
+
+/// The layout of the LIDAR_DATA packets.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OusterUdpProfile {
+    /// The original packet format, in which each column carries its
+    /// own header and status.
+    Legacy,
+    /// The single return profile with 19-bit range.
+    Rng19Rfl8Sig16Nir16,
+}
+
+impl OusterUdpProfile {
+    fn from_name(name: &str) -> Result<Self> {
+        let profile = match name {
+            "LEGACY" => Self::Legacy,
+            "RNG19_RFL8_SIG16_NIR16" => Self::Rng19Rfl8Sig16Nir16,
+            _ => bail!("the Ouster UDP profile '{name}' is not supported"),
+        };
+        Ok(profile)
+    }
+
+    /// Gives the sizes of the column header and the column footer.
+    fn column_overhead(self) -> (usize, usize) {
+        match self {
+            Self::Legacy => (16, 4),
+            Self::Rng19Rfl8Sig16Nir16 => (12, 0),
+        }
+    }
+}
+
+/// The sensor metadata of an Ouster LiDAR, read from the JSON file
+/// saved along with the capture.
+#[derive(Debug, Clone)]
+pub struct OusterMetadata {
+    pub columns_per_frame: usize,
+    pub columns_per_packet: usize,
+    pub pixels_per_column: usize,
+    /// The beam elevation angles in degrees.
+    pub beam_altitude_angles: Vec<f64>,
+    /// The beam azimuth offsets in degrees.
+    pub beam_azimuth_angles: Vec<f64>,
+    /// The distance from the lidar origin to the beam origin in
+    /// millimeters.
+    pub lidar_origin_to_beam_origin_mm: f64,
+    /// The row-major 4x4 transformation from the lidar frame to the
+    /// sensor frame. The translation is in millimeters.
+    pub lidar_to_sensor_transform: [f64; 16],
+    pub udp_profile_lidar: OusterUdpProfile,
+    /// The serial number of the sensor.
+    pub serial_number: Option<String>,
+}
+
+impl OusterMetadata {
+    /// Loads the metadata JSON file. Both the flat layout written by
+    /// older firmwares and the layout grouped by sections are
+    /// accepted.
+    pub fn load<P>(path: P) -> Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        let path = path.as_ref();
+        let text = fs::read_to_string(path)?;
+        let json: Value = serde_json::from_str(&text)?;
+        Self::from_json(&json)
+            .wrap_err_with(|| format!("unable to read the Ouster metadata {}", path.display()))
+    }
+
+    /// Finds the metadata file of a pcap file, which is the file with
+    /// the same stem and the .json extension.
+    pub fn find_for_pcap<P>(pcap_file: P) -> Option<PathBuf>
+    where
+        P: AsRef<Path>,
+    {
+        let path = pcap_file.as_ref().with_extension("json");
+        path.is_file().then_some(path)
+    }
+
+    fn from_json(json: &Value) -> Result<Self> {
+        // Finds a key at the top level or in any section.
+        let find = |key: &str| find_key(json, key);
+        let find_f64_array = |key: &str| -> Result<Vec<f64>> {
+            let array = find(key)
+                .and_then(|value| value.as_array())
+                .ok_or_else(|| format_err!("\"{key}\" is missing"))?;
+            array
+                .iter()
+                .map(|value| {
+                    value
+                        .as_f64()
+                        .ok_or_else(|| format_err!("\"{key}\" has a non-numeric element"))
+                })
+                .collect()
+        };
+        let find_usize = |key: &str| {
+            find(key)
+                .and_then(|value| value.as_u64())
+                .map(|value| value as usize)
+        };
+
+        let beam_altitude_angles = find_f64_array("beam_altitude_angles")?;
+        let beam_azimuth_angles = find_f64_array("beam_azimuth_angles")?;
+        ensure!(
+            beam_altitude_angles.len() == beam_azimuth_angles.len(),
+            "the beam altitude and azimuth angles differ in length"
+        );
+
+        let columns_per_frame = match find_usize("columns_per_frame") {
+            Some(columns) => columns,
+            None => {
+                // The lidar mode is given as "<columns>x<rate>".
+                let mode = find("lidar_mode")
+                    .and_then(|value| value.as_str())
+                    .ok_or_else(|| format_err!("\"columns_per_frame\" is missing"))?;
+                mode.split('x')
+                    .next()
+                    .and_then(|columns| columns.parse().ok())
+                    .ok_or_else(|| format_err!("invalid lidar mode '{mode}'"))?
+            }
+        };
+        let columns_per_packet = find_usize("columns_per_packet").unwrap_or(16);
+        let pixels_per_column =
+            find_usize("pixels_per_column").unwrap_or(beam_altitude_angles.len());
+        ensure!(
+            pixels_per_column == beam_altitude_angles.len(),
+            "the number of beam angles does not match the pixels per column"
+        );
+
+        let lidar_origin_to_beam_origin_mm = find("lidar_origin_to_beam_origin_mm")
+            .and_then(|value| value.as_f64())
+            .unwrap_or(0.0);
+        let lidar_to_sensor_transform = match find("lidar_to_sensor_transform") {
+            Some(_) => {
+                let values = find_f64_array("lidar_to_sensor_transform")?;
+                values.try_into().map_err(|_| {
+                    format_err!("\"lidar_to_sensor_transform\" must have 16 elements")
+                })?
+            }
+            None => [
+                1.0, 0.0, 0.0, 0.0, //
+                0.0, 1.0, 0.0, 0.0, //
+                0.0, 0.0, 1.0, 0.0, //
+                0.0, 0.0, 0.0, 1.0,
+            ],
+        };
+        let udp_profile_lidar = match find("udp_profile_lidar").and_then(|value| value.as_str()) {
+            Some(name) => OusterUdpProfile::from_name(name)?,
+            None => OusterUdpProfile::Legacy,
+        };
+        // The serial number is written as a string or as a number
+        // depending on the firmware.
+        let serial_number = find("prod_sn").and_then(|value| match value {
+            Value::String(text) => Some(text.clone()),
+            Value::Number(number) => Some(number.to_string()),
+            _ => None,
+        });
+
+        Ok(Self {
+            columns_per_frame,
+            columns_per_packet,
+            pixels_per_column,
+            beam_altitude_angles,
+            beam_azimuth_angles,
+            lidar_origin_to_beam_origin_mm,
+            lidar_to_sensor_transform,
+            udp_profile_lidar,
+            serial_number,
+        })
+    }
+
+    /// Gives the size of a LIDAR_DATA packet.
+    pub fn packet_size(&self) -> usize {
+        let (header, footer) = self.udp_profile_lidar.column_overhead();
+        let column_size = header + self.pixels_per_column * PIXEL_SIZE + footer;
+        let columns_size = self.columns_per_packet * column_size;
+
+        match self.udp_profile_lidar {
+            OusterUdpProfile::Legacy => columns_size,
+            _ => PACKET_HEADER_SIZE + columns_size + PACKET_FOOTER_SIZE,
+        }
+    }
+
+    /// Computes the point in the sensor frame in meters from a range in
+    /// millimeters.
+    fn range_to_xyz(&self, range_mm: f64, beam: usize, measurement_id: usize) -> [f64; 3] {
+        let n = self.lidar_origin_to_beam_origin_mm;
+        let encoder_angle = TAU * (1.0 - measurement_id as f64 / self.columns_per_frame as f64);
+        let azimuth = encoder_angle - self.beam_azimuth_angles[beam].to_radians();
+        let altitude = self.beam_altitude_angles[beam].to_radians();
+
+        let x = (range_mm - n) * azimuth.cos() * altitude.cos() + n * encoder_angle.cos();
+        let y = (range_mm - n) * azimuth.sin() * altitude.cos() + n * encoder_angle.sin();
+        let z = (range_mm - n) * altitude.sin();
+
+        let m = &self.lidar_to_sensor_transform;
+        let transform =
+            |row: usize| m[row * 4] * x + m[row * 4 + 1] * y + m[row * 4 + 2] * z + m[row * 4 + 3];
+        [
+            transform(0) / 1000.0,
+            transform(1) / 1000.0,
+            transform(2) / 1000.0,
+        ]
+    }
+}
+
+fn find_key<'a>(json: &'a Value, key: &str) -> Option<&'a Value> {
+    json.get(key).or_else(|| {
+        json.as_object()?
+            .values()
+            .find_map(|section| section.get(key))
+    })
+}
+
+/// A pixel of an Ouster frame.
+///
+/// Pixels of columns missing in the capture and pixels without a
+/// return have a zero range.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct OusterSample {
+    /// The Cartesian coordinates in meters in the sensor frame.
+    pub xyz: [f64; 3],
+    /// The range in meters.
+    pub range: f64,
+    pub signal: u16,
+    pub reflectivity: u16,
+    pub near_ir: u16,
+    /// The beam index, which is the row of the frame.
+    pub beam: u32,
+    /// The column index within the frame.
+    pub measurement_id: u16,
+    /// The time of the column. It is the Unix time only if the sensor
+    /// clock is synchronized by PTP or GPS.
+    pub timestamp: Duration,
+    pub azimuthal_angle: f64,
+    pub vertical_angle: f64,
+}
+
+impl PcdPoint for OusterSample {
+    fn x(&self) -> f64 {
+        self.xyz[0]
+    }
+
+    fn y(&self) -> f64 {
+        self.xyz[1]
+    }
+
+    fn z(&self) -> f64 {
+        self.xyz[2]
+    }
+
+    fn distance(&self) -> f64 {
+        self.range
+    }
+
+    fn azimuthal_angle(&self) -> f64 {
+        self.azimuthal_angle
+    }
+
+    fn vertical_angle(&self) -> f64 {
+        self.vertical_angle
+    }
+
+    fn polar_angle(&self) -> f64 {
+        FRAC_PI_2 - self.vertical_angle
+    }
+
+    fn has_measured_spherical(&self) -> bool {
+        true
+    }
+
+    fn intensity(&self) -> Option<f64> {
+        Some(self.signal as f64)
+    }
+
+    fn laser_id(&self) -> Option<u32> {
+        Some(self.beam)
+    }
+
+    fn timestamp_ns(&self) -> Option<u32> {
+        Some(self.timestamp.as_nanos() as u32)
+    }
+
+    fn timestamp(&self) -> Option<f64> {
+        Some(self.timestamp.as_secs_f64())
+    }
+
+    fn reflectivity(&self) -> Option<f64> {
+        Some(self.reflectivity as f64)
+    }
+
+    fn near_ir(&self) -> Option<f64> {
+        Some(self.near_ir as f64)
+    }
+}
+
+/// A full revolution of an Ouster LiDAR.
+///
+/// The samples are in row-major order, so that the frame forms an
+/// organized point cloud of `width` columns by `height` beams. The
+/// columns are in measurement order without destaggering.
+#[derive(Debug, Clone)]
+pub struct OusterFrame {
+    pub frame_id: u16,
+    pub width: usize,
+    pub height: usize,
+    pub samples: Vec<OusterSample>,
+    /// The time of the earliest column received.
+    pub start_time: Duration,
+}
+
+/// Reads the frames in an Ouster pcap file. The LIDAR_DATA packets are
+/// told by their size, and a new frame starts whenever the frame ID
+/// changes.
+pub fn ouster_frame_iter_from_file<P>(
+    path: P,
+    filter: &PacketFilter,
+    metadata: &OusterMetadata,
+) -> Result<impl Iterator<Item = Result<OusterFrame>>>
+where
+    P: AsRef<Path>,
+{
+    let mut capture = CaptureReader::open(path, filter)?;
+    let metadata = metadata.clone();
+    let packet_size = metadata.packet_size();
+
+    let mut current: Option<OusterFrame> = None;
+    let mut finished = false;
+
+    let iter = std::iter::from_fn(move || -> Option<Result<OusterFrame>> {
+        loop {
+            if finished {
+                return None;
+            }
+
+            let packet = match capture.next_packet() {
+                Ok(Some(packet)) => packet,
+                Ok(None) => {
+                    finished = true;
+                    return current.take().map(Ok);
+                }
+                Err(err) => {
+                    finished = true;
+                    return Some(Err(err));
+                }
+            };
+            let Some(packet) = packet.udp() else {
+                continue;
+            };
+            if packet.payload.len() != packet_size {
+                continue;
+            }
+
+            let columns = decode_packet(&metadata, packet.payload);
+            let Some(&(frame_id, ..)) = columns.first() else {
+                continue;
+            };
+
+            let completed = match &current {
+                Some(frame) if frame.frame_id != frame_id => current.take(),
+                _ => None,
+            };
+            let frame = current.get_or_insert_with(|| OusterFrame {
+                frame_id,
+                width: metadata.columns_per_frame,
+                height: metadata.pixels_per_column,
+                samples: vec![
+                    OusterSample::default();
+                    metadata.columns_per_frame * metadata.pixels_per_column
+                ],
+                start_time: Duration::MAX,
+            });
+
+            for (_, measurement_id, timestamp, pixels) in columns {
+                let column = measurement_id as usize;
+                if column >= frame.width {
+                    continue;
+                }
+                frame.start_time = frame.start_time.min(timestamp);
+
+                for (beam, sample) in pixels.into_iter().enumerate() {
+                    frame.samples[beam * frame.width + column] = sample;
+                }
+            }
+
+            if let Some(frame) = completed {
+                return Some(Ok(frame));
+            }
+        }
+    });
+
+    Ok(iter)
+}
+
+/// Decodes the valid columns of a LIDAR_DATA packet into the frame ID,
+/// the measurement ID, the timestamp and the pixels of each.
+fn decode_packet(
+    metadata: &OusterMetadata,
+    payload: &[u8],
+) -> Vec<(u16, u16, Duration, Vec<OusterSample>)> {
+    let read_u16 = |offset: usize| u16::from_le_bytes([payload[offset], payload[offset + 1]]);
+    let read_u32 =
+        |offset: usize| u32::from_le_bytes(payload[offset..offset + 4].try_into().unwrap());
+    let read_u64 =
+        |offset: usize| u64::from_le_bytes(payload[offset..offset + 8].try_into().unwrap());
+
+    let profile = metadata.udp_profile_lidar;
+    let (header_size, footer_size) = profile.column_overhead();
+    let column_size = header_size + metadata.pixels_per_column * PIXEL_SIZE + footer_size;
+    let (columns_offset, packet_frame_id) = match profile {
+        OusterUdpProfile::Legacy => (0, None),
+        _ => (PACKET_HEADER_SIZE, Some(read_u16(2))),
+    };
+
+    (0..metadata.columns_per_packet)
+        .filter_map(|index| {
+            let offset = columns_offset + index * column_size;
+            let timestamp = Duration::from_nanos(read_u64(offset));
+            let measurement_id = read_u16(offset + 8);
+
+            let (frame_id, valid) = match profile {
+                OusterUdpProfile::Legacy => {
+                    let status = read_u32(offset + column_size - 4);
+                    (read_u16(offset + 10), status == 0xffff_ffff)
+                }
+                _ => {
+                    let status = read_u16(offset + 10);
+                    (packet_frame_id.unwrap(), status & 0x1 != 0)
+                }
+            };
+            if !valid {
+                return None;
+            }
+
+            let encoder_angle =
+                TAU * (1.0 - measurement_id as f64 / metadata.columns_per_frame as f64);
+
+            let pixels = (0..metadata.pixels_per_column)
+                .map(|beam| {
+                    let offset = offset + header_size + beam * PIXEL_SIZE;
+                    let (range_mm, reflectivity, signal, near_ir) = match profile {
+                        OusterUdpProfile::Legacy => (
+                            read_u32(offset) & 0x000f_ffff,
+                            read_u16(offset + 4),
+                            read_u16(offset + 6),
+                            read_u16(offset + 8),
+                        ),
+                        OusterUdpProfile::Rng19Rfl8Sig16Nir16 => (
+                            read_u32(offset) & 0x0007_ffff,
+                            payload[offset + 4] as u16,
+                            read_u16(offset + 6),
+                            read_u16(offset + 8),
+                        ),
+                    };
+
+                    let xyz = if range_mm == 0 {
+                        [0.0; 3]
+                    } else {
+                        metadata.range_to_xyz(range_mm as f64, beam, measurement_id as usize)
+                    };
+                    let azimuth = encoder_angle - metadata.beam_azimuth_angles[beam].to_radians();
+
+                    OusterSample {
+                        xyz,
+                        range: range_mm as f64 / 1000.0,
+                        signal,
+                        reflectivity,
+                        near_ir,
+                        beam: beam as u32,
+                        measurement_id,
+                        timestamp,
+                        azimuthal_angle: azimuth.sin().atan2(azimuth.cos()),
+                        vertical_angle: metadata.beam_altitude_angles[beam].to_radians(),
+                    }
+                })
+                .collect();
+
+            Some((frame_id, measurement_id, timestamp, pixels))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use approx::assert_abs_diff_eq;
+    use serde_json::json;
+    use std::f64::consts::FRAC_1_SQRT_2;
+
+    /// The metadata of a sensor with 2 beams, 4 columns per frame and 2
+    /// columns per packet.
+    fn small_metadata(udp_profile_lidar: &str) -> OusterMetadata {
+        OusterMetadata::from_json(&json!({
+            "beam_altitude_angles": [0.0, 45.0],
+            "beam_azimuth_angles": [0.0, 0.0],
+            "columns_per_frame": 4,
+            "columns_per_packet": 2,
+            "udp_profile_lidar": udp_profile_lidar,
+        }))
+        .unwrap()
+    }
+
+    /// Writes a pixel with the range in millimeters, the reflectivity,
+    /// the signal and the near infrared.
+    fn write_pixel(bytes: &mut [u8], range: u32, reflectivity: u8, signal: u16, near_ir: u16) {
+        bytes[..4].copy_from_slice(&range.to_le_bytes());
+        bytes[4] = reflectivity;
+        bytes[6..8].copy_from_slice(&signal.to_le_bytes());
+        bytes[8..10].copy_from_slice(&near_ir.to_le_bytes());
+    }
+
+    #[test]
+    fn metadata_in_sections() {
+        let metadata = OusterMetadata::from_json(&json!({
+            "sensor_info": { "prod_sn": "992109000123" },
+            "config_params": {
+                "lidar_mode": "1024x10",
+                "udp_profile_lidar": "RNG19_RFL8_SIG16_NIR16",
+            },
+            "beam_intrinsics": {
+                "beam_altitude_angles": [20.0, 0.0, -20.0],
+                "beam_azimuth_angles": [3.0, 1.0, -1.0],
+                "lidar_origin_to_beam_origin_mm": 15.8,
+            },
+            "lidar_data_format": {
+                "columns_per_frame": 1024,
+                "columns_per_packet": 16,
+                "pixels_per_column": 3,
+            },
+            "lidar_intrinsics": {
+                "lidar_to_sensor_transform": [
+                    -1, 0, 0, 0, 0, -1, 0, 0, 0, 0, 1, 36.18, 0, 0, 0, 1
+                ],
+            },
+        }))
+        .unwrap();
+
+        assert_eq!(metadata.columns_per_frame, 1024);
+        assert_eq!(metadata.columns_per_packet, 16);
+        assert_eq!(metadata.pixels_per_column, 3);
+        assert_eq!(metadata.beam_altitude_angles, [20.0, 0.0, -20.0]);
+        assert_eq!(metadata.beam_azimuth_angles, [3.0, 1.0, -1.0]);
+        assert_eq!(metadata.lidar_origin_to_beam_origin_mm, 15.8);
+        assert_eq!(metadata.lidar_to_sensor_transform[11], 36.18);
+        assert_eq!(
+            metadata.udp_profile_lidar,
+            OusterUdpProfile::Rng19Rfl8Sig16Nir16
+        );
+        assert_eq!(metadata.serial_number.as_deref(), Some("992109000123"));
+        assert_eq!(metadata.packet_size(), 32 + 16 * (12 + 3 * 12) + 32);
+    }
+
+    #[test]
+    fn metadata_of_older_firmwares() {
+        // The flat layout gives the columns by the lidar mode and the
+        // serial number as a number.
+        let metadata = OusterMetadata::from_json(&json!({
+            "prod_sn": 122001000456u64,
+            "lidar_mode": "512x20",
+            "beam_altitude_angles": [1.0, -1.0],
+            "beam_azimuth_angles": [0.5, -0.5],
+        }))
+        .unwrap();
+
+        assert_eq!(metadata.columns_per_frame, 512);
+        assert_eq!(metadata.columns_per_packet, 16);
+        assert_eq!(metadata.pixels_per_column, 2);
+        assert_eq!(metadata.lidar_origin_to_beam_origin_mm, 0.0);
+        assert_eq!(metadata.lidar_to_sensor_transform[0], 1.0);
+        assert_eq!(metadata.lidar_to_sensor_transform[3], 0.0);
+        assert_eq!(metadata.udp_profile_lidar, OusterUdpProfile::Legacy);
+        assert_eq!(metadata.serial_number.as_deref(), Some("122001000456"));
+        assert_eq!(metadata.packet_size(), 16 * (16 + 2 * 12 + 4));
+    }
+
+    #[test]
+    fn reject_invalid_metadata() {
+        let parse = |json: Value| OusterMetadata::from_json(&json);
+        let angles = json!([0.0, 1.0]);
+
+        assert!(parse(json!({ "beam_azimuth_angles": angles, "lidar_mode": "512x10" })).is_err());
+        assert!(parse(json!({
+            "beam_altitude_angles": [0.0],
+            "beam_azimuth_angles": angles,
+            "lidar_mode": "512x10",
+        }))
+        .is_err());
+        assert!(parse(json!({
+            "beam_altitude_angles": angles,
+            "beam_azimuth_angles": angles,
+        }))
+        .is_err());
+        assert!(parse(json!({
+            "beam_altitude_angles": angles,
+            "beam_azimuth_angles": angles,
+            "lidar_mode": "512x10",
+            "pixels_per_column": 3,
+        }))
+        .is_err());
+        assert!(parse(json!({
+            "beam_altitude_angles": angles,
+            "beam_azimuth_angles": angles,
+            "lidar_mode": "512x10",
+            "lidar_to_sensor_transform": [1.0, 0.0, 0.0],
+        }))
+        .is_err());
+        assert!(parse(json!({
+            "beam_altitude_angles": angles,
+            "beam_azimuth_angles": angles,
+            "lidar_mode": "512x10",
+            "udp_profile_lidar": "RNG15_RFL8_NIR8",
+        }))
+        .is_err());
+    }
+
+    #[test]
+    fn decode_single_return_packet() {
+        let metadata = small_metadata("RNG19_RFL8_SIG16_NIR16");
+        let mut payload = vec![0; metadata.packet_size()];
+        payload[2..4].copy_from_slice(&5u16.to_le_bytes());
+
+        // The first column is measured at a quarter turn, and the
+        // second column is invalid.
+        let column = &mut payload[PACKET_HEADER_SIZE..];
+        column[..8].copy_from_slice(&1_000_000_123u64.to_le_bytes());
+        column[8..10].copy_from_slice(&1u16.to_le_bytes());
+        column[10..12].copy_from_slice(&1u16.to_le_bytes());
+        // The bits above the 19-bit range are ignored.
+        write_pixel(&mut column[12..], 0x0008_0000 | 2_000, 9, 300, 40);
+        write_pixel(&mut column[24..], 1_000, 10, 310, 41);
+
+        let columns = decode_packet(&metadata, &payload);
+        let [(frame_id, measurement_id, timestamp, pixels)] = &columns[..] else {
+            panic!("expect a single valid column");
+        };
+        assert_eq!(*frame_id, 5);
+        assert_eq!(*measurement_id, 1);
+        assert_eq!(*timestamp, Duration::from_nanos(1_000_000_123));
+        assert_eq!(pixels.len(), 2);
+
+        let pixel = &pixels[0];
+        assert_abs_diff_eq!(pixel.range, 2.0);
+        assert_abs_diff_eq!(&pixel.xyz[..], &[0.0, -2.0, 0.0][..], epsilon = 1e-9);
+        assert_eq!(
+            (pixel.reflectivity, pixel.signal, pixel.near_ir),
+            (9, 300, 40)
+        );
+        assert_eq!(pixel.beam, 0);
+        assert_abs_diff_eq!(pixel.azimuthal_angle, -FRAC_PI_2, epsilon = 1e-9);
+        assert_abs_diff_eq!(pixel.vertical_angle, 0.0);
+
+        let pixel = &pixels[1];
+        assert_eq!(pixel.beam, 1);
+        assert_abs_diff_eq!(
+            &pixel.xyz[..],
+            &[0.0, -FRAC_1_SQRT_2, FRAC_1_SQRT_2][..],
+            epsilon = 1e-9
+        );
+        assert_abs_diff_eq!(pixel.vertical_angle, 45f64.to_radians());
+    }
+
+    #[test]
+    fn decode_legacy_packet() {
+        let metadata = small_metadata("LEGACY");
+        let column_size = 16 + 2 * PIXEL_SIZE + 4;
+        let mut payload = vec![0; metadata.packet_size()];
+        assert_eq!(payload.len(), 2 * column_size);
+
+        // Each column has its own frame ID and is valid if the status
+        // is all ones.
+        for (index, status) in [(0, 0xffff_ffffu32), (1, 0x7fff_ffff)] {
+            let column = &mut payload[index * column_size..(index + 1) * column_size];
+            column[..8].copy_from_slice(&(1_000 + index as u64).to_le_bytes());
+            column[8..10].copy_from_slice(&(2 + index as u16).to_le_bytes());
+            column[10..12].copy_from_slice(&7u16.to_le_bytes());
+            write_pixel(&mut column[16..], 0x00f0_0000 | 3_000, 0, 0, 0);
+            column[column_size - 4..].copy_from_slice(&status.to_le_bytes());
+        }
+
+        let columns = decode_packet(&metadata, &payload);
+        let [(frame_id, measurement_id, timestamp, pixels)] = &columns[..] else {
+            panic!("expect a single valid column");
+        };
+        assert_eq!((*frame_id, *measurement_id), (7, 2));
+        assert_eq!(*timestamp, Duration::from_nanos(1_000));
+
+        // The column is measured at half a turn. The pixel without a
+        // return stays at the origin.
+        assert_abs_diff_eq!(pixels[0].range, 3.0);
+        assert_abs_diff_eq!(&pixels[0].xyz[..], &[-3.0, 0.0, 0.0][..], epsilon = 1e-9);
+        assert_eq!(pixels[1].range, 0.0);
+        assert_eq!(pixels[1].xyz, [0.0; 3]);
+    }
+
+    #[test]
+    fn range_to_xyz_with_beam_offset_and_transform() {
+        let mut metadata = small_metadata("LEGACY");
+        metadata.beam_altitude_angles = vec![30.0, 0.0];
+        metadata.lidar_origin_to_beam_origin_mm = 20.0;
+        metadata.lidar_to_sensor_transform[11] = 36.18;
+
+        // The beam starts 20 mm off the axis, and the sensor frame is
+        // 36.18 mm above the lidar frame.
+        let (sin, cos) = 30f64.to_radians().sin_cos();
+        let xyz = metadata.range_to_xyz(1_000.0, 0, 0);
+        assert_abs_diff_eq!(xyz[0], (980.0 * cos + 20.0) / 1000.0, epsilon = 1e-9);
+        assert_abs_diff_eq!(xyz[1], 0.0, epsilon = 1e-9);
+        assert_abs_diff_eq!(xyz[2], (980.0 * sin + 36.18) / 1000.0, epsilon = 1e-9);
+    }
+}
@@ -0,0 +1,137 @@
+//! Parsing of UDP packets in captures and of the Velodyne packet
+//! layout.
+
+use std::{
+    net::{Ipv4Addr, SocketAddrV4},
+    time::Duration,
+};
+use velodyne_lidar::{ProductID, ReturnMode};
+
+/// The link type of Ethernet frames.
+const LINKTYPE_ETHERNET: u16 = 1;
+/// The link type of Linux cooked captures.
+const LINKTYPE_LINUX_SLL: u16 = 113;
+
+/// The UDP payload size of a Velodyne data packet.
+pub const VELODYNE_DATA_PACKET_SIZE: usize = 1206;
+/// The UDP payload size of a Velodyne position packet.
+pub const VELODYNE_POSITION_PACKET_SIZE: usize = 512;
+
+/// A UDP datagram over IPv4 found in a captured packet.
+#[derive(Debug, Clone, Copy)]
+pub struct UdpPacket<'a> {
+    /// The capture time since the Unix epoch.
+    pub time: Duration,
+    pub source: SocketAddrV4,
+    pub destination: SocketAddrV4,
+    pub payload: &'a [u8],
+}
+
+impl<'a> UdpPacket<'a> {
+    /// Parses a captured packet. It gives `None` if the packet is not
+    /// a UDP datagram over IPv4 or the link type is not supported.
+    pub fn parse(linktype: u16, time: Duration, data: &'a [u8]) -> Option<Self> {
+        let (mut ethertype, mut rest) = match linktype {
+            LINKTYPE_ETHERNET => (read_u16(data, 12)?, data.get(14..)?),
+            LINKTYPE_LINUX_SLL => (read_u16(data, 14)?, data.get(16..)?),
+            _ => return None,
+        };
+
+        // Skip VLAN tags
+        while ethertype == 0x8100 || ethertype == 0x88a8 {
+            ethertype = read_u16(rest, 2)?;
+            rest = rest.get(4..)?;
+        }
+
+        if ethertype != 0x0800 {
+            return None;
+        }
+
+        // IPv4 header
+        let ihl = (*rest.first()? & 0x0f) as usize * 4;
+        let protocol = *rest.get(9)?;
+        if protocol != 17 {
+            return None;
+        }
+        let source_ip = Ipv4Addr::from(<[u8; 4]>::try_from(rest.get(12..16)?).ok()?);
+        let destination_ip = Ipv4Addr::from(<[u8; 4]>::try_from(rest.get(16..20)?).ok()?);
+
+        // UDP header
+        let udp = rest.get(ihl..)?;
+        let source_port = read_u16(udp, 0)?;
+        let destination_port = read_u16(udp, 2)?;
+        let udp_len = read_u16(udp, 4)? as usize;
+        let payload = udp.get(8..udp_len.max(8))?;
+
+        Some(Self {
+            time,
+            source: SocketAddrV4::new(source_ip, source_port),
+            destination: SocketAddrV4::new(destination_ip, destination_port),
+            payload,
+        })
+    }
+}
+
+/// Gives the name of a link type supported by [UdpPacket::parse].
+pub fn linktype_name(linktype: u16) -> Option<&'static str> {
+    match linktype {
+        LINKTYPE_ETHERNET => Some("ethernet"),
+        LINKTYPE_LINUX_SLL => Some("linux-sll"),
+        _ => None,
+    }
+}
+
+/// The kind of a Velodyne packet told by the payload size.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VelodynePacket {
+    /// A data packet with the factory bytes at the end of the payload.
+    Data { return_mode: u8, product_id: u8 },
+    /// A position packet carrying GPS and PPS status.
+    Position,
+}
+
+impl VelodynePacket {
+    /// Classifies a UDP payload. It gives `None` if the size matches
+    /// neither packet kind.
+    pub fn classify(payload: &[u8]) -> Option<Self> {
+        match payload.len() {
+            VELODYNE_DATA_PACKET_SIZE => Some(Self::Data {
+                return_mode: payload[VELODYNE_DATA_PACKET_SIZE - 2],
+                product_id: payload[VELODYNE_DATA_PACKET_SIZE - 1],
+            }),
+            VELODYNE_POSITION_PACKET_SIZE => Some(Self::Position),
+            _ => None,
+        }
+    }
+}
+
+/// Interprets the product ID factory byte of a data packet.
+pub fn velodyne_product_id_from_byte(byte: u8) -> Option<ProductID> {
+    let model = match byte {
+        0x21 => ProductID::HDL32E,
+        0x22 => ProductID::VLP16,
+        0x23 => ProductID::PuckLite,
+        0x24 => ProductID::PuckHiRes,
+        0x28 => ProductID::VLP32C,
+        0x31 => ProductID::Velarray,
+        0xa1 => ProductID::VLS128,
+        _ => return None,
+    };
+    Some(model)
+}
+
+/// Interprets the return mode factory byte of a data packet.
+pub fn velodyne_return_mode_from_byte(byte: u8) -> Option<ReturnMode> {
+    let mode = match byte {
+        0x37 => ReturnMode::Strongest,
+        0x38 => ReturnMode::Last,
+        0x39 => ReturnMode::Dual,
+        _ => return None,
+    };
+    Some(mode)
+}
+
+fn read_u16(data: &[u8], offset: usize) -> Option<u16> {
+    let bytes = data.get(offset..offset + 2)?;
+    Some(u16::from_be_bytes([bytes[0], bytes[1]]))
+}
@@ -0,0 +1,581 @@
+//! Extraction of the GPS time and position carried by Velodyne
+//! position packets.
+//!
+//! A position packet carries the top-of-hour timestamp of the sensor,
+//! the PPS status and the NMEA sentence received from the GPS
+//! receiver. The $GPRMC sentence ties the top-of-hour timestamp to
+//! UTC, which gives data packets absolute timestamps.
+
+use crate::{
+    capture::{CaptureReader, PacketFilter},
+    io::first_packet_time_in_pcap,
+    opts::Positions,
+    packet::{VelodynePacket, VELODYNE_POSITION_PACKET_SIZE},
+    spinning::SpinningLidar,
+    utils::{format_time, TohClock},
+};
+use chrono::{NaiveDate, NaiveTime};
+use eyre::Result;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::{
+    fs::File,
+    io::{self, prelude::*, BufWriter},
+    net::Ipv4Addr,
+    path::Path,
+    time::Duration,
+};
+
+/// The offset of the top-of-hour timestamp in a position packet.
+const TOH_OFFSET: usize = 198;
+/// The offset of the PPS status in a position packet.
+const PPS_STATUS_OFFSET: usize = 202;
+/// The offset of the NMEA sentence in a position packet.
+const NMEA_OFFSET: usize = 206;
+/// The maximum size of the NMEA sentence in a position packet.
+const NMEA_SIZE: usize = 128;
+
+/// The synchronization state of the PPS signal.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PpsStatus {
+    Absent,
+    Synchronizing,
+    Locked,
+    Error,
+}
+
+impl PpsStatus {
+    fn from_byte(byte: u8) -> Option<Self> {
+        let status = match byte {
+            0 => Self::Absent,
+            1 => Self::Synchronizing,
+            2 => Self::Locked,
+            3 => Self::Error,
+            _ => return None,
+        };
+        Some(status)
+    }
+
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Absent => "absent",
+            Self::Synchronizing => "synchronizing",
+            Self::Locked => "locked",
+            Self::Error => "error",
+        }
+    }
+}
+
+/// The recommended minimum data in a $GPRMC sentence.
+#[derive(Debug, Clone, Copy)]
+pub struct Gprmc {
+    /// The UTC time since the Unix epoch.
+    pub time: Duration,
+    /// Whether the receiver reports a valid fix.
+    pub valid: bool,
+    /// The latitude in degrees, positive to the north.
+    pub latitude: Option<f64>,
+    /// The longitude in degrees, positive to the east.
+    pub longitude: Option<f64>,
+    pub speed_knots: Option<f64>,
+    /// The course over ground in degrees from the true north.
+    pub course: Option<f64>,
+}
+
+impl Gprmc {
+    /// Parses a $GPRMC or $GNRMC sentence. It gives `None` if the
+    /// sentence is of another kind, has a checksum mismatch or lacks
+    /// the date and time.
+    pub fn parse(sentence: &str) -> Option<Self> {
+        let sentence = sentence.trim();
+        let body = sentence.strip_prefix('$')?;
+        let body = match body.split_once('*') {
+            Some((body, checksum)) => {
+                let checksum = u8::from_str_radix(checksum.get(..2)?, 16).ok()?;
+                let actual = body.bytes().fold(0, |sum, byte| sum ^ byte);
+                if actual != checksum {
+                    return None;
+                }
+                body
+            }
+            None => body,
+        };
+
+        let fields: Vec<&str> = body.split(',').collect();
+        if !matches!(fields.first(), Some(&("GPRMC" | "GNRMC"))) || fields.len() < 10 {
+            return None;
+        }
+
+        let time = parse_nmea_time(fields[1])?;
+        let date = NaiveDate::parse_from_str(fields[9], "%d%m%y").ok()?;
+        let time = date.and_time(time).and_utc();
+        let time = Duration::new(
+            time.timestamp().try_into().ok()?,
+            time.timestamp_subsec_nanos(),
+        );
+
+        let number = |text: &str| text.parse::<f64>().ok();
+        let latitude = parse_nmea_angle(fields[3], 2).map(|deg| match fields[4] {
+            "S" => -deg,
+            _ => deg,
+        });
+        let longitude = parse_nmea_angle(fields[5], 3).map(|deg| match fields[6] {
+            "W" => -deg,
+            _ => deg,
+        });
+
+        Some(Self {
+            time,
+            valid: fields[2] == "A",
+            latitude,
+            longitude,
+            speed_knots: number(fields[7]),
+            course: number(fields[8]),
+        })
+    }
+}
+
+/// The content of a Velodyne position packet.
+#[derive(Debug, Clone)]
+pub struct VelodynePosition {
+    /// The capture time since the Unix epoch.
+    pub capture_time: Duration,
+    pub source: Ipv4Addr,
+    /// The top-of-hour timestamp of the sensor.
+    pub toh: Duration,
+    /// The PPS status. It is `None` if the byte is not recognized,
+    /// as on older models not reporting it.
+    pub pps: Option<PpsStatus>,
+    /// The NMEA sentence as received.
+    pub nmea: String,
+    pub gprmc: Option<Gprmc>,
+}
+
+impl VelodynePosition {
+    fn parse(capture_time: Duration, source: Ipv4Addr, payload: &[u8]) -> Option<Self> {
+        if payload.len() != VELODYNE_POSITION_PACKET_SIZE {
+            return None;
+        }
+
+        let toh = u32::from_le_bytes(payload[TOH_OFFSET..TOH_OFFSET + 4].try_into().unwrap());
+        let nmea = &payload[NMEA_OFFSET..NMEA_OFFSET + NMEA_SIZE];
+        let nmea_len = nmea.iter().position(|&byte| byte == 0).unwrap_or(NMEA_SIZE);
+        let nmea = String::from_utf8_lossy(&nmea[..nmea_len])
+            .trim()
+            .to_string();
+
+        Some(Self {
+            capture_time,
+            source,
+            toh: Duration::from_micros(toh as u64),
+            pps: PpsStatus::from_byte(payload[PPS_STATUS_OFFSET]),
+            gprmc: Gprmc::parse(&nmea),
+            nmea,
+        })
+    }
+}
+
+/// Reads the position packets in a Velodyne pcap file.
+pub fn velodyne_position_iter_from_file<P>(
+    path: P,
+    filter: &PacketFilter,
+) -> Result<impl Iterator<Item = Result<VelodynePosition>>>
+where
+    P: AsRef<Path>,
+{
+    let capture = CaptureReader::open(path, filter)?;
+
+    let iter = capture.filter_map(|packet| {
+        let packet = match packet {
+            Ok(packet) => packet,
+            Err(err) => return Some(Err(err)),
+        };
+        let udp = packet.udp()?;
+        let Some(VelodynePacket::Position) = VelodynePacket::classify(udp.payload) else {
+            return None;
+        };
+        VelodynePosition::parse(packet.time, *udp.source.ip(), udp.payload).map(Ok)
+    });
+    Ok(iter)
+}
+
+/// Builds a top-of-hour clock from the first position packet having a
+/// valid GPS fix. It gives `None` if no such packet is found.
+///
+/// The port in the filter is ignored, since the position packets are
+/// sent to another port than the data packets.
+pub fn gps_toh_clock<P>(path: P, filter: &PacketFilter) -> Result<Option<TohClock>>
+where
+    P: AsRef<Path>,
+{
+    let filter = PacketFilter {
+        port: None,
+        ..filter.clone()
+    };
+
+    for position in velodyne_position_iter_from_file(path, &filter)? {
+        let position = position?;
+        let Some(gprmc) = position.gprmc else {
+            continue;
+        };
+        if !gprmc.valid {
+            continue;
+        }
+        return Ok(Some(TohClock::from_reference(gprmc.time, position.toh)));
+    }
+
+    Ok(None)
+}
+
+/// The source of the hour of absolute timestamps.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TimeSource {
+    /// The GPS time in the position packets.
+    Gps,
+    /// The capture time of the first packet.
+    Capture,
+}
+
+impl TimeSource {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Gps => "gps",
+            Self::Capture => "capture",
+        }
+    }
+}
+
+/// Builds the clock giving absolute timestamps to the frames of a
+/// spinning LiDAR. The GPS time is preferred for Velodyne LiDARs, and
+/// the capture time is used otherwise.
+pub fn spinning_toh_clock<P>(
+    path: P,
+    filter: &PacketFilter,
+    lidar: &SpinningLidar,
+) -> Result<(TohClock, TimeSource)>
+where
+    P: AsRef<Path>,
+{
+    let path = path.as_ref();
+
+    if let SpinningLidar::Velodyne { .. } = lidar {
+        if let Some(clock) = gps_toh_clock(path, filter)? {
+            return Ok((clock, TimeSource::Gps));
+        }
+    }
+
+    let clock = TohClock::new(first_packet_time_in_pcap(path, filter)?);
+    Ok((clock, TimeSource::Capture))
+}
+
+/// Records the absolute timestamps of the converted frames in a
+/// `frames.csv` file next to them.
+pub struct FrameTimeLog {
+    writer: BufWriter<File>,
+    source: TimeSource,
+}
+
+impl FrameTimeLog {
+    pub const FILE_NAME: &'static str = "frames.csv";
+
+    pub fn create<P>(output_dir: P, source: TimeSource) -> Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        let path = output_dir.as_ref().join(Self::FILE_NAME);
+        let mut writer = BufWriter::new(File::create(path)?);
+        writeln!(writer, "index,file,toh,timestamp,time_source")?;
+        Ok(Self { writer, source })
+    }
+
+    /// Appends a frame given the top-of-hour timestamp and the
+    /// absolute time of the first firing.
+    pub fn record(
+        &mut self,
+        index: usize,
+        file_name: &str,
+        toh: Duration,
+        timestamp: Duration,
+    ) -> Result<()> {
+        writeln!(
+            self.writer,
+            "{},{},{:.6},{},{}",
+            index,
+            file_name,
+            toh.as_secs_f64(),
+            format_time(timestamp).unwrap_or_default(),
+            self.source.as_str()
+        )?;
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> Result<()> {
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+pub fn positions(args: Positions) -> Result<()> {
+    let Positions {
+        input,
+        output,
+        json,
+        interface,
+        source_ip,
+        port,
+    } = args;
+    let filter = PacketFilter {
+        interface,
+        source_ip,
+        port,
+    };
+
+    let json = json
+        || output
+            .as_ref()
+            .is_some_and(|path| path.extension().is_some_and(|ext| ext == "json"));
+    let mut writer: Box<dyn Write> = match &output {
+        Some(path) => Box::new(BufWriter::new(File::create(path)?)),
+        None => Box::new(BufWriter::new(io::stdout().lock())),
+    };
+
+    let positions = velodyne_position_iter_from_file(&input, &filter)?;
+
+    if json {
+        let records: Vec<_> = positions
+            .map(|position| -> Result<_> {
+                let position = position?;
+                let gprmc = position.gprmc;
+
+                Ok(json!({
+                    "capture_time": format_time(position.capture_time),
+                    "source": position.source.to_string(),
+                    "toh": position.toh.as_secs_f64(),
+                    "pps": position.pps.map(PpsStatus::as_str),
+                    "gps_time": gprmc.and_then(|gprmc| format_time(gprmc.time)),
+                    "valid": gprmc.map(|gprmc| gprmc.valid),
+                    "latitude": gprmc.and_then(|gprmc| gprmc.latitude),
+                    "longitude": gprmc.and_then(|gprmc| gprmc.longitude),
+                    "speed_knots": gprmc.and_then(|gprmc| gprmc.speed_knots),
+                    "course": gprmc.and_then(|gprmc| gprmc.course),
+                    "nmea": position.nmea,
+                }))
+            })
+            .collect::<Result<_>>()?;
+        serde_json::to_writer_pretty(&mut writer, &records)?;
+        writeln!(writer)?;
+    } else {
+        writeln!(
+            writer,
+            "capture_time,source,toh,pps,gps_time,valid,latitude,longitude,speed_knots,course,nmea"
+        )?;
+
+        for position in positions {
+            let position = position?;
+            let gprmc = position.gprmc;
+            let show = |value: Option<String>| value.unwrap_or_default();
+            let show_number = |value: Option<f64>| show(value.map(|value| value.to_string()));
+
+            writeln!(
+                writer,
+                "{},{},{:.6},{},{},{},{},{},{},{},\"{}\"",
+                show(format_time(position.capture_time)),
+                position.source,
+                position.toh.as_secs_f64(),
+                show(position.pps.map(|pps| pps.as_str().to_string())),
+                show(gprmc.and_then(|gprmc| format_time(gprmc.time))),
+                show(gprmc.map(|gprmc| gprmc.valid.to_string())),
+                show_number(gprmc.and_then(|gprmc| gprmc.latitude)),
+                show_number(gprmc.and_then(|gprmc| gprmc.longitude)),
+                show_number(gprmc.and_then(|gprmc| gprmc.speed_knots)),
+                show_number(gprmc.and_then(|gprmc| gprmc.course)),
+                position.nmea
+            )?;
+        }
+    }
+
+    writer.flush()?;
+    Ok(())
+}
+
+/// Parses a time written as hhmmss with optional fractional seconds.
+fn parse_nmea_time(text: &str) -> Option<NaiveTime> {
+    let (hms, frac) = match text.split_once('.') {
+        Some((hms, frac)) => (hms, frac),
+        None => (text, ""),
+    };
+    if hms.len() != 6 {
+        return None;
+    }
+
+    let hour = hms.get(0..2)?.parse().ok()?;
+    let min = hms.get(2..4)?.parse().ok()?;
+    let sec = hms.get(4..6)?.parse().ok()?;
+    let nanos = if frac.is_empty() {
+        0
+    } else {
+        let frac: f64 = format!("0.{frac}").parse().ok()?;
+        (frac * 1e9).round() as u32
+    };
+
+    NaiveTime::from_hms_nano_opt(hour, min, sec, nanos)
+}
+
+/// Parses an angle written as degrees followed by minutes, where the
+/// degrees take `deg_digits` digits.
+fn parse_nmea_angle(text: &str, deg_digits: usize) -> Option<f64> {
+    let degrees: f64 = text.get(..deg_digits)?.parse().ok()?;
+    let minutes: f64 = text.get(deg_digits..)?.parse().ok()?;
+    Some(degrees + minutes / 60.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use approx::assert_abs_diff_eq;
+
+    /// Builds a position packet with the top-of-hour timestamp in
+    /// microseconds, the PPS status byte and the NMEA sentence.
+    fn position_packet(toh: u32, pps: u8, nmea: &str) -> Vec<u8> {
+        let mut payload = vec![0; VELODYNE_POSITION_PACKET_SIZE];
+        payload[TOH_OFFSET..TOH_OFFSET + 4].copy_from_slice(&toh.to_le_bytes());
+        payload[PPS_STATUS_OFFSET] = pps;
+        payload[NMEA_OFFSET..NMEA_OFFSET + nmea.len()].copy_from_slice(nmea.as_bytes());
+        payload
+    }
+
+    #[test]
+    fn parse_position_packet() {
+        let nmea = "$GPRMC,103015.50,A,3539.1834,N,13944.4522,E,0.02,,161023,,,A*4E\r\n";
+        let payload = position_packet(1_815_500_000, 2, nmea);
+        let source = Ipv4Addr::new(192, 168, 1, 201);
+
+        let position = VelodynePosition::parse(Duration::from_secs(1), source, &payload).unwrap();
+        assert_eq!(position.capture_time, Duration::from_secs(1));
+        assert_eq!(position.source, source);
+        assert_eq!(position.toh, Duration::from_millis(1_815_500));
+        assert_eq!(position.pps, Some(PpsStatus::Locked));
+        assert_eq!(position.nmea, nmea.trim());
+
+        // 2023-10-16T10:30:15.5Z
+        let gprmc = position.gprmc.unwrap();
+        assert_eq!(gprmc.time, Duration::from_millis(1_697_452_215_500));
+        assert!(gprmc.valid);
+        assert_abs_diff_eq!(
+            gprmc.latitude.unwrap(),
+            35.0 + 39.1834 / 60.0,
+            epsilon = 1e-12
+        );
+        assert_abs_diff_eq!(
+            gprmc.longitude.unwrap(),
+            139.0 + 44.4522 / 60.0,
+            epsilon = 1e-12
+        );
+        assert_eq!(gprmc.speed_knots, Some(0.02));
+        assert_eq!(gprmc.course, None);
+    }
+
+    #[test]
+    fn parse_position_packet_without_fix() {
+        // Older models leave the PPS status and the sentence empty
+        // until a receiver is attached.
+        let payload = position_packet(0, 0xff, "");
+        let position =
+            VelodynePosition::parse(Duration::ZERO, Ipv4Addr::LOCALHOST, &payload).unwrap();
+        assert_eq!(position.pps, None);
+        assert_eq!(position.nmea, "");
+        assert!(position.gprmc.is_none());
+
+        assert!(
+            VelodynePosition::parse(Duration::ZERO, Ipv4Addr::LOCALHOST, &payload[1..]).is_none()
+        );
+    }
+
+    #[test]
+    fn parse_gprmc() {
+        let gprmc =
+            Gprmc::parse("$GPRMC,123519,A,4807.038,N,01131.000,W,022.4,084.4,230394,003.1,W*78")
+                .unwrap();
+
+        // 1994-03-23T12:35:19Z
+        assert_eq!(gprmc.time, Duration::from_secs(764_426_119));
+        assert!(gprmc.valid);
+        assert_abs_diff_eq!(gprmc.latitude.unwrap(), 48.1173, epsilon = 1e-12);
+        assert_abs_diff_eq!(
+            gprmc.longitude.unwrap(),
+            -(11.0 + 31.0 / 60.0),
+            epsilon = 1e-12
+        );
+        assert_eq!(gprmc.speed_knots, Some(22.4));
+        assert_eq!(gprmc.course, Some(84.4));
+
+        // The checksum is optional, and the GNSS variant is accepted.
+        let gprmc = Gprmc::parse("$GNRMC,000000.00,V,,,,,,,010124,,,N").unwrap();
+        assert_eq!(gprmc.time, Duration::from_secs(1_704_067_200));
+        assert!(!gprmc.valid);
+        assert_eq!(gprmc.latitude, None);
+        assert_eq!(gprmc.longitude, None);
+        assert_eq!(gprmc.speed_knots, None);
+    }
+
+    #[test]
+    fn reject_gprmc_with_checksum_mismatch() {
+        let body = "GPRMC,123519,A,4807.038,N,01131.000,E,022.4,084.4,230394,003.1,W";
+        assert!(Gprmc::parse(&format!("${body}*6A")).is_some());
+        assert!(Gprmc::parse(&format!("${body}*6B")).is_none());
+        assert!(Gprmc::parse(&format!("${body}*")).is_none());
+        assert!(Gprmc::parse(&format!("${body}*6")).is_none());
+        assert!(Gprmc::parse(&format!("${body}*ZZ")).is_none());
+    }
+
+    #[test]
+    fn reject_gprmc_with_multibyte_characters() {
+        // Multibyte characters in the checksum, the time, the date and
+        // the angles, some straddling the byte offsets read.
+        let rest = "A,4807.038,N,01131.000,E,022.4,084.4,230394,003.1,W";
+        assert!(Gprmc::parse(&format!("$GPRMC,123519,{rest}*6\u{e9}")).is_none());
+        assert!(Gprmc::parse(&format!("$GPRMC,1\u{e9}519,{rest}")).is_none());
+        assert!(
+            Gprmc::parse("$GPRMC,123519,A,4807.038,N,01131.000,E,022.4,084.4,23\u{e9}4").is_none()
+        );
+
+        let gprmc = Gprmc::parse(
+            "$GPRMC,123519,A,4\u{e9}07.038,N,01\u{e9}31.000,E,022.4,084.4,230394,003.1,W",
+        )
+        .unwrap();
+        assert_eq!(gprmc.time, Duration::from_secs(764_426_119));
+        assert_eq!(gprmc.latitude, None);
+        assert_eq!(gprmc.longitude, None);
+    }
+
+    #[test]
+    fn parse_nmea_time_and_angle() {
+        assert_eq!(
+            parse_nmea_time("103015"),
+            NaiveTime::from_hms_opt(10, 30, 15)
+        );
+        assert_eq!(
+            parse_nmea_time("235959.125"),
+            NaiveTime::from_hms_milli_opt(23, 59, 59, 125)
+        );
+        assert_eq!(parse_nmea_time("10301"), None);
+        assert_eq!(parse_nmea_time("246000"), None);
+        assert_eq!(parse_nmea_time("10301a.5"), None);
+        assert_eq!(parse_nmea_time("103015.x"), None);
+
+        assert_abs_diff_eq!(
+            parse_nmea_angle("4807.038", 2).unwrap(),
+            48.1173,
+            epsilon = 1e-12
+        );
+        assert_abs_diff_eq!(
+            parse_nmea_angle("01131.000", 3).unwrap(),
+            11.0 + 31.0 / 60.0,
+            epsilon = 1e-12
+        );
+        assert_eq!(parse_nmea_angle("", 2), None);
+        assert_eq!(parse_nmea_angle("48", 2), None);
+        assert_eq!(parse_nmea_angle("4x07.038", 2), None);
+    }
+}
@@ -0,0 +1,344 @@
+//! Selection of frames by time and by stride.
+//!
+//! Frames are first selected by their numbers with --start and --end.
+//! Among them, the frames starting between --start-time and
+//! --end-time are kept, and one of every --step frames is kept in turn.
+
+use crate::{
+    capture::PacketFilter,
+    index::{frame_index, indexed_frame_iter, FrameIndexEntry},
+    opts::{TimeSelector, VelodyneReturnMode},
+    spinning::SpinningLidar,
+    split::FrameSplit,
+    velodyne::VelodyneFrame,
+};
+use eyre::Result;
+use itertools::{Either, Itertools};
+use std::{iter, num::NonZeroUsize, ops::Range, path::Path, time::Duration};
+
+const DAY: Duration = Duration::from_secs(86400);
+const HALF_DAY: Duration = Duration::from_secs(43200);
+
+/// How frames are selected by time and by stride.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FrameSelector {
+    pub start_time: Option<TimeSelector>,
+    pub end_time: Option<TimeSelector>,
+    pub step: NonZeroUsize,
+}
+
+impl FrameSelector {
+    pub fn from_opts(
+        start_time: Option<TimeSelector>,
+        end_time: Option<TimeSelector>,
+        step: NonZeroUsize,
+    ) -> Self {
+        Self {
+            start_time,
+            end_time,
+            step,
+        }
+    }
+
+    /// Tells whether every frame is selected.
+    pub fn is_default(&self) -> bool {
+        self.start_time.is_none() && self.end_time.is_none() && self.step.get() == 1
+    }
+
+    /// Starts selecting among the frames of the zero-based indices in
+    /// `range`. Times are relative to `origin`, which is the start time
+    /// of the first frame of the file, or is taken from the first frame
+    /// fed if `None`.
+    pub fn sampler(&self, range: Range<usize>, origin: Option<Duration>) -> FrameSampler {
+        FrameSampler {
+            selector: *self,
+            range,
+            origin,
+            num_matched: 0,
+        }
+    }
+
+    /// Gives the indices of the selected frames in `range` among the
+    /// frames of an index.
+    pub fn select_entries(&self, entries: &[FrameIndexEntry], range: Range<usize>) -> Vec<usize> {
+        let mut sampler = self.sampler(range, entries.first().map(|entry| entry.start_time));
+
+        entries
+            .iter()
+            .enumerate()
+            .map(|(index, entry)| (index, sampler.select(index, entry.start_time)))
+            .take_while(|&(_, selection)| selection != Selection::Stop)
+            .filter(|&(_, selection)| selection == Selection::Keep)
+            .map(|(index, _)| index)
+            .collect()
+    }
+
+    /// Resolves the times from --start-time, inclusive, to --end-time,
+    /// exclusive.
+    fn window(&self, origin: Duration) -> (Option<Duration>, Option<Duration>) {
+        let start = self
+            .start_time
+            .map(|selector| resolve_time(selector, origin, origin));
+        let end = self
+            .end_time
+            .map(|selector| resolve_time(selector, origin, start.unwrap_or(origin)));
+        (start, end)
+    }
+}
+
+/// What becomes of a frame fed to a [FrameSampler].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Selection {
+    Keep,
+    Skip,
+    /// The frame is skipped, and so are all later frames.
+    Stop,
+}
+
+/// Selects frames fed one after another with a [FrameSelector].
+#[derive(Debug, Clone)]
+pub struct FrameSampler {
+    selector: FrameSelector,
+    range: Range<usize>,
+    origin: Option<Duration>,
+    num_matched: usize,
+}
+
+impl FrameSampler {
+    /// Selects the frame of the zero-based index, which starts at the
+    /// time since the Unix epoch. Frames are expected in order.
+    pub fn select(&mut self, index: usize, time: Duration) -> Selection {
+        if index >= self.range.end {
+            return Selection::Stop;
+        }
+
+        let origin = *self.origin.get_or_insert(time);
+        let (start, end) = self.selector.window(origin);
+
+        if index < self.range.start || start.is_some_and(|start| time < start) {
+            return Selection::Skip;
+        }
+        if end.is_some_and(|end| time >= end) {
+            return Selection::Stop;
+        }
+
+        let nth = self.num_matched;
+        self.num_matched += 1;
+
+        if nth % self.selector.step.get() == 0 {
+            Selection::Keep
+        } else {
+            Selection::Skip
+        }
+    }
+}
+
+/// Selects among the frames read from the start of a file, and gives
+/// the selected frames along with their zero-based indices. Reading
+/// stops once no later frame can be selected.
+pub fn select_frames<I, T, F>(
+    frames: I,
+    mut sampler: FrameSampler,
+    frame_time: F,
+) -> impl Iterator<Item = Result<(usize, T)>>
+where
+    I: Iterator<Item = Result<T>>,
+    F: Fn(&T) -> Duration,
+{
+    let mut frames = frames.enumerate();
+
+    iter::from_fn(move || loop {
+        let (index, frame) = frames.next()?;
+        let frame = match frame {
+            Ok(frame) => frame,
+            Err(err) => return Some(Err(err)),
+        };
+
+        match sampler.select(index, frame_time(&frame)) {
+            Selection::Keep => return Some(Ok((index, frame))),
+            Selection::Skip => {}
+            Selection::Stop => return None,
+        }
+    })
+    .fuse()
+}
+
+/// Reads the selected frames of a Velodyne or Hesai pcap file along
+/// with their zero-based indices. Unless every frame is selected, the
+/// frames are timed and located with the frame index.
+pub fn select_spinning_frames<P>(
+    path: P,
+    filter: &PacketFilter,
+    lidar: &SpinningLidar,
+    mode: VelodyneReturnMode,
+    split: &FrameSplit,
+    selector: &FrameSelector,
+) -> Result<impl Iterator<Item = Result<(usize, VelodyneFrame)>>>
+where
+    P: AsRef<Path>,
+{
+    if selector.is_default() {
+        let frames = lidar
+            .frame_iter_from_file(path, filter, mode.0, split)?
+            .enumerate()
+            .map(|(index, frame)| eyre::Ok((index, frame?)));
+        return Ok(Either::Left(frames));
+    }
+
+    let indexed = frame_index(&path, filter, lidar, mode, split)?;
+    let selected = selector.select_entries(&indexed.frames, 0..indexed.frames.len());
+    let frames = indexed_frame_iter(path, filter, lidar, mode, split, &indexed, selected)?
+        .map_ok(|(index, frame, _)| (index, frame));
+    Ok(Either::Right(frames))
+}
+
+/// Resolves a time to the time since the Unix epoch. `origin` is the
+/// start time of the first frame, and `base` is the time a time of day
+/// or a time after is taken from.
+fn resolve_time(selector: TimeSelector, origin: Duration, base: Duration) -> Duration {
+    match selector {
+        TimeSelector::At(time) => time,
+        TimeSelector::TimeOfDay(time) => {
+            // The time of day is taken on the day nearest to the base,
+            // so that the range may cross midnight.
+            let day_start = Duration::from_secs(base.as_secs() - base.as_secs() % DAY.as_secs());
+            let time = day_start + time;
+
+            if time + HALF_DAY < base {
+                time + DAY
+            } else if time > base + HALF_DAY {
+                time.saturating_sub(DAY)
+            } else {
+                time
+            }
+        }
+        TimeSelector::Offset(offset) => origin + offset,
+        TimeSelector::After(duration) => base + duration,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 2023-10-16T00:00:00Z.
+    const DAY_START: Duration = Duration::from_secs(1_697_414_400);
+
+    fn time_of_day(hour: u64, min: u64, sec: u64) -> Duration {
+        Duration::from_secs(hour * 3600 + min * 60 + sec)
+    }
+
+    fn selector(
+        start_time: Option<TimeSelector>,
+        end_time: Option<TimeSelector>,
+        step: usize,
+    ) -> FrameSelector {
+        FrameSelector::from_opts(start_time, end_time, NonZeroUsize::new(step).unwrap())
+    }
+
+    /// Feeds frames starting at the times and gives the selections.
+    fn feed(sampler: &mut FrameSampler, times: &[Duration]) -> Vec<Selection> {
+        times
+            .iter()
+            .enumerate()
+            .map(|(index, &time)| sampler.select(index, time))
+            .collect()
+    }
+
+    #[test]
+    fn time_of_day_across_midnight() {
+        let start = TimeSelector::TimeOfDay(time_of_day(23, 59, 50));
+        let end = TimeSelector::After(Duration::from_secs(20));
+        let selector = selector(Some(start), Some(end), 1);
+
+        let origin = DAY_START + time_of_day(23, 59, 40);
+        let times: Vec<_> = (0..7)
+            .map(|n| origin + Duration::from_secs(n * 5))
+            .collect();
+        let mut sampler = selector.sampler(0..times.len(), None);
+
+        use Selection as S;
+        assert_eq!(
+            feed(&mut sampler, &times),
+            [
+                S::Skip,
+                S::Skip,
+                S::Keep,
+                S::Keep,
+                S::Keep,
+                S::Keep,
+                S::Stop
+            ]
+        );
+    }
+
+    #[test]
+    fn end_time_of_day_is_taken_after_midnight() {
+        let origin = DAY_START + time_of_day(23, 59, 40);
+        let start = resolve_time(
+            TimeSelector::TimeOfDay(time_of_day(23, 59, 50)),
+            origin,
+            origin,
+        );
+        let end = resolve_time(
+            TimeSelector::TimeOfDay(time_of_day(0, 0, 10)),
+            origin,
+            start,
+        );
+
+        assert_eq!(start, DAY_START + time_of_day(23, 59, 50));
+        assert_eq!(end, DAY_START + DAY + time_of_day(0, 0, 10));
+    }
+
+    #[test]
+    fn offset_and_after_are_relative() {
+        let origin = DAY_START + time_of_day(12, 0, 0);
+        let start = resolve_time(
+            TimeSelector::Offset(Duration::from_secs(30)),
+            origin,
+            origin,
+        );
+        let end = resolve_time(TimeSelector::After(Duration::from_secs(20)), origin, start);
+
+        assert_eq!(start, origin + Duration::from_secs(30));
+        assert_eq!(end, origin + Duration::from_secs(50));
+    }
+
+    #[test]
+    fn step_counts_from_the_first_frame_in_window() {
+        let start = TimeSelector::Offset(Duration::from_secs(10));
+        let selector = selector(Some(start), None, 2);
+
+        let times: Vec<_> = (0..7)
+            .map(|n| DAY_START + Duration::from_secs(n * 5))
+            .collect();
+        let mut sampler = selector.sampler(0..times.len(), None);
+
+        use Selection as S;
+        assert_eq!(
+            feed(&mut sampler, &times),
+            [
+                S::Skip,
+                S::Skip,
+                S::Keep,
+                S::Skip,
+                S::Keep,
+                S::Skip,
+                S::Keep
+            ]
+        );
+    }
+
+    #[test]
+    fn frames_out_of_range() {
+        let selector = selector(None, None, 2);
+        let times: Vec<_> = (0..6).map(|n| DAY_START + Duration::from_secs(n)).collect();
+        let mut sampler = selector.sampler(1..4, None);
+
+        use Selection as S;
+        assert_eq!(
+            feed(&mut sampler, &times),
+            [S::Skip, S::Keep, S::Skip, S::Keep, S::Stop, S::Stop]
+        );
+    }
+}
@@ -2,16 +2,25 @@ mod gui;
 
 use self::gui::run_gui;
 use crate::{
+    capture::PacketFilter,
+    io::create_dyn_point_reader,
+    livox::livox_frame_iter_from_file,
     opts::{Show, VelodyneReturnMode},
+    ouster::{ouster_frame_iter_from_file, OusterMetadata},
+    select::{select_frames, select_spinning_frames, FrameSelector},
     show::gui::PointAndColor,
+    sources,
+    spinning::SpinningLidar,
+    split::FrameSplit,
     types::FileFormat,
-    utils::{build_velodyne_config, guess_file_format},
+    utils::{
+        detect_file_format, resolve_hesai_lidar, resolve_ouster_metadata, resolve_velodyne_lidar,
+    },
 };
 use eyre::{format_err, Result};
-use itertools::Itertools;
-use measurements::Length;
+use itertools::{chain, Itertools};
+use pcd_format::PcdPoint;
 use std::path::Path;
-use velodyne_lidar::ProductID;
 
 pub fn show(args: Show) -> Result<()> {
     let Show {
@@ -19,77 +28,197 @@ pub fn show(args: Show) -> Result<()> {
         input,
         velodyne_model,
         velodyne_return_mode,
+        velodyne_calibration,
+        ouster_metadata,
+        hesai_model,
+        hesai_angles,
+        interface,
+        source_ip,
+        port,
+        list_sources,
+        cut_angle,
+        frame_period,
+        frame_packets,
+        drop_partial_frames,
+        start_time,
+        end_time,
+        step,
     } = args;
+    let filter = PacketFilter {
+        interface,
+        source_ip,
+        port,
+    };
+    let split = FrameSplit::from_opts(cut_angle, frame_period, frame_packets, drop_partial_frames);
+    let selector = FrameSelector::from_opts(start_time, end_time, step);
+
+    if list_sources {
+        return sources::list_sources(&input, &filter, false);
+    }
 
     let format = match format {
         Some(format) => format,
-        None => guess_file_format(&input)
+        None => detect_file_format(&input)?
             .ok_or_else(|| format_err!("unable to guess file format of '{}'", input.display()))?,
     };
+    split.check_format(format)?;
 
     use FileFormat as F;
     match format {
-        F::LibpclPcd | F::NewslabPcd => show_pcd(&input)?,
+        F::LibpclPcd
+        | F::LibpclExtPcd
+        | F::LibpclXyzPcd
+        | F::LibpclXyziPcd
+        | F::LibpclXyzrgbaPcd
+        | F::LibpclXyziNormalPcd
+        | F::LibpclNormalPcd
+        | F::LibpclXyzlPcd
+        | F::NewslabPcd
+        | F::NewslabV2Pcd
+        | F::XyzirtPcd
+        | F::OusterPcd
+        | F::LivoxPcd => show_pcd(&input)?,
         F::VelodynePcap => {
-            let velodyne_model =
-                velodyne_model.ok_or_else(|| format_err!("--velodyne-mode must be set"))?;
-            let velodyne_return_mode = velodyne_return_mode
-                .ok_or_else(|| format_err!("--velodyne-return-mode must be set"))?;
-
-            show_velodyne_pcap(&input, velodyne_model, velodyne_return_mode)?;
+            let (lidar, mode) = resolve_velodyne_lidar(
+                &input,
+                &filter,
+                velodyne_model,
+                velodyne_return_mode,
+                velodyne_calibration.as_deref(),
+            )?;
+            show_spinning_pcap(&input, &filter, &lidar, mode, &split, &selector)?;
+        }
+        F::HesaiPcap => {
+            let (lidar, mode) =
+                resolve_hesai_lidar(&input, &filter, hesai_model, hesai_angles.as_deref())?;
+            show_spinning_pcap(&input, &filter, &lidar, mode, &split, &selector)?;
+        }
+        F::OusterPcap => {
+            let metadata = resolve_ouster_metadata(&input, ouster_metadata.as_deref())?;
+            show_ouster_pcap(&input, &filter, &metadata, &selector)?;
         }
+        F::LivoxLvx => show_livox_lvx(&input, &selector)?,
         F::RawBin => todo!(),
     }
 
     Ok(())
 }
 
-fn show_velodyne_pcap<P>(path: P, model: ProductID, mode: VelodyneReturnMode) -> Result<()>
+fn show_spinning_pcap<P>(
+    path: P,
+    filter: &PacketFilter,
+    lidar: &SpinningLidar,
+    mode: VelodyneReturnMode,
+    split: &FrameSplit,
+    selector: &FrameSelector,
+) -> Result<()>
 where
     P: AsRef<Path>,
 {
-    let config = build_velodyne_config(model, mode.0)?;
-    let frames = velodyne_lidar::iter::frame_xyz_iter_from_file(config, path)?;
+    use velodyne_lidar::ReturnMode as R;
+
+    let frames = select_spinning_frames(path, filter, lidar, mode, split, selector)?;
+
+    // Single returns are drawn in white. Dual returns are drawn in
+    // green for the strongest and in blue for the last.
+    let (strongest_color, last_color) = match mode.0 {
+        R::Dual => ([0.0, 1.0, 0.0], [0.0, 0.0, 1.0]),
+        _ => ([1.0, 1.0, 1.0], [1.0, 1.0, 1.0]),
+    };
 
     let frames: Vec<_> = frames
         .map(|frame| -> Result<_> {
-            let frame = frame?;
+            let (_, frame) = frame?;
+
+            let points: Vec<_> = frame
+                .points
+                .into_iter()
+                .flat_map(|point| {
+                    let to_f32 = |[x, y, z]: [f64; 3]| [x as f32, y as f32, z as f32];
+
+                    let strongest = point.strongest.map(|measurement| PointAndColor {
+                        point: to_f32(measurement.xyz),
+                        color: strongest_color,
+                    });
+                    let last = point.last.map(|measurement| PointAndColor {
+                        point: to_f32(measurement.xyz),
+                        color: last_color,
+                    });
+                    chain!(strongest, last)
+                })
+                .collect();
+
+            Ok(points)
+        })
+        .try_collect()?;
+
+    run_gui(frames.into_iter());
+
+    Ok(())
+}
 
+fn show_ouster_pcap<P>(
+    path: P,
+    filter: &PacketFilter,
+    metadata: &OusterMetadata,
+    selector: &FrameSelector,
+) -> Result<()>
+where
+    P: AsRef<Path>,
+{
+    let frames = select_frames(
+        ouster_frame_iter_from_file(path, filter, metadata)?,
+        selector.sampler(0..usize::MAX, None),
+        |frame| frame.start_time,
+    );
+
+    let frames: Vec<_> = frames
+        .map(|frame| -> Result<_> {
+            let (_, frame) = frame?;
+            let points: Vec<_> = frame
+                .samples
+                .into_iter()
+                .filter(|sample| sample.range > 0.0)
+                .map(|sample| {
+                    let [x, y, z] = sample.xyz;
+                    PointAndColor {
+                        point: [x as f32, y as f32, z as f32],
+                        color: [1.0, 1.0, 1.0],
+                    }
+                })
+                .collect();
+
+            Ok(points)
+        })
+        .try_collect()?;
+
+    run_gui(frames.into_iter());
+
+    Ok(())
+}
+
+fn show_livox_lvx<P>(path: P, selector: &FrameSelector) -> Result<()>
+where
+    P: AsRef<Path>,
+{
+    let frames = select_frames(
+        livox_frame_iter_from_file(path)?,
+        selector.sampler(0..usize::MAX, None),
+        |frame| frame.start_time,
+    );
+
+    let frames: Vec<_> = frames
+        .map(|frame| -> Result<_> {
+            let (_, frame) = frame?;
             let points: Vec<_> = frame
-                .into_firing_iter()
-                .flat_map(|firing| {
-                    firing.into_point_iter().flat_map(|point| {
-                        use velodyne_lidar::types::point::Point as P;
-
-                        let length_to_f32 = |[x, y, z]: [Length; 3]| {
-                            [
-                                x.as_meters() as f32,
-                                y.as_meters() as f32,
-                                z.as_meters() as f32,
-                            ]
-                        };
-
-                        match point {
-                            P::Single(point) => {
-                                vec![PointAndColor {
-                                    point: length_to_f32(point.measurement.xyz),
-                                    color: [1.0, 1.0, 1.0],
-                                }]
-                            }
-                            P::Dual(point) => {
-                                vec![
-                                    PointAndColor {
-                                        point: length_to_f32(point.measurements.strongest.xyz),
-                                        color: [0.0, 1.0, 0.0],
-                                    },
-                                    PointAndColor {
-                                        point: length_to_f32(point.measurements.last.xyz),
-                                        color: [0.0, 0.0, 1.0],
-                                    },
-                                ]
-                            }
-                        }
-                    })
+                .samples
+                .into_iter()
+                .map(|sample| {
+                    let [x, y, z] = sample.xyz;
+                    PointAndColor {
+                        point: [x as f32, y as f32, z as f32],
+                        color: [1.0, 1.0, 1.0],
+                    }
                 })
                 .collect();
 
@@ -106,13 +235,11 @@ fn show_pcd<P>(path: P) -> Result<()>
 where
     P: AsRef<Path>,
 {
-    let reader = pcd_rs::DynReader::open(path)?;
-    let points: Vec<_> = reader
-        .map(|record| -> Result<_> {
-            let record = record?;
-            let point = record
-                .to_xyz()
-                .ok_or_else(|| format_err!("No x, y or z field found"))?;
+    let (_, points) = create_dyn_point_reader(path)?;
+    let points: Vec<_> = points
+        .map(|point| -> Result<_> {
+            let point = point?;
+            let point = [point.x() as f32, point.y() as f32, point.z() as f32];
             let color = [1.0, 1.0, 1.0];
             Ok(gui::PointAndColor { point, color })
         })
@@ -0,0 +1,285 @@
+//! The UDP sources in a capture, which tell apart the sensors mixed in
+//! a multi-LiDAR pcap file.
+
+use crate::{
+    capture::{CaptureReader, PacketFilter},
+    hesai::HesaiModel,
+    packet::{velodyne_product_id_from_byte, velodyne_return_mode_from_byte, VelodynePacket},
+};
+use eyre::{bail, format_err, Result};
+use serde_json::json;
+use std::{collections::BTreeMap, fmt, net::Ipv4Addr, path::Path, str::FromStr};
+use velodyne_lidar::{ProductID, ReturnMode};
+
+/// Selects a UDP source by the IP address and optionally by the
+/// destination port, written as `IP` or `IP:PORT`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SourceSelector {
+    pub ip: Ipv4Addr,
+    pub port: Option<u16>,
+}
+
+impl SourceSelector {
+    /// Tells whether the selector matches the packets selected by the
+    /// filter. The filter must select the source IP address, and also
+    /// the port if the selector has one.
+    pub fn matches_filter(&self, filter: &PacketFilter) -> bool {
+        if filter.source_ip != Some(self.ip) {
+            return false;
+        }
+        match self.port {
+            Some(port) => filter.port == Some(port),
+            None => true,
+        }
+    }
+}
+
+impl FromStr for SourceSelector {
+    type Err = eyre::Error;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let (ip, port) = match text.split_once(':') {
+            Some((ip, port)) => {
+                let port = port
+                    .parse()
+                    .map_err(|_| format_err!("invalid port in source '{text}'"))?;
+                (ip, Some(port))
+            }
+            None => (text, None),
+        };
+        let ip = ip
+            .parse()
+            .map_err(|_| format_err!("invalid IP address in source '{text}'"))?;
+
+        Ok(Self { ip, port })
+    }
+}
+
+impl fmt::Display for SourceSelector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.port {
+            Some(port) => write!(f, "{}:{}", self.ip, port),
+            None => write!(f, "{}", self.ip),
+        }
+    }
+}
+
+/// Parses a per-source value written as `IP[:PORT]=VALUE`.
+pub fn parse_source_value<T>(text: &str) -> Result<(SourceSelector, T), String>
+where
+    T: FromStr,
+{
+    let Some((source, value)) = text.split_once('=') else {
+        return Err(format!("expect IP[:PORT]=VALUE, but get '{text}'"));
+    };
+    let source: SourceSelector = source.parse().map_err(|err| format!("{err}"))?;
+    let Ok(value) = value.parse() else {
+        return Err(format!("invalid value '{value}' for source {source}"));
+    };
+    Ok((source, value))
+}
+
+/// Finds the value given to the source selected by the filter among
+/// per-source values. The first match wins.
+pub fn find_source_value<T>(values: &[(SourceSelector, T)], filter: &PacketFilter) -> Option<T>
+where
+    T: Clone,
+{
+    values
+        .iter()
+        .find(|(source, _)| source.matches_filter(filter))
+        .map(|(_, value)| value.clone())
+}
+
+/// Warns about the per-source values given to an option that select
+/// none of the sources read by the filters.
+pub fn warn_unmatched_source_values<T>(
+    values: &[(SourceSelector, T)],
+    filters: &[PacketFilter],
+    option: &str,
+) {
+    for (source, _) in values {
+        if !filters.iter().any(|filter| source.matches_filter(filter)) {
+            eprintln!("Warning: the source {source} given to {option} is not found");
+        }
+    }
+}
+
+/// The kind of LiDAR packets sent from a source, told by the first
+/// recognized packet.
+#[derive(Debug, Clone, Copy)]
+pub enum SourceLidar {
+    Velodyne {
+        model: Option<ProductID>,
+        mode: Option<ReturnMode>,
+    },
+    VelodynePosition,
+    Hesai {
+        model: HesaiModel,
+        mode: Option<ReturnMode>,
+    },
+}
+
+impl SourceLidar {
+    fn from_payload(payload: &[u8]) -> Option<Self> {
+        let lidar = match VelodynePacket::classify(payload) {
+            Some(VelodynePacket::Data {
+                return_mode,
+                product_id,
+            }) => Self::Velodyne {
+                model: velodyne_product_id_from_byte(product_id),
+                mode: velodyne_return_mode_from_byte(return_mode),
+            },
+            Some(VelodynePacket::Position) => Self::VelodynePosition,
+            None => {
+                let model = HesaiModel::from_packet(payload)?;
+                Self::Hesai {
+                    model,
+                    mode: model.return_mode_of_packet(payload),
+                }
+            }
+        };
+        Some(lidar)
+    }
+
+    /// Tells whether the source sends point data which can be
+    /// assembled into frames.
+    pub fn has_frames(&self) -> bool {
+        matches!(self, Self::Velodyne { .. } | Self::Hesai { .. })
+    }
+}
+
+impl fmt::Display for SourceLidar {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let show_mode = |mode: Option<ReturnMode>| match mode {
+            Some(mode) => format!("{:?}", mode).to_lowercase(),
+            None => "unknown".to_string(),
+        };
+
+        match *self {
+            Self::Velodyne { model, mode } => {
+                let model = match model {
+                    Some(model) => model.to_string(),
+                    None => "unknown".to_string(),
+                };
+                write!(f, "velodyne {} {}", model, show_mode(mode))
+            }
+            Self::VelodynePosition => write!(f, "velodyne position"),
+            Self::Hesai { model, mode } => write!(f, "hesai {} {}", model, show_mode(mode)),
+        }
+    }
+}
+
+/// The UDP datagrams sent from an IP address to a port.
+#[derive(Debug, Clone)]
+pub struct CaptureSource {
+    pub ip: Ipv4Addr,
+    pub port: u16,
+    pub num_packets: usize,
+    pub lidar: Option<SourceLidar>,
+}
+
+impl CaptureSource {
+    /// Gives the name of the source used in file names.
+    pub fn dir_name(&self) -> String {
+        format!("{}_{}", self.ip, self.port)
+    }
+}
+
+/// Scans the UDP sources in a pcap file, ordered by the IP address and
+/// the port.
+pub fn scan_sources<P>(path: P, filter: &PacketFilter) -> Result<Vec<CaptureSource>>
+where
+    P: AsRef<Path>,
+{
+    let mut capture = CaptureReader::open(path, filter)?;
+    let mut sources: BTreeMap<(Ipv4Addr, u16), CaptureSource> = BTreeMap::new();
+
+    while let Some(packet) = capture.next_packet()? {
+        let Some(packet) = packet.udp() else {
+            continue;
+        };
+        let ip = *packet.source.ip();
+        let port = packet.destination.port();
+
+        let source = sources.entry((ip, port)).or_insert(CaptureSource {
+            ip,
+            port,
+            num_packets: 0,
+            lidar: None,
+        });
+        source.num_packets += 1;
+        if source.lidar.is_none() {
+            source.lidar = SourceLidar::from_payload(packet.payload);
+        }
+    }
+
+    Ok(sources.into_values().collect())
+}
+
+/// Scans the sources sending point data in a pcap file. It fails if
+/// none is found.
+pub fn scan_frame_sources<P>(path: P, filter: &PacketFilter) -> Result<Vec<CaptureSource>>
+where
+    P: AsRef<Path>,
+{
+    let path = path.as_ref();
+    let sources: Vec<_> = scan_sources(path, filter)?
+        .into_iter()
+        .filter(|source| source.lidar.is_some_and(|lidar| lidar.has_frames()))
+        .collect();
+
+    if sources.is_empty() {
+        bail!("no LiDAR data packets found in '{}'", path.display());
+    }
+    Ok(sources)
+}
+
+/// Warns if the data packets sniffed from a pcap file come from
+/// several sources, whose frames would be garbled together.
+pub fn warn_mixed_sources(num_sources: usize) {
+    if num_sources > 1 {
+        eprintln!(
+            "Warning: the data packets come from {num_sources} sources. \
+             Please select one with --source-ip and --port, or list them with --list-sources"
+        );
+    }
+}
+
+/// Prints the UDP sources in a pcap file.
+pub fn list_sources<P>(path: P, filter: &PacketFilter, json: bool) -> Result<()>
+where
+    P: AsRef<Path>,
+{
+    let sources = scan_sources(path, filter)?;
+
+    if json {
+        let sources: Vec<_> = sources
+            .iter()
+            .map(|source| {
+                json!({
+                    "source": source.ip.to_string(),
+                    "port": source.port,
+                    "packets": source.num_packets,
+                    "lidar": source.lidar.map(|lidar| lidar.to_string()),
+                })
+            })
+            .collect();
+        println!("{}", serde_json::to_string_pretty(&sources)?);
+        return Ok(());
+    }
+
+    println!("source\tport\tpackets\tlidar");
+    sources.iter().for_each(|source| {
+        let lidar = match source.lidar {
+            Some(lidar) => lidar.to_string(),
+            None => "-".to_string(),
+        };
+        println!(
+            "{}\t{}\t{}\t{}",
+            source.ip, source.port, source.num_packets, lidar
+        );
+    });
+
+    Ok(())
+}
@@ -0,0 +1,89 @@
+//! The spinning LiDARs whose pcap files are decoded into
+//! [VelodyneFrame]s.
+
+use crate::{
+    calibration::VelodyneCalibration,
+    capture::PacketFilter,
+    hesai::{hesai_frame_iter_from_file, HesaiAngles, HesaiModel},
+    index::FrameIndexEntry,
+    split::FrameSplit,
+    velodyne::{velodyne_frame_iter_from_file, VelodyneFrame},
+};
+use eyre::Result;
+use itertools::Either;
+use std::{fmt, path::Path};
+use velodyne_lidar::{ProductID, ReturnMode};
+
+/// A LiDAR model along with the laser angles to decode its packets.
+#[derive(Debug, Clone)]
+pub enum SpinningLidar {
+    Velodyne {
+        model: ProductID,
+        calibration: Option<VelodyneCalibration>,
+    },
+    Hesai {
+        model: HesaiModel,
+        angles: HesaiAngles,
+    },
+}
+
+impl SpinningLidar {
+    /// Reads the frames in a pcap file of the LiDAR, split as told by
+    /// `split`.
+    pub fn frame_iter_from_file<P>(
+        &self,
+        path: P,
+        filter: &PacketFilter,
+        mode: ReturnMode,
+        split: &FrameSplit,
+    ) -> Result<impl Iterator<Item = Result<VelodyneFrame>>>
+    where
+        P: AsRef<Path>,
+    {
+        self.frame_iter_from_entry(path, filter, mode, split, None)
+    }
+
+    /// Reads the frames in a pcap file of the LiDAR from the frame
+    /// recorded in the index, or from the start if it is `None`.
+    pub fn frame_iter_from_entry<P>(
+        &self,
+        path: P,
+        filter: &PacketFilter,
+        mode: ReturnMode,
+        split: &FrameSplit,
+        start: Option<&FrameIndexEntry>,
+    ) -> Result<impl Iterator<Item = Result<VelodyneFrame>>>
+    where
+        P: AsRef<Path>,
+    {
+        let frames = match self {
+            Self::Velodyne { model, calibration } => {
+                let frames = velodyne_frame_iter_from_file(
+                    path,
+                    filter,
+                    *model,
+                    mode,
+                    split,
+                    calibration.as_ref(),
+                    start,
+                )?;
+                Either::Left(frames)
+            }
+            Self::Hesai { model, angles } => {
+                let frames =
+                    hesai_frame_iter_from_file(path, filter, *model, mode, split, angles, start)?;
+                Either::Right(frames)
+            }
+        };
+        Ok(frames)
+    }
+}
+
+impl fmt::Display for SpinningLidar {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Velodyne { model, .. } => write!(f, "velodyne {model}"),
+            Self::Hesai { model, .. } => write!(f, "hesai {model}"),
+        }
+    }
+}
@@ -0,0 +1,165 @@
+//! Splitting of the firings of a spinning LiDAR into frames.
+//!
+//! By default, a frame is a full revolution cut where the azimuth wraps
+//! around. The cut can be moved to another azimuth, or frames can span
+//! a fixed duration or a fixed number of packets instead.
+
+use crate::types::FileFormat;
+use eyre::{ensure, Result};
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+/// Where the frames are cut.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FrameCut {
+    /// Cut when the azimuth passes the angle in degrees, which is
+    /// clockwise from the y axis as in Velodyne packets.
+    Angle(f64),
+    /// Cut at the multiples of the duration since the top of the hour.
+    Period(Duration),
+    /// Cut every the number of data packets.
+    Packets(usize),
+}
+
+/// How the firings are split into frames.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct FrameSplit {
+    pub cut: FrameCut,
+    /// Drop the first and last frames if they do not span a full
+    /// frame, which happens when the capture starts or stops in the
+    /// middle of a rotation.
+    pub drop_partial: bool,
+}
+
+impl FrameSplit {
+    /// Builds the frame split from the command line options. At most
+    /// one of the cuts is expected to be set.
+    pub fn from_opts(
+        cut_angle: Option<f64>,
+        frame_period: Option<Duration>,
+        frame_packets: Option<usize>,
+        drop_partial: bool,
+    ) -> Self {
+        let cut = match (cut_angle, frame_period, frame_packets) {
+            (_, Some(period), _) => FrameCut::Period(period),
+            (_, _, Some(count)) => FrameCut::Packets(count),
+            (Some(angle), _, _) => FrameCut::Angle(angle),
+            (None, None, None) => FrameCut::Angle(0.0),
+        };
+
+        Self { cut, drop_partial }
+    }
+
+    /// Tells whether the frames are the full revolutions cut at the
+    /// azimuth wrap, which is the only split supported for LiDARs
+    /// framing the data by themselves.
+    pub fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Fails if the frames of the input format cannot be split as
+    /// told. Only the frames of Velodyne and Hesai pcap files are
+    /// assembled from firings.
+    pub fn check_format(&self, format: FileFormat) -> Result<()> {
+        ensure!(
+            self.is_default() || matches!(format, FileFormat::VelodynePcap | FileFormat::HesaiPcap),
+            "--cut-angle, --frame-period, --frame-packets and --drop-partial-frames \
+             do not support the {format} format"
+        );
+        Ok(())
+    }
+}
+
+impl Default for FrameSplit {
+    fn default() -> Self {
+        Self {
+            cut: FrameCut::Angle(0.0),
+            drop_partial: false,
+        }
+    }
+}
+
+/// Tracks the packets and firings fed to a frame, and decides where
+/// the next frame starts.
+#[derive(Debug, Clone)]
+pub struct FrameSplitter {
+    split: FrameSplit,
+    last_azimuth: Option<f64>,
+    last_window: Option<u128>,
+    num_packets: usize,
+    num_cuts: usize,
+}
+
+impl FrameSplitter {
+    pub fn new(split: FrameSplit) -> Self {
+        Self {
+            split,
+            last_azimuth: None,
+            last_window: None,
+            num_packets: 0,
+            num_cuts: 0,
+        }
+    }
+
+    /// Feeds a data packet. It tells whether the frame is cut before
+    /// the packet.
+    pub fn feed_packet(&mut self) -> bool {
+        let cut = match self.split.cut {
+            FrameCut::Packets(count) if self.num_packets >= count => {
+                self.num_packets = 0;
+                true
+            }
+            _ => false,
+        };
+        self.num_packets += 1;
+        cut
+    }
+
+    /// Feeds a firing given its azimuth in degrees and the
+    /// top-of-hour timestamp of its first laser. It tells whether the
+    /// frame is cut before the firing.
+    pub fn feed_firing(&mut self, azimuth: f64, toh: Option<Duration>) -> bool {
+        match self.split.cut {
+            FrameCut::Angle(angle) => {
+                // The azimuth is measured from the cut angle, so that
+                // the cut is where the relative azimuth wraps around.
+                let azimuth = (azimuth - angle).rem_euclid(360.0);
+                let cut = matches!(self.last_azimuth, Some(last) if azimuth < last);
+                self.last_azimuth = Some(azimuth);
+                cut
+            }
+            FrameCut::Period(period) => {
+                let Some(toh) = toh else {
+                    return false;
+                };
+                let window = toh.as_nanos() / period.as_nanos().max(1);
+                let cut = matches!(self.last_window, Some(last) if window != last);
+                self.last_window = Some(window);
+                cut
+            }
+            FrameCut::Packets(_) => false,
+        }
+    }
+
+    /// Tells whether a frame ended by a cut is kept, and counts the
+    /// cut.
+    pub fn keep_cut_frame(&mut self) -> bool {
+        let is_first = self.num_cuts == 0;
+        self.num_cuts += 1;
+
+        // A frame cut by the packet count starts with the capture and
+        // is complete.
+        let partial = is_first && !matches!(self.split.cut, FrameCut::Packets(_));
+        !(self.split.drop_partial && partial)
+    }
+
+    /// Tells whether the frame left at the end of the capture is kept.
+    pub fn keep_last_frame(&self) -> bool {
+        let partial = match self.split.cut {
+            FrameCut::Packets(count) => self.num_packets < count,
+            FrameCut::Angle(_) | FrameCut::Period(_) => true,
+        };
+        !(self.split.drop_partial && partial)
+    }
+}
@@ -0,0 +1,312 @@
+//! Naming of converted frame files after a template.
+//!
+//! A template is a path relative to the output directory, without the
+//! file extension, where placeholders in braces are replaced by the
+//! properties of each frame. `{{` and `}}` stand for literal braces.
+
+use eyre::{bail, ensure, format_err};
+use std::{
+    path::{Component, Path, PathBuf},
+    str::FromStr,
+    time::Duration,
+};
+
+/// A property of a frame to be placed in a file name.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Placeholder {
+    /// The zero-based frame index.
+    Index,
+    /// The one-based frame number.
+    Number,
+    /// The start time in seconds since the Unix epoch.
+    StartTime,
+    /// The end time in seconds since the Unix epoch.
+    EndTime,
+    /// The return mode, either "strongest" or "last".
+    Mode,
+    /// The identifier of the sensor.
+    Sensor,
+}
+
+impl Placeholder {
+    fn from_name(name: &str) -> Option<Self> {
+        let placeholder = match name {
+            "index" => Self::Index,
+            "number" => Self::Number,
+            "start_time" => Self::StartTime,
+            "end_time" => Self::EndTime,
+            "mode" => Self::Mode,
+            "sensor" => Self::Sensor,
+            _ => return None,
+        };
+        Some(placeholder)
+    }
+
+    /// Tells whether the placeholder tells apart the frames of a file.
+    fn is_per_frame(self) -> bool {
+        matches!(
+            self,
+            Self::Index | Self::Number | Self::StartTime | Self::EndTime
+        )
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Part {
+    Text(String),
+    Placeholder {
+        placeholder: Placeholder,
+        /// The minimum width of a number, padded with zeros.
+        width: usize,
+    },
+}
+
+/// A template naming the file of each converted frame.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OutputTemplate {
+    parts: Vec<Part>,
+}
+
+impl OutputTemplate {
+    /// The template used if none is given. The returns of Velodyne and
+    /// Hesai LiDARs are written to `strongest/` and `last/`.
+    pub fn default_for(has_modes: bool) -> Self {
+        let text = if has_modes {
+            "{mode}/{index:06}"
+        } else {
+            "{index:06}"
+        };
+        text.parse().unwrap()
+    }
+
+    /// Tells whether the template contains the placeholder.
+    pub fn uses(&self, placeholder: Placeholder) -> bool {
+        self.parts.iter().any(|part| {
+            matches!(part, Part::Placeholder { placeholder: other, .. } if *other == placeholder)
+        })
+    }
+
+    /// Fails if the template cannot name the files of a LiDAR, which
+    /// gives the return modes of Velodyne and Hesai LiDARs, or `None`
+    /// for other LiDARs.
+    pub fn check(&self, num_modes: Option<usize>) -> eyre::Result<()> {
+        match num_modes {
+            None => ensure!(
+                !self.uses(Placeholder::Mode),
+                "{{mode}} is only supported for Velodyne and Hesai LiDARs"
+            ),
+            Some(num_modes) => ensure!(
+                num_modes < 2 || self.uses(Placeholder::Mode),
+                "the output template must contain {{mode}} to tell apart the dual returns"
+            ),
+        }
+        Ok(())
+    }
+
+    /// Names the file of a frame, relative to the output directory.
+    pub fn render(&self, fields: &FrameFields<'_>, extension: &str) -> PathBuf {
+        let mut text = String::new();
+
+        for part in &self.parts {
+            match *part {
+                Part::Text(ref part) => text.push_str(part),
+                Part::Placeholder { placeholder, width } => {
+                    let value = match placeholder {
+                        Placeholder::Index => format!("{:0width$}", fields.index),
+                        Placeholder::Number => format!("{:0width$}", fields.index + 1),
+                        Placeholder::StartTime => format_seconds(fields.start_time),
+                        Placeholder::EndTime => format_seconds(fields.end_time),
+                        Placeholder::Mode => fields.mode.unwrap_or_default().to_string(),
+                        Placeholder::Sensor => fields.sensor.unwrap_or_default().to_string(),
+                    };
+                    text.push_str(&value);
+                }
+            }
+        }
+
+        text.push('.');
+        text.push_str(extension);
+        PathBuf::from(text)
+    }
+}
+
+impl FromStr for OutputTemplate {
+    type Err = eyre::Error;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let mut parts = vec![];
+        let mut literal = String::new();
+        let mut chars = text.chars();
+
+        while let Some(ch) = chars.next() {
+            match ch {
+                '{' if chars.as_str().starts_with('{') => {
+                    chars.next();
+                    literal.push('{');
+                }
+                '}' if chars.as_str().starts_with('}') => {
+                    chars.next();
+                    literal.push('}');
+                }
+                '{' => {
+                    let rest = chars.as_str();
+                    let end = rest
+                        .find('}')
+                        .ok_or_else(|| format_err!("unclosed placeholder in '{text}'"))?;
+                    let (name, spec) = match rest[..end].split_once(':') {
+                        Some((name, spec)) => (name, Some(spec)),
+                        None => (&rest[..end], None),
+                    };
+                    chars = rest[end + 1..].chars();
+
+                    let placeholder = Placeholder::from_name(name)
+                        .ok_or_else(|| format_err!("unknown placeholder '{{{name}}}'"))?;
+                    let width = match spec {
+                        None => 0,
+                        Some(spec) => {
+                            ensure!(
+                                matches!(placeholder, Placeholder::Index | Placeholder::Number),
+                                "only {{index}} and {{number}} accept a width"
+                            );
+                            spec.parse()
+                                .map_err(|_| format_err!("invalid width '{spec}' of {{{name}}}"))?
+                        }
+                    };
+
+                    if !literal.is_empty() {
+                        parts.push(Part::Text(std::mem::take(&mut literal)));
+                    }
+                    parts.push(Part::Placeholder { placeholder, width });
+                }
+                '}' => bail!("unmatched '}}' in '{text}'"),
+                ch => literal.push(ch),
+            }
+        }
+        if !literal.is_empty() {
+            parts.push(Part::Text(literal));
+        }
+
+        let template = Self { parts };
+
+        // The files are kept in the output directory.
+        let is_inside = Path::new(text)
+            .components()
+            .all(|component| matches!(component, Component::Normal(_) | Component::CurDir));
+        ensure!(
+            is_inside,
+            "the output template '{text}' must be a relative path inside the output directory"
+        );
+
+        // Frames would overwrite each other's file otherwise.
+        let is_per_frame = template.parts.iter().any(|part| {
+            matches!(part, Part::Placeholder { placeholder, .. } if placeholder.is_per_frame())
+        });
+        ensure!(
+            is_per_frame,
+            "the output template must contain one of {{index}}, {{number}}, \
+             {{start_time}} and {{end_time}}"
+        );
+
+        Ok(template)
+    }
+}
+
+/// The properties of a frame placed in its file name.
+#[derive(Debug, Clone, Copy)]
+pub struct FrameFields<'a> {
+    pub index: usize,
+    /// The start time since the Unix epoch.
+    pub start_time: Duration,
+    /// The end time since the Unix epoch.
+    pub end_time: Duration,
+    pub mode: Option<&'a str>,
+    pub sensor: Option<&'a str>,
+}
+
+/// Formats a time as seconds with microseconds, such as
+/// 1697423112.123456.
+fn format_seconds(time: Duration) -> String {
+    format!("{}.{:06}", time.as_secs(), time.subsec_micros())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fields(index: usize) -> FrameFields<'static> {
+        FrameFields {
+            index,
+            start_time: Duration::new(1_697_423_112, 123_456_789),
+            end_time: Duration::new(1_697_423_112, 223_456_789),
+            mode: Some("strongest"),
+            sensor: Some("192.168.1.201"),
+        }
+    }
+
+    fn render(template: &str, index: usize) -> String {
+        let template: OutputTemplate = template.parse().unwrap();
+        let path = template.render(&fields(index), "pcd");
+        path.to_str().unwrap().to_string()
+    }
+
+    #[test]
+    fn escaped_braces() {
+        assert_eq!(render("{{{index}}}", 3), "{3}.pcd");
+        assert_eq!(render("frame}}{{_{number}", 3), "frame}{_4.pcd");
+    }
+
+    #[test]
+    fn widths() {
+        assert_eq!(render("{index:06}", 42), "000042.pcd");
+        assert_eq!(render("{number:03}", 4), "005.pcd");
+        assert_eq!(render("{index:2}", 123), "123.pcd");
+        assert!("{start_time:06}".parse::<OutputTemplate>().is_err());
+        assert!("{index:x}".parse::<OutputTemplate>().is_err());
+    }
+
+    #[test]
+    fn times() {
+        assert_eq!(
+            render("{start_time}_{end_time}", 0),
+            "1697423112.123456_1697423112.223456.pcd"
+        );
+    }
+
+    #[test]
+    fn sensor_and_mode() {
+        assert_eq!(
+            render("{sensor}/{mode}/{index}", 7),
+            "192.168.1.201/strongest/7.pcd"
+        );
+        assert_eq!(
+            OutputTemplate::default_for(true).render(&fields(7), "pcd"),
+            PathBuf::from("strongest/000007.pcd")
+        );
+    }
+
+    #[test]
+    fn rejected_templates() {
+        for text in [
+            "../{index}",
+            "/tmp/{index}",
+            "{sensor}",
+            "{sensor}/{mode}",
+            "{index",
+            "index}",
+            "{frame}",
+        ] {
+            assert!(text.parse::<OutputTemplate>().is_err(), "{text}");
+        }
+    }
+
+    #[test]
+    fn mode_placeholder() {
+        let template: OutputTemplate = "{mode}/{index}".parse().unwrap();
+        assert!(template.check(Some(2)).is_ok());
+        assert!(template.check(None).is_err());
+
+        let template: OutputTemplate = "{index}".parse().unwrap();
+        assert!(template.check(Some(1)).is_ok());
+        assert!(template.check(Some(2)).is_err());
+    }
+}
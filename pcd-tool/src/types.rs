@@ -1,17 +1,56 @@
 use clap::ValueEnum;
+use pcd_format::PcdPoint;
+use std::{f64::consts::FRAC_PI_2, fmt};
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, ValueEnum)]
 pub enum FileFormat {
     #[value(name = "pcd.libpcl")]
     LibpclPcd,
+    #[value(name = "pcd.libpcl-ext")]
+    LibpclExtPcd,
+    #[value(name = "pcd.libpcl-xyz")]
+    LibpclXyzPcd,
+    #[value(name = "pcd.libpcl-xyzi")]
+    LibpclXyziPcd,
+    #[value(name = "pcd.libpcl-xyzrgba")]
+    LibpclXyzrgbaPcd,
+    #[value(name = "pcd.libpcl-xyzinormal")]
+    LibpclXyziNormalPcd,
+    #[value(name = "pcd.libpcl-normal")]
+    LibpclNormalPcd,
+    #[value(name = "pcd.libpcl-xyzl")]
+    LibpclXyzlPcd,
+    #[value(name = "pcd.xyzirt")]
+    XyzirtPcd,
     #[value(name = "pcd.newslab")]
     NewslabPcd,
+    #[value(name = "pcd.newslab-v2")]
+    NewslabV2Pcd,
+    #[value(name = "pcd.ouster")]
+    OusterPcd,
+    #[value(name = "pcd.livox")]
+    LivoxPcd,
     #[value(name = "pcap.velodyne")]
     VelodynePcap,
+    #[value(name = "pcap.ouster")]
+    OusterPcap,
+    #[value(name = "pcap.hesai")]
+    HesaiPcap,
+    #[value(name = "lvx.livox")]
+    LivoxLvx,
     #[value(name = "raw.bin")]
     RawBin,
 }
 
+impl fmt::Display for FileFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let value = self
+            .to_possible_value()
+            .expect("every file format has a name");
+        f.write_str(value.get_name())
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub struct BinPoint {
     pub x: f32,
@@ -19,3 +58,45 @@ pub struct BinPoint {
     pub z: f32,
     pub intensity: f32,
 }
+
+impl PcdPoint for BinPoint {
+    fn x(&self) -> f64 {
+        self.x as f64
+    }
+
+    fn y(&self) -> f64 {
+        self.y as f64
+    }
+
+    fn z(&self) -> f64 {
+        self.z as f64
+    }
+
+    fn distance(&self) -> f64 {
+        (self.x().powi(2) + self.y().powi(2) + self.z().powi(2)).sqrt()
+    }
+
+    fn azimuthal_angle(&self) -> f64 {
+        self.y().atan2(self.x())
+    }
+
+    fn vertical_angle(&self) -> f64 {
+        -self.polar_angle() + FRAC_PI_2
+    }
+
+    fn polar_angle(&self) -> f64 {
+        (self.x().powi(2) + self.y().powi(2)).sqrt().atan2(self.z())
+    }
+
+    fn intensity(&self) -> Option<f64> {
+        Some(self.intensity as f64)
+    }
+
+    fn laser_id(&self) -> Option<u32> {
+        None
+    }
+
+    fn timestamp_ns(&self) -> Option<u32> {
+        None
+    }
+}
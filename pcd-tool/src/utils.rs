@@ -1,6 +1,29 @@
-use crate::types::FileFormat;
-use eyre::{bail, Result};
-use std::path::Path;
+use crate::{
+    calibration::VelodyneCalibration,
+    capture::{is_capture_file, CaptureReader, PacketFilter},
+    hesai::{detect_hesai_model_and_mode, HesaiAngles, HesaiModel},
+    livox::is_livox_file,
+    opts::VelodyneReturnMode,
+    ouster::OusterMetadata,
+    packet::{velodyne_product_id_from_byte, velodyne_return_mode_from_byte, VelodynePacket},
+    sources::warn_mixed_sources,
+    spinning::SpinningLidar,
+    types::FileFormat,
+};
+use chrono::{DateTime, Utc};
+use eyre::{bail, format_err, Result};
+use pcd_format::{
+    schema_matches, LibpclExtPoint, LibpclNormalPoint, LibpclPoint, LibpclXyzPoint,
+    LibpclXyziNormalPoint, LibpclXyziPoint, LibpclXyzlPoint, LibpclXyzrgbaPoint, LivoxPoint,
+    NewslabV1Point, NewslabV2Point, OusterPoint, XyzirtPoint,
+};
+use std::{
+    collections::{HashMap, HashSet},
+    fs::File,
+    io::{self, prelude::*},
+    path::Path,
+    time::Duration,
+};
 use velodyne_lidar::{ProductID, ReturnMode};
 
 // use crate::types::LidarType;
@@ -20,10 +43,34 @@ where
 
     let format = if file_name.ends_with(".newslab.pcd") {
         FileFormat::NewslabPcd
+    } else if file_name.ends_with(".newslab-v2.pcd") {
+        FileFormat::NewslabV2Pcd
+    } else if file_name.ends_with(".libpcl-ext.pcd") {
+        FileFormat::LibpclExtPcd
+    } else if file_name.ends_with(".libpcl-xyz.pcd") {
+        FileFormat::LibpclXyzPcd
+    } else if file_name.ends_with(".libpcl-xyzi.pcd") {
+        FileFormat::LibpclXyziPcd
+    } else if file_name.ends_with(".libpcl-xyzrgba.pcd") {
+        FileFormat::LibpclXyzrgbaPcd
+    } else if file_name.ends_with(".libpcl-xyzinormal.pcd") {
+        FileFormat::LibpclXyziNormalPcd
+    } else if file_name.ends_with(".libpcl-normal.pcd") {
+        FileFormat::LibpclNormalPcd
+    } else if file_name.ends_with(".libpcl-xyzl.pcd") {
+        FileFormat::LibpclXyzlPcd
+    } else if file_name.ends_with(".xyzirt.pcd") {
+        FileFormat::XyzirtPcd
+    } else if file_name.ends_with(".ouster.pcd") {
+        FileFormat::OusterPcd
+    } else if file_name.ends_with(".livox.pcd") {
+        FileFormat::LivoxPcd
     } else if file_name.ends_with(".pcd") {
         FileFormat::LibpclPcd
-    } else if file_name.ends_with(".pcap") {
+    } else if file_name.ends_with(".pcap") || file_name.ends_with(".pcapng") {
         FileFormat::VelodynePcap
+    } else if file_name.ends_with(".lvx") || file_name.ends_with(".lvx2") {
+        FileFormat::LivoxLvx
     } else {
         return None;
     };
@@ -31,6 +78,205 @@ where
     Some(format)
 }
 
+/// The size of a point in raw.bin files, which is four `f32` values.
+const RAW_BIN_POINT_SIZE: u64 = 16;
+
+/// The number of leading points checked when sniffing a raw.bin file.
+const RAW_BIN_SNIFF_POINTS: usize = 1024;
+
+/// Detects the format of an existing input file by its content.
+///
+/// PCD files are matched against the schemas in pcd-format by the
+/// fields in the header. A PCD file not matching any schema but
+/// having x, y and z fields is treated as `pcd.libpcl`. pcap and
+/// pcapng files are recognized by the magic number, and `.bin` files must pass a
+/// sanity check on the size and values. Directories and other files
+/// fall back to [guess_file_format].
+pub fn detect_file_format<P>(file: P) -> Result<Option<FileFormat>>
+where
+    P: AsRef<Path>,
+{
+    let file = file.as_ref();
+
+    if !file.is_file() {
+        return Ok(guess_file_format(file));
+    }
+
+    let mut head = [0u8; 16];
+    let head_len = read_head(file, &mut head)?;
+    let head = &head[..head_len];
+
+    if is_capture_file(head) {
+        return Ok(Some(detect_pcap_vendor(file)?));
+    }
+
+    if is_livox_file(head) {
+        return Ok(Some(FileFormat::LivoxLvx));
+    }
+
+    if head.starts_with(b"#") || head.starts_with(b"VERSION") || head.starts_with(b"FIELDS") {
+        let reader = pcd_rs::DynReader::open(file)?;
+        let field_defs = &reader.meta().field_defs;
+
+        if let Some(format) = detect_pcd_schema(field_defs) {
+            return Ok(Some(format));
+        }
+
+        let has_field = |name: &str| field_defs.iter().any(|field| field.name == name);
+        if has_field("x") && has_field("y") && has_field("z") {
+            return Ok(Some(FileFormat::LibpclPcd));
+        }
+
+        bail!(
+            "the fields of '{}' do not match any known schema",
+            file.display()
+        );
+    }
+
+    if file.extension().map(|ext| ext == "bin").unwrap_or(false) {
+        check_raw_bin_file(file)?;
+        return Ok(Some(FileFormat::RawBin));
+    }
+
+    Ok(guess_file_format(file))
+}
+
+/// Finds the PCD format whose schema matches exactly the fields in
+/// a PCD header.
+pub fn detect_pcd_schema(field_defs: &pcd_rs::Schema) -> Option<FileFormat> {
+    use FileFormat as F;
+
+    let candidates: [(FileFormat, fn(&pcd_rs::Schema) -> bool); 13] = [
+        (F::LibpclPcd, schema_matches::<LibpclPoint>),
+        (F::LibpclExtPcd, schema_matches::<LibpclExtPoint>),
+        (F::LibpclXyzPcd, schema_matches::<LibpclXyzPoint>),
+        (F::LibpclXyziPcd, schema_matches::<LibpclXyziPoint>),
+        (F::LibpclXyzrgbaPcd, schema_matches::<LibpclXyzrgbaPoint>),
+        (
+            F::LibpclXyziNormalPcd,
+            schema_matches::<LibpclXyziNormalPoint>,
+        ),
+        (F::LibpclNormalPcd, schema_matches::<LibpclNormalPoint>),
+        (F::LibpclXyzlPcd, schema_matches::<LibpclXyzlPoint>),
+        (F::XyzirtPcd, schema_matches::<XyzirtPoint>),
+        (F::OusterPcd, schema_matches::<OusterPoint>),
+        (F::LivoxPcd, schema_matches::<LivoxPoint>),
+        (F::NewslabPcd, schema_matches::<NewslabV1Point>),
+        (F::NewslabV2Pcd, schema_matches::<NewslabV2Point>),
+    ];
+
+    candidates
+        .into_iter()
+        .find(|(_, matches)| matches(field_defs))
+        .map(|(format, _)| format)
+}
+
+/// The number of UDP packets inspected to tell the vendor of a pcap
+/// file.
+const PCAP_SNIFF_PACKETS: usize = 64;
+
+/// Tells the vendor of the LiDAR captured in a pcap file. Velodyne
+/// and Hesai captures are told by the packet layouts. Other captures
+/// are taken to be from Ouster if a metadata file is found next to
+/// the file, and are assumed to be from Velodyne otherwise.
+fn detect_pcap_vendor(file: &Path) -> Result<FileFormat> {
+    let capture = CaptureReader::open(file, &PacketFilter::default())?;
+
+    for packet in capture.take(PCAP_SNIFF_PACKETS) {
+        let packet = packet?;
+        let Some(packet) = packet.udp() else {
+            continue;
+        };
+        if VelodynePacket::classify(packet.payload).is_some() {
+            return Ok(FileFormat::VelodynePcap);
+        }
+        if HesaiModel::from_packet(packet.payload).is_some() {
+            return Ok(FileFormat::HesaiPcap);
+        }
+    }
+
+    if OusterMetadata::find_for_pcap(file).is_some() {
+        return Ok(FileFormat::OusterPcap);
+    }
+
+    Ok(FileFormat::VelodynePcap)
+}
+
+/// Finds and loads the metadata of an Ouster pcap file. The file given
+/// on the command line takes precedence over the one next to the pcap
+/// file.
+pub fn resolve_ouster_metadata<P>(
+    pcap_file: P,
+    metadata_file: Option<&Path>,
+) -> Result<OusterMetadata>
+where
+    P: AsRef<Path>,
+{
+    let pcap_file = pcap_file.as_ref();
+    let metadata_file = match metadata_file {
+        Some(file) => file.to_path_buf(),
+        None => OusterMetadata::find_for_pcap(pcap_file).ok_or_else(|| {
+            format_err!(
+                "unable to find the metadata of '{}'. Please set --ouster-metadata",
+                pcap_file.display()
+            )
+        })?,
+    };
+    OusterMetadata::load(metadata_file)
+}
+
+/// Checks that a file looks like a raw.bin file. The size must be a
+/// non-zero multiple of the point size and the leading points must
+/// have finite values.
+fn check_raw_bin_file(file: &Path) -> Result<()> {
+    let len = file.metadata()?.len();
+
+    if len == 0 || len % RAW_BIN_POINT_SIZE != 0 {
+        bail!(
+            "'{}' is not a raw.bin file: the size {} is not a multiple of {} bytes",
+            file.display(),
+            len,
+            RAW_BIN_POINT_SIZE
+        );
+    }
+
+    let mut reader = io::BufReader::new(File::open(file)?);
+    let mut buf = [0u8; RAW_BIN_POINT_SIZE as usize];
+    let num_points = (len / RAW_BIN_POINT_SIZE).min(RAW_BIN_SNIFF_POINTS as u64);
+
+    for _ in 0..num_points {
+        reader.read_exact(&mut buf)?;
+        let all_finite = buf
+            .chunks_exact(4)
+            .all(|bytes| f32::from_le_bytes(bytes.try_into().unwrap()).is_finite());
+
+        if !all_finite {
+            bail!(
+                "'{}' is not a raw.bin file: non-finite values found",
+                file.display()
+            );
+        }
+    }
+
+    Ok(())
+}
+
+/// Reads the leading bytes of a file and returns the number of bytes
+/// read, which is less than the buffer size for short files.
+fn read_head(file: &Path, buf: &mut [u8]) -> io::Result<usize> {
+    let mut reader = File::open(file)?;
+    let mut len = 0;
+
+    while len < buf.len() {
+        match reader.read(&mut buf[len..])? {
+            0 => break,
+            cnt => len += cnt,
+        }
+    }
+
+    Ok(len)
+}
+
 // pub fn time(file: impl AsRef<Path>) -> Result<()> {
 //     let term = Term::stdout();
 //     let lidar_type = {
@@ -90,31 +336,314 @@ where
 //     Ok(())
 // }
 
-pub fn build_velodyne_config(model: ProductID, mode: ReturnMode) -> Result<velodyne_lidar::Config> {
-    use velodyne_lidar::Config;
+/// The number of data packets inspected to detect the model and
+/// return mode of a Velodyne pcap file.
+const VELODYNE_SNIFF_PACKETS: usize = 16;
 
-    use ProductID as P;
-    use ReturnMode as R;
+/// Detects the model and return mode of a Velodyne pcap file from the
+/// factory bytes of the first data packets. The most frequent values
+/// are chosen. Each gives `None` if it cannot be recognized.
+pub fn detect_velodyne_model_and_mode<P>(
+    path: P,
+    filter: &PacketFilter,
+) -> Result<(Option<ProductID>, Option<ReturnMode>)>
+where
+    P: AsRef<Path>,
+{
+    let mut capture = CaptureReader::open(path, filter)?;
+    let mut counts: HashMap<(u8, u8), usize> = HashMap::new();
+    let mut sources = HashSet::new();
+    let mut num_data_packets = 0;
 
-    let config = match (model, mode) {
-        (P::VLP16, R::Last) => Config::new_vlp_16_last(),
-        (P::VLP16, R::Strongest) => Config::new_vlp_16_strongest(),
-        (P::VLP16, R::Dual) => Config::new_vlp_16_dual(),
+    while num_data_packets < VELODYNE_SNIFF_PACKETS {
+        let Some(packet) = capture.next_packet()? else {
+            break;
+        };
+        let Some(packet) = packet.udp() else {
+            continue;
+        };
+        let Some(VelodynePacket::Data {
+            return_mode,
+            product_id,
+        }) = VelodynePacket::classify(packet.payload)
+        else {
+            continue;
+        };
 
-        (P::PuckHiRes, R::Last) => Config::new_puck_hires_last(),
-        (P::PuckHiRes, R::Strongest) => Config::new_puck_hires_strongest(),
-        (P::PuckHiRes, R::Dual) => Config::new_puck_hires_dual(),
+        *counts.entry((return_mode, product_id)).or_default() += 1;
+        sources.insert((packet.source, packet.destination.port()));
+        num_data_packets += 1;
+    }
+    warn_mixed_sources(sources.len());
 
-        (P::PuckLite, R::Last) => Config::new_puck_lite_last(),
-        (P::PuckLite, R::Strongest) => Config::new_puck_lite_strongest(),
-        (P::PuckLite, R::Dual) => Config::new_puck_lite_dual(),
+    let Some((return_mode, product_id)) = counts
+        .into_iter()
+        .max_by_key(|&(_, count)| count)
+        .map(|(bytes, _)| bytes)
+    else {
+        return Ok((None, None));
+    };
 
-        (P::VLP32C, R::Last) => Config::new_vlp_32c_last(),
-        (P::VLP32C, R::Strongest) => Config::new_vlp_32c_strongest(),
-        (P::VLP32C, R::Dual) => Config::new_vlp_32c_dual(),
+    Ok((
+        velodyne_product_id_from_byte(product_id),
+        velodyne_return_mode_from_byte(return_mode),
+    ))
+}
 
+/// Determines the model and return mode of a Velodyne pcap file. The
+/// values given on the command line take precedence over the detected
+/// ones, and a warning is printed if they disagree.
+pub fn resolve_velodyne_model_and_mode<P>(
+    path: P,
+    filter: &PacketFilter,
+    model: Option<ProductID>,
+    mode: Option<VelodyneReturnMode>,
+) -> Result<(ProductID, VelodyneReturnMode)>
+where
+    P: AsRef<Path>,
+{
+    // Detect even if both are given to check the flags.
+    let (detected_model, detected_mode) = detect_velodyne_model_and_mode(path, filter)?;
+
+    let model = match (model, detected_model) {
+        (Some(model), Some(detected)) => {
+            if model != detected {
+                eprintln!(
+                    "Warning: --velodyne-model is set to {model}, but the packets are from {detected}"
+                );
+            }
+            model
+        }
+        (Some(model), None) => model,
+        (None, Some(detected)) => detected,
+        (None, None) => bail!(
+            "unable to detect the Velodyne model from the packets. Please set --velodyne-model"
+        ),
+    };
+
+    let mode = match (mode, detected_mode) {
+        (Some(mode), Some(detected)) => {
+            if mode.0 != detected {
+                eprintln!(
+                    "Warning: --velodyne-return-mode is set to {:?}, but the packets are in {:?} mode",
+                    mode.0, detected
+                );
+            }
+            mode
+        }
+        (Some(mode), None) => mode,
+        (None, Some(detected)) => VelodyneReturnMode(detected),
+        (None, None) => bail!(
+            "unable to detect the Velodyne return mode from the packets. Please set --velodyne-return-mode"
+        ),
+    };
+
+    Ok((model, mode))
+}
+
+/// Determines the model and return mode of a Velodyne pcap file and
+/// loads the calibration file if it is given. It fails before any
+/// frame is decoded if the model has no built-in laser angles and no
+/// calibration is given.
+pub fn resolve_velodyne_lidar<P>(
+    path: P,
+    filter: &PacketFilter,
+    model: Option<ProductID>,
+    mode: Option<VelodyneReturnMode>,
+    calibration_file: Option<&Path>,
+) -> Result<(SpinningLidar, VelodyneReturnMode)>
+where
+    P: AsRef<Path>,
+{
+    let (model, mode) = resolve_velodyne_model_and_mode(path, filter, model, mode)?;
+    let calibration = calibration_file
+        .map(VelodyneCalibration::load)
+        .transpose()?;
+    if calibration.is_none() {
+        velodyne_laser_elevations(model)?;
+    }
+
+    Ok((SpinningLidar::Velodyne { model, calibration }, mode))
+}
+
+/// Determines the model and return mode of a Hesai pcap file. The
+/// model given on the command line takes precedence over the detected
+/// one, while the return mode is always read from the packets. The
+/// angles are loaded from the angle file if it is given, or are the
+/// nominal angles of the model otherwise.
+pub fn resolve_hesai_lidar<P>(
+    path: P,
+    filter: &PacketFilter,
+    model: Option<HesaiModel>,
+    angles_file: Option<&Path>,
+) -> Result<(SpinningLidar, VelodyneReturnMode)>
+where
+    P: AsRef<Path>,
+{
+    let (detected_model, detected_mode) = detect_hesai_model_and_mode(path, filter)?;
+
+    let model = match (model, detected_model) {
+        (Some(model), Some(detected)) => {
+            if model != detected {
+                eprintln!(
+                    "Warning: --hesai-model is set to {model}, but the packets are from {detected}"
+                );
+            }
+            model
+        }
+        (Some(model), None) => model,
+        (None, Some(detected)) => detected,
+        (None, None) => {
+            bail!("unable to detect the Hesai model from the packets. Please set --hesai-model")
+        }
+    };
+    let Some(mode) = detected_mode else {
+        bail!("the return mode of the Hesai packets is not supported");
+    };
+    let angles = match angles_file {
+        Some(file) => HesaiAngles::load(file)?,
+        None => HesaiAngles::builtin(model)?,
+    };
+
+    Ok((
+        SpinningLidar::Hesai { model, angles },
+        VelodyneReturnMode(mode),
+    ))
+}
+
+/// Gives the nominal elevation angles in degrees of the lasers,
+/// indexed by the laser ID.
+pub fn velodyne_laser_elevations(model: ProductID) -> Result<&'static [f64]> {
+    use ProductID as P;
+
+    const VLP_16: [f64; 16] = [
+        -15.0, 1.0, -13.0, 3.0, -11.0, 5.0, -9.0, 7.0, -7.0, 9.0, -5.0, 11.0, -3.0, 13.0, -1.0,
+        15.0,
+    ];
+    const PUCK_HIRES: [f64; 16] = [
+        -10.0, 0.67, -8.67, 2.0, -7.33, 3.33, -6.0, 4.67, -4.67, 6.0, -3.33, 7.33, -2.0, 8.67,
+        -0.67, 10.0,
+    ];
+    const VLP_32C: [f64; 32] = [
+        -25.0, -1.0, -1.667, -15.639, -11.31, 0.0, -0.667, -8.843, -7.254, 0.333, -0.333, -6.148,
+        -5.333, 1.333, 0.667, -4.0, -4.667, 1.667, 1.0, -3.667, -3.333, 3.333, 2.333, -2.667, -3.0,
+        7.0, 4.667, -2.333, -2.0, 15.0, 10.333, -1.333,
+    ];
+
+    const HDL_32E: [f64; 32] = [
+        -30.67, -9.33, -29.33, -8.0, -28.0, -6.67, -26.67, -5.33, -25.33, -4.0, -24.0, -2.67,
+        -22.67, -1.33, -21.33, 0.0, -20.0, 1.33, -18.67, 2.67, -17.33, 4.0, -16.0, 5.33, -14.67,
+        6.67, -13.33, 8.0, -12.0, 9.33, -10.67, 10.67,
+    ];
+
+    let elevations: &[f64] = match model {
+        P::VLP16 | P::PuckLite => &VLP_16,
+        P::PuckHiRes => &PUCK_HIRES,
+        P::VLP32C => &VLP_32C,
+        P::HDL32E => &HDL_32E,
+        // The laser table of VLS-128 is not built in and has to be
+        // read from the calibration file of the sensor.
+        P::VLS128 => bail!(
+            "The laser angles of the model '{}' are not built in. Please set --velodyne-calibration",
+            model
+        ),
         _ => bail!("The model '{}' is not supported", model),
     };
 
-    Ok(config)
+    Ok(elevations)
+}
+
+/// Gives the nominal azimuth offsets in degrees of the lasers, indexed
+/// by the laser ID, for the models whose lasers are not aligned in
+/// azimuth.
+pub fn velodyne_laser_azimuth_offsets(model: ProductID) -> Option<&'static [f64]> {
+    const VLP_32C: [f64; 32] = [
+        1.4, -4.2, 1.4, -1.4, 1.4, -1.4, 4.2, -1.4, 1.4, -4.2, 1.4, -1.4, 4.2, -1.4, 4.2, -1.4,
+        1.4, -4.2, 1.4, -4.2, 4.2, -1.4, 1.4, -1.4, 1.4, -1.4, 1.4, -4.2, 4.2, -1.4, 1.4, -1.4,
+    ];
+
+    match model {
+        ProductID::VLP32C => Some(&VLP_32C),
+        _ => None,
+    }
+}
+
+/// Recovers absolute timestamps from the top-of-hour timestamps of a
+/// Velodyne LiDAR.
+///
+/// The hour is initialized from the GPS time in a position packet, or
+/// else from the capture time of the first packet, assuming the sensor
+/// clock is synchronized with the capturing host. The hour is moved
+/// whenever the top-of-hour timestamp wraps around.
+#[derive(Debug, Clone)]
+pub struct TohClock {
+    hour_start: Duration,
+    last_toh: Duration,
+}
+
+impl TohClock {
+    const HOUR: Duration = Duration::from_secs(3600);
+    const HALF_HOUR: Duration = Duration::from_secs(1800);
+
+    pub fn new(first_packet_time: Duration) -> Self {
+        let secs = first_packet_time.as_secs();
+        let hour_start = Duration::from_secs(secs - secs % 3600);
+
+        Self {
+            hour_start,
+            last_toh: first_packet_time - hour_start,
+        }
+    }
+
+    /// Creates a clock from a time close to a top-of-hour timestamp,
+    /// such as the UTC time reported by the GPS receiver in the same
+    /// packet.
+    pub fn from_reference(time: Duration, toh: Duration) -> Self {
+        // The hour is the one which brings the top-of-hour timestamp
+        // the closest to the reference time.
+        let secs = (time + Self::HALF_HOUR).saturating_sub(toh).as_secs();
+        let hour_start = Duration::from_secs(secs - secs % 3600);
+
+        Self {
+            hour_start,
+            last_toh: toh,
+        }
+    }
+
+    /// Moves the clock to the top-of-hour timestamp.
+    pub fn advance(&mut self, toh: Duration) {
+        self.hour_start = self.hour_start_of(toh);
+        self.last_toh = toh;
+    }
+
+    /// Gives the time since the Unix epoch of the last top-of-hour
+    /// timestamp the clock moved to.
+    pub fn current(&self) -> Duration {
+        self.hour_start + self.last_toh
+    }
+
+    /// Gives the time since the Unix epoch for a top-of-hour
+    /// timestamp not far from the current one.
+    pub fn to_absolute(&self, toh: Duration) -> Duration {
+        self.hour_start_of(toh) + toh
+    }
+
+    /// Gives the start of the hour of a top-of-hour timestamp, which
+    /// is in the next hour if the timestamp wrapped around, or in the
+    /// previous hour if the clock was set ahead of it.
+    fn hour_start_of(&self, toh: Duration) -> Duration {
+        if toh + Self::HALF_HOUR < self.last_toh {
+            self.hour_start + Self::HOUR
+        } else if self.last_toh + Self::HALF_HOUR < toh {
+            self.hour_start.saturating_sub(Self::HOUR)
+        } else {
+            self.hour_start
+        }
+    }
+}
+
+/// Formats a time since the Unix epoch in RFC 3339.
+pub fn format_time(time: Duration) -> Option<String> {
+    let time = DateTime::<Utc>::from_timestamp(time.as_secs() as i64, time.subsec_nanos())?;
+    Some(time.to_rfc3339())
 }
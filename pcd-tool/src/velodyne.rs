@@ -0,0 +1,535 @@
+//! Decoding of Velodyne pcap files into frames.
+//!
+//! The data packets of all supported models share the 12 blocks of 32
+//! channels. The models differ in how firings are laid out over the
+//! blocks, which is described by [FiringLayout].
+
+use crate::{
+    calibration::VelodyneCalibration,
+    capture::{CaptureReader, PacketFilter},
+    index::FrameIndexEntry,
+    packet::VelodynePacket,
+    split::{FrameSplit, FrameSplitter},
+    utils::{velodyne_laser_azimuth_offsets, velodyne_laser_elevations},
+};
+use eyre::{bail, Result};
+use measurements::Angle;
+use serde::{Deserialize, Serialize};
+use std::{collections::VecDeque, path::Path, time::Duration};
+use velodyne_lidar::{ProductID, ReturnMode};
+
+/// A return measured by a laser.
+#[derive(Debug, Clone, Copy)]
+pub struct VelodyneMeasurement {
+    /// The distance in meters.
+    pub distance: f64,
+    pub intensity: u8,
+    /// The Cartesian coordinates in meters.
+    pub xyz: [f64; 3],
+}
+
+/// A laser firing with the returns enabled by the return mode.
+#[derive(Debug, Clone, Copy)]
+pub struct VelodynePoint {
+    pub laser_id: u32,
+    /// The firing time since the top of the hour.
+    pub toh: Duration,
+    /// The azimuth in the Velodyne convention, which is clockwise
+    /// from the y axis.
+    pub azimuth: Angle,
+    /// The elevation angle of the laser.
+    pub elevation: Angle,
+    /// The strongest return. It is set in the strongest and dual
+    /// return modes.
+    pub strongest: Option<VelodyneMeasurement>,
+    /// The last return. It is set in the last and dual return modes.
+    pub last: Option<VelodyneMeasurement>,
+}
+
+/// A full revolution of the sensor, or the firings between the cuts
+/// chosen by [FrameSplit].
+///
+/// The points are ordered by firing and then by laser ID, so that the
+/// frame forms an organized point cloud of `width` firings by `height`
+/// lasers.
+#[derive(Debug, Clone)]
+pub struct VelodyneFrame {
+    pub width: usize,
+    pub height: usize,
+    pub points: Vec<VelodynePoint>,
+    /// Where the frame is located in the capture.
+    pub span: FrameSpan,
+}
+
+impl VelodyneFrame {
+    /// Gives the top-of-hour timestamp of the first firing.
+    pub fn first_toh(&self) -> Option<Duration> {
+        self.points.first().map(|point| point.toh)
+    }
+
+    /// Gives the top-of-hour timestamp of the last firing.
+    pub fn last_toh(&self) -> Option<Duration> {
+        self.points.last().map(|point| point.toh)
+    }
+}
+
+/// The packets of a frame in the capture file.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct FrameSpan {
+    /// The byte offset of the packet to resume reading from to get the
+    /// frame. It is the data packet before the first firing if the
+    /// frame is cut by the azimuth or the time, since the splitter
+    /// needs the firings before the cut.
+    pub start_offset: u64,
+    /// The byte offset past the packet holding the last firing.
+    pub end_offset: u64,
+    /// The number of capture interfaces declared before the start
+    /// offset.
+    pub num_interfaces: usize,
+}
+
+/// Reads the frames in a Velodyne pcap file.
+///
+/// The points are computed with the nominal laser angles of the model,
+/// or are corrected with the calibration if it is given.
+pub fn velodyne_frame_iter_from_file<P>(
+    path: P,
+    filter: &PacketFilter,
+    model: ProductID,
+    mode: ReturnMode,
+    split: &FrameSplit,
+    calibration: Option<&VelodyneCalibration>,
+    start: Option<&FrameIndexEntry>,
+) -> Result<impl Iterator<Item = Result<VelodyneFrame>>>
+where
+    P: AsRef<Path>,
+{
+    let layout = FiringLayout::of(model)?;
+    let calibration = calibration.cloned();
+
+    // The calibration carries its own azimuth corrections, which
+    // replace the nominal offsets.
+    let (elevations, azimuth_offsets) = match &calibration {
+        Some(calibration) => {
+            calibration.check_num_lasers(layout.num_lasers)?;
+            (calibration.elevations(), vec![0.0; layout.num_lasers])
+        }
+        None => {
+            let offsets = match velodyne_laser_azimuth_offsets(model) {
+                Some(offsets) => offsets.to_vec(),
+                None => vec![0.0; layout.num_lasers],
+            };
+            (velodyne_laser_elevations(model)?.to_vec(), offsets)
+        }
+    };
+
+    let decode = move |payload: &[u8]| {
+        let Some(VelodynePacket::Data { .. }) = VelodynePacket::classify(payload) else {
+            return None;
+        };
+        Some(layout.decode_packet(&elevations, &azimuth_offsets, mode, payload))
+    };
+    let mut frames = FiringFrameIter::new(path, filter, layout.num_lasers, split, decode)?;
+    if let Some(entry) = start {
+        frames.seek(entry)?;
+    }
+
+    let frames = frames.map(move |frame| -> Result<_> {
+        let mut frame = frame?;
+        if let Some(calibration) = &calibration {
+            frame
+                .points
+                .iter_mut()
+                .for_each(|point| calibration.correct_point(point));
+        }
+        Ok(frame)
+    });
+
+    Ok(frames)
+}
+
+/// The number of 100-byte data blocks in a data packet.
+const BLOCKS_PER_PACKET: usize = 12;
+/// The size of a data block.
+const BLOCK_SIZE: usize = 100;
+/// The number of channels in a data block.
+const CHANNELS_PER_BLOCK: usize = 32;
+/// The offset of the timestamp in a data packet.
+const TIMESTAMP_OFFSET: usize = BLOCKS_PER_PACKET * BLOCK_SIZE;
+
+/// How the firings of a model are laid out over the data blocks.
+#[derive(Debug, Clone, Copy)]
+struct FiringLayout {
+    /// The number of lasers, which is the frame height.
+    num_lasers: usize,
+    /// The number of consecutive data blocks holding one firing of
+    /// all lasers.
+    blocks_per_firing: usize,
+    /// The number of firings in a data block. The 16 laser models
+    /// fire twice per block and report the azimuth of the first
+    /// firing only.
+    firings_per_block: usize,
+    /// The distance unit in meters.
+    distance_resolution: f64,
+    /// The duration between firings.
+    firing_period: Duration,
+    /// The delay between consecutive shots within a firing. It is
+    /// `None` if the lasers are considered to fire at once.
+    laser_period: Option<Duration>,
+    /// The number of lasers fired together in a shot.
+    lasers_per_shot: usize,
+    /// The number of shots after which the lasers recharge for one
+    /// laser period, or `None` if the shots are evenly spaced.
+    shots_per_recharge: Option<usize>,
+}
+
+impl FiringLayout {
+    fn of(model: ProductID) -> Result<Self> {
+        use ProductID as P;
+
+        let layout = match model {
+            P::VLP16 | P::PuckLite | P::PuckHiRes => Self {
+                num_lasers: 16,
+                blocks_per_firing: 1,
+                firings_per_block: 2,
+                distance_resolution: 0.002,
+                firing_period: Duration::from_nanos(55_296),
+                laser_period: Some(Duration::from_nanos(2_304)),
+                lasers_per_shot: 1,
+                shots_per_recharge: None,
+            },
+            P::VLP32C => Self {
+                num_lasers: 32,
+                blocks_per_firing: 1,
+                firings_per_block: 1,
+                distance_resolution: 0.004,
+                firing_period: Duration::from_nanos(55_296),
+                laser_period: Some(Duration::from_nanos(2_304)),
+                lasers_per_shot: 2,
+                shots_per_recharge: None,
+            },
+            P::HDL32E => Self {
+                num_lasers: 32,
+                blocks_per_firing: 1,
+                firings_per_block: 1,
+                distance_resolution: 0.002,
+                firing_period: Duration::from_nanos(46_080),
+                laser_period: Some(Duration::from_nanos(1_152)),
+                lasers_per_shot: 1,
+                shots_per_recharge: None,
+            },
+            // A firing sequence fires 16 groups of 8 lasers, with a
+            // recharge slot after the 8th group and at the end.
+            P::VLS128 => Self {
+                num_lasers: 128,
+                blocks_per_firing: 4,
+                firings_per_block: 1,
+                distance_resolution: 0.004,
+                firing_period: Duration::from_nanos(53_300),
+                laser_period: Some(Duration::from_nanos(2_665)),
+                lasers_per_shot: 8,
+                shots_per_recharge: Some(8),
+            },
+            _ => bail!("The model '{}' is not supported", model),
+        };
+        Ok(layout)
+    }
+
+    /// Decodes the firings in a data packet.
+    ///
+    /// In the single return modes, consecutive groups of
+    /// `blocks_per_firing` blocks hold `firings_per_block` firings
+    /// each. In the dual return mode, the firings span two groups
+    /// sharing the same azimuth, which hold the last and the strongest
+    /// returns in order.
+    fn decode_packet(
+        &self,
+        elevations: &[f64],
+        azimuth_offsets: &[f64],
+        mode: ReturnMode,
+        payload: &[u8],
+    ) -> Vec<(Angle, Vec<VelodynePoint>)> {
+        let read_u16 = |offset: usize| u16::from_le_bytes([payload[offset], payload[offset + 1]]);
+        let timestamp = u32::from_le_bytes(
+            payload[TIMESTAMP_OFFSET..TIMESTAMP_OFFSET + 4]
+                .try_into()
+                .unwrap(),
+        );
+        let packet_toh = Duration::from_micros(timestamp as u64);
+
+        let groups_per_set = match mode {
+            ReturnMode::Dual => 2,
+            _ => 1,
+        };
+        let blocks_per_group = self.blocks_per_firing;
+        let num_sets = BLOCKS_PER_PACKET / (blocks_per_group * groups_per_set);
+        let num_firings = num_sets * self.firings_per_block;
+
+        let block_azimuth = |block: usize| read_u16(block * BLOCK_SIZE + 2) as f64 / 100.0;
+        let set_azimuth = |set: usize| block_azimuth(set * blocks_per_group * groups_per_set);
+
+        // Gives the azimuth change over the firings of a set, which is
+        // used to interpolate the azimuths of lasers fired in sequence.
+        let set_rotation = |set: usize| {
+            let (from, to) = if set + 1 < num_sets {
+                (set, set + 1)
+            } else if set > 0 {
+                (set - 1, set)
+            } else {
+                return 0.0;
+            };
+            (set_azimuth(to) - set_azimuth(from)).rem_euclid(360.0)
+        };
+
+        let read_measurement = |block: usize, channel: usize, laser_id: usize, azimuth: f64| {
+            let offset = block * BLOCK_SIZE + 4 + channel * 3;
+            let distance = read_u16(offset) as f64 * self.distance_resolution;
+            let intensity = payload[offset + 2];
+
+            let elevation = elevations[laser_id].to_radians();
+            let azimuth = azimuth.to_radians();
+            let xyz = [
+                distance * elevation.cos() * azimuth.sin(),
+                distance * elevation.cos() * azimuth.cos(),
+                distance * elevation.sin(),
+            ];
+
+            VelodyneMeasurement {
+                distance,
+                intensity,
+                xyz,
+            }
+        };
+
+        (0..num_firings)
+            .map(|firing| {
+                let set = firing / self.firings_per_block;
+                let sequence = firing % self.firings_per_block;
+                let first_block = set * blocks_per_group * groups_per_set;
+                let firing_toh = packet_toh + self.firing_period * firing as u32;
+
+                let rate = set_rotation(set) / self.firings_per_block as f64;
+                let azimuth = (set_azimuth(set) + rate * sequence as f64) % 360.0;
+
+                let points: Vec<_> = (0..self.num_lasers)
+                    .map(|laser_id| {
+                        let channel = sequence * self.num_lasers + laser_id;
+                        let block = first_block + channel / CHANNELS_PER_BLOCK;
+                        let channel = channel % CHANNELS_PER_BLOCK;
+
+                        let (toh, laser_azimuth) = match self.laser_period {
+                            Some(period) => {
+                                let shot = laser_id / self.lasers_per_shot;
+                                let slot = match self.shots_per_recharge {
+                                    Some(shots) => shot + shot / shots,
+                                    None => shot,
+                                };
+                                let delay = period * slot as u32;
+                                let fraction =
+                                    delay.as_secs_f64() / self.firing_period.as_secs_f64();
+                                (firing_toh + delay, azimuth + rate * fraction)
+                            }
+                            None => (firing_toh, azimuth),
+                        };
+                        let laser_azimuth =
+                            (laser_azimuth + azimuth_offsets[laser_id]).rem_euclid(360.0);
+
+                        let first = read_measurement(block, channel, laser_id, laser_azimuth);
+                        let (strongest, last) = match mode {
+                            ReturnMode::Strongest => (Some(first), None),
+                            ReturnMode::Last => (None, Some(first)),
+                            ReturnMode::Dual => {
+                                let second = read_measurement(
+                                    block + blocks_per_group,
+                                    channel,
+                                    laser_id,
+                                    laser_azimuth,
+                                );
+                                (Some(second), Some(first))
+                            }
+                        };
+
+                        VelodynePoint {
+                            laser_id: laser_id as u32,
+                            toh,
+                            azimuth: Angle::from_degrees(laser_azimuth),
+                            elevation: Angle::from_degrees(elevations[laser_id]),
+                            strongest,
+                            last,
+                        }
+                    })
+                    .collect();
+
+                (Angle::from_degrees(azimuth), points)
+            })
+            .collect()
+    }
+}
+
+/// Assembles frames from the firings decoded from the UDP payloads,
+/// which are cut as told by a [FrameSplit].
+///
+/// The `decode` function gives the firings along with their azimuths
+/// in a payload, or `None` if the payload is to be skipped.
+pub struct FiringFrameIter<D> {
+    capture: CaptureReader,
+    height: usize,
+    decode: D,
+    firings: Vec<Vec<VelodynePoint>>,
+    splitter: FrameSplitter,
+    /// The span of the frame being assembled.
+    span: FrameSpan,
+    /// The offset and the number of interfaces of the last data
+    /// packet, which is where to resume reading a frame cut after it.
+    last_packet: Option<(u64, usize)>,
+    /// Frames are skipped until the one starting at the top-of-hour
+    /// timestamp after seeking.
+    seek_toh: Option<Duration>,
+    ready: VecDeque<VelodyneFrame>,
+    finished: bool,
+}
+
+impl<D> FiringFrameIter<D>
+where
+    D: FnMut(&[u8]) -> Option<Vec<(Angle, Vec<VelodynePoint>)>>,
+{
+    pub fn new<P>(
+        path: P,
+        filter: &PacketFilter,
+        height: usize,
+        split: &FrameSplit,
+        decode: D,
+    ) -> Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        let capture = CaptureReader::open(path, filter)?;
+
+        Ok(Self {
+            capture,
+            height,
+            decode,
+            firings: vec![],
+            splitter: FrameSplitter::new(*split),
+            span: FrameSpan::default(),
+            last_packet: None,
+            seek_toh: None,
+            ready: VecDeque::new(),
+            finished: false,
+        })
+    }
+
+    /// Moves to a frame recorded in the index. The frames read before
+    /// it in the lead-in packet are skipped.
+    pub fn seek(&mut self, entry: &FrameIndexEntry) -> Result<()> {
+        let FrameSpan {
+            start_offset,
+            num_interfaces,
+            ..
+        } = entry.span;
+        self.capture.seek(start_offset, num_interfaces)?;
+        self.seek_toh = Some(entry.toh);
+        Ok(())
+    }
+
+    fn take_frame(&mut self) -> Option<VelodyneFrame> {
+        if self.firings.is_empty() {
+            return None;
+        }
+
+        let firings = std::mem::take(&mut self.firings);
+        Some(VelodyneFrame {
+            width: firings.len(),
+            height: self.height,
+            points: firings.into_iter().flatten().collect(),
+            span: self.span,
+        })
+    }
+
+    /// Ends the current frame at a cut, and queues it unless it is
+    /// dropped as partial.
+    fn cut_frame(&mut self) {
+        let keep = self.splitter.keep_cut_frame();
+        match self.take_frame() {
+            Some(frame) if keep => self.ready.push_back(frame),
+            _ => {}
+        }
+    }
+}
+
+impl<D> Iterator for FiringFrameIter<D>
+where
+    D: FnMut(&[u8]) -> Option<Vec<(Angle, Vec<VelodynePoint>)>>,
+{
+    type Item = Result<VelodyneFrame>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(frame) = self.ready.pop_front() {
+                if let Some(toh) = self.seek_toh {
+                    if frame.first_toh() != Some(toh) {
+                        continue;
+                    }
+                    self.seek_toh = None;
+                }
+                return Some(Ok(frame));
+            }
+            if self.finished {
+                return None;
+            }
+
+            let packet = match self.capture.next_packet() {
+                Ok(Some(packet)) => packet,
+                Ok(None) => {
+                    self.finished = true;
+                    if self.splitter.keep_last_frame() {
+                        if let Some(frame) = self.take_frame() {
+                            self.ready.push_back(frame);
+                        }
+                    }
+                    continue;
+                }
+                Err(err) => {
+                    self.finished = true;
+                    return Some(Err(err));
+                }
+            };
+            let Some(udp) = packet.udp() else {
+                continue;
+            };
+            let Some(firings) = (self.decode)(udp.payload) else {
+                continue;
+            };
+            let this_packet = (packet.offset, self.capture.interfaces().len());
+            let end_offset = self.capture.offset();
+
+            if self.splitter.feed_packet() {
+                self.cut_frame();
+            }
+
+            for (azimuth, points) in firings {
+                let toh = points.first().map(|point| point.toh);
+                let cut = self.splitter.feed_firing(azimuth.as_degrees(), toh);
+                if cut {
+                    self.cut_frame();
+                }
+
+                if self.firings.is_empty() {
+                    // A frame cut within the packet stream is resumed
+                    // from the packet before, so that the splitter
+                    // sees the cut again.
+                    let (start_offset, num_interfaces) = match self.last_packet {
+                        Some(last_packet) if cut => last_packet,
+                        _ => this_packet,
+                    };
+                    self.span.start_offset = start_offset;
+                    self.span.num_interfaces = num_interfaces;
+                }
+                self.span.end_offset = end_offset;
+                self.firings.push(points);
+            }
+
+            self.last_packet = Some(this_packet);
+        }
+    }
+}